@@ -0,0 +1,646 @@
+use crate::grid::{Boundary, Grid, Lattice};
+use crate::movement::{Movement, MovementParams};
+use crate::neighborhood::{Kernel, Neighborhood};
+use crate::rules::{create_rule, CellRule};
+use crate::traits::Domain;
+use crate::update::UpdateMode;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+
+const MAGIC: &[u8; 4] = b"TRAC";
+/// Number of trait values converted at once when streaming the grid
+const IO_BLOCK: usize = 1 << 16;
+/// Most elements allocated ahead for a length read from the file, longer vectors grow as the
+/// reads succeed, so that a corrupted length ends in an error rather than an allocation failure
+const MAX_PREALLOC: usize = 1 << 16;
+/// The same for the trait values, grids up to this many values are read without reallocating
+const MAX_GRID_PREALLOC: usize = 1 << 26;
+/// Bump when the layout changes, older files are rejected instead of misread
+pub const CHECKPOINT_VERSION: u32 = 14;
+
+
+/// Full simulation state, enough to resume a run bit for bit.
+///
+/// Layout (little-endian): magic, version, timestep, seed, randomize count, density, initialisation ranges,
+/// active traits, update mode and probability, trait names, rule names, rulestrings, parameters, inputs and states, trait domains and own neighborhoods, movement name and params, both neighborhoods (mask and kernel), then the grid
+/// (dimensions, `num_traits`, boundaries, lattice, `data`, bit-packed `is_empty`).
+/// The randomness is counter-based, so (seed, timestep, randomize count) is the whole RNG state.
+/// The grid is borrowed when saving, so large grids are not duplicated in memory.
+pub struct Checkpoint<'a> {
+    pub timestep: u64,
+    pub seed: u64,
+    /// `Simulation::randomize` calls since the grid was initialised
+    pub randomizations: u64,
+    pub grid_density: f32,
+    pub initialisation_ranges: Vec<(f32, f32)>,
+    pub active_traits: Vec<usize>,
+    pub update_mode: UpdateMode,
+    /// Names the expression rules are compiled against
+    pub trait_names: Vec<String>,
+    pub rules: Vec<Box<dyn CellRule>>,
+    /// Domain of every trait, same length as `rules`
+    pub domains: Vec<Domain>,
+    /// Own neighborhood of every trait (None for the global one), same length as `rules`
+    pub neighborhoods: Vec<Option<Neighborhood>>,
+    pub movement: Movement,
+    pub movement_params: MovementParams,
+    pub neighborhood_traits: Neighborhood,
+    pub neighborhood_mvt: Neighborhood,
+    pub grid: Cow<'a, Grid>,
+}
+
+impl Checkpoint<'_> {
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Rules are found by name in the catalog, those of other crates must be registered
+    /// with `register_rule` before loading
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Checkpoint<'static>> {
+        let mut reader = BufReader::new(File::open(path)?);
+        Self::read_from(&mut reader)
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        write_u32(w, CHECKPOINT_VERSION)?;
+
+        write_u64(w, self.timestep)?;
+        write_u64(w, self.seed)?;
+        write_u64(w, self.randomizations)?;
+        write_f32(w, self.grid_density)?;
+
+        write_u64(w, self.initialisation_ranges.len() as u64)?;
+        for &(min, max) in &self.initialisation_ranges {
+            write_f32(w, min)?;
+            write_f32(w, max)?;
+        }
+
+        write_u64(w, self.active_traits.len() as u64)?;
+        for &trait_idx in &self.active_traits {
+            write_u64(w, trait_idx as u64)?;
+        }
+
+        write_str(w, self.update_mode.name())?;
+        write_f32(w, self.update_mode.probability().unwrap_or_default())?;
+
+        write_u64(w, self.trait_names.len() as u64)?;
+        for name in &self.trait_names {
+            write_str(w, name)?;
+        }
+
+        // Rules and movement are stored by name so reordering the enums keeps old files valid
+        write_u64(w, self.rules.len() as u64)?;
+        for rule in &self.rules {
+            write_str(w, rule.name())?;
+            write_str(w, &rule.rulestring().unwrap_or_default())?;
+            let params = rule.params();
+            write_u64(w, params.len() as u64)?;
+            for (name, value) in params {
+                write_str(w, name)?;
+                write_f32(w, value)?;
+            }
+            let inputs = rule.input_names();
+            write_u64(w, inputs.len() as u64)?;
+            for &role in inputs {
+                write_str(w, role)?;
+                write_u64(w, rule.get_input(role).unwrap_or_default() as u64)?;
+            }
+            let state = rule.save_state();
+            write_u64(w, state.len() as u64)?;
+            w.write_all(&state)?;
+        }
+        for domain in &self.domains {
+            write_str(w, domain.name())?;
+            let (min, max) = domain.bounds().unwrap_or((0.0, 0.0));
+            write_f32(w, min)?;
+            write_f32(w, max)?;
+        }
+        for neighborhood in &self.neighborhoods {
+            write_u32(w, neighborhood.is_some() as u32)?;
+            if let Some(neighborhood) = neighborhood {
+                write_neighborhood(w, neighborhood)?;
+            }
+        }
+        write_str(w, self.movement.name())?;
+        write_u64(w, self.movement_params.inputs.len() as u64)?;
+        for &trait_idx in &self.movement_params.inputs {
+            write_u64(w, trait_idx as u64)?;
+        }
+        write_u32(w, self.movement_params.descend as u32)?;
+        write_f32(w, self.movement_params.threshold)?;
+
+        write_neighborhood(w, &self.neighborhood_traits)?;
+        write_neighborhood(w, &self.neighborhood_mvt)?;
+
+        write_grid(w, &self.grid)
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Checkpoint<'static>> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a trait_ac checkpoint"));
+        }
+        let version = read_u32(r)?;
+        if version != CHECKPOINT_VERSION {
+            return Err(invalid(format!(
+                "unsupported checkpoint version {} (expected {})",
+                version, CHECKPOINT_VERSION
+            )));
+        }
+
+        let timestep = read_u64(r)?;
+        let seed = read_u64(r)?;
+        let randomizations = read_u64(r)?;
+        let grid_density = read_f32(r)?;
+
+        let num_ranges = read_len(r)?;
+        let mut initialisation_ranges = Vec::with_capacity(num_ranges.min(MAX_PREALLOC));
+        for _ in 0..num_ranges {
+            initialisation_ranges.push((read_f32(r)?, read_f32(r)?));
+        }
+
+        let num_active = read_len(r)?;
+        let mut active_traits = Vec::with_capacity(num_active.min(MAX_PREALLOC));
+        for _ in 0..num_active {
+            active_traits.push(read_len(r)?);
+        }
+
+        let name = read_str(r)?;
+        let update_mode = UpdateMode::from_name(&name, read_f32(r)?)
+            .ok_or_else(|| invalid(format!("Unknown update mode: {}", name)))?;
+
+        let num_names = read_len(r)?;
+        let mut trait_names = Vec::with_capacity(num_names.min(MAX_PREALLOC));
+        for _ in 0..num_names {
+            trait_names.push(read_str(r)?);
+        }
+
+        let num_rules = read_len(r)?;
+        let mut rules = Vec::with_capacity(num_rules.min(MAX_PREALLOC));
+        for _ in 0..num_rules {
+            let name = read_str(r)?;
+            let mut rule = create_rule(&name).ok_or_else(|| invalid(format!("Unknown rule: {}", name)))?;
+            let rulestring = read_str(r)?;
+            if !rulestring.is_empty() {
+                rule.set_rulestring(&rulestring).map_err(invalid)?;
+            }
+            for _ in 0..read_len(r)? {
+                let param = read_str(r)?;
+                if !rule.set_param(&param, read_f32(r)?) {
+                    return Err(invalid(format!("Unknown parameter {} of rule {}", param, name)));
+                }
+            }
+            for _ in 0..read_len(r)? {
+                let role = read_str(r)?;
+                let trait_name = trait_names.get(read_len(r)?).ok_or_else(|| invalid("input trait index beyond the trait names"))?;
+                if !rule.set_input(&role, trait_name) {
+                    return Err(invalid(format!("Unknown input {} of rule {}", role, name)));
+                }
+            }
+            let state_len = read_len(r)?;
+            rule.load_state(&read_bytes(r, state_len)?).map_err(invalid)?;
+            rules.push(rule);
+        }
+        let mut domains = Vec::with_capacity(num_rules.min(MAX_PREALLOC));
+        for _ in 0..num_rules {
+            let name = read_str(r)?;
+            let (min, max) = (read_f32(r)?, read_f32(r)?);
+            domains.push(Domain::from_name(&name, min, max).ok_or_else(|| invalid(format!("Unknown domain: {}", name)))?);
+        }
+        let mut neighborhoods = Vec::with_capacity(num_rules.min(MAX_PREALLOC));
+        for _ in 0..num_rules {
+            neighborhoods.push(if read_u32(r)? != 0 { Some(read_neighborhood(r)?) } else { None });
+        }
+        let name = read_str(r)?;
+        let movement = Movement::from_name(&name).ok_or_else(|| invalid(format!("Unknown movement: {}", name)))?;
+        let num_inputs = read_len(r)?;
+        let mut inputs = Vec::with_capacity(num_inputs.min(MAX_PREALLOC));
+        for _ in 0..num_inputs {
+            inputs.push(read_len(r)?);
+        }
+        let movement_params = MovementParams {
+            inputs,
+            descend: read_u32(r)? != 0,
+            threshold: read_f32(r)?,
+        };
+
+        let neighborhood_traits = read_neighborhood(r)?;
+        let neighborhood_mvt = read_neighborhood(r)?;
+
+        let grid = read_grid(r)?;
+
+        if active_traits.iter().any(|&t| t >= grid.num_traits) {
+            return Err(invalid("active trait index beyond num_traits"));
+        }
+        if rules.len() < grid.num_traits {
+            return Err(invalid("fewer rules than traits"));
+        }
+        if trait_names.len() < grid.num_traits {
+            return Err(invalid("fewer trait names than traits"));
+        }
+        // Bound to the names of the grid traits, the rules never read a trait beyond num_traits
+        for rule in &mut rules {
+            rule.bind_traits(&trait_names[..grid.num_traits]).map_err(invalid)?;
+        }
+        if movement_params.inputs.len() != movement.inputs().len()
+            || movement_params.inputs.iter().any(|&t| t >= grid.num_traits) {
+            return Err(invalid("movement inputs do not match the movement and traits"));
+        }
+
+        Ok(Checkpoint {
+            timestep,
+            seed,
+            randomizations,
+            grid_density,
+            initialisation_ranges,
+            active_traits,
+            update_mode,
+            trait_names,
+            rules,
+            domains,
+            neighborhoods,
+            movement,
+            movement_params,
+            neighborhood_traits,
+            neighborhood_mvt,
+            grid: Cow::Owned(grid),
+        })
+    }
+}
+
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn write_u32<W: Write>(w: &mut W, v: u32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn write_u64<W: Write>(w: &mut W, v: u64) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn write_f32<W: Write>(w: &mut W, v: f32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn write_str<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+    write_u64(w, s.len() as u64)?;
+    w.write_all(s.as_bytes())
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_len<R: Read>(r: &mut R) -> io::Result<usize> {
+    usize::try_from(read_u64(r)?).map_err(|_| invalid("length does not fit in memory"))
+}
+
+fn read_f32<R: Read>(r: &mut R) -> io::Result<f32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(f32::from_le_bytes(buf))
+}
+
+fn read_str<R: Read>(r: &mut R) -> io::Result<String> {
+    let len = read_len(r)?;
+    let buf = read_bytes(r, len)?;
+    String::from_utf8(buf).map_err(|_| invalid("invalid utf-8 string"))
+}
+
+/// `len` bytes, the buffer growing with the data actually read
+fn read_bytes<R: Read>(r: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(len.min(MAX_PREALLOC));
+    r.by_ref().take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf)
+}
+
+fn write_neighborhood<W: Write>(w: &mut W, neighborhood: &Neighborhood) -> io::Result<()> {
+    write_u64(w, neighborhood.width as u64)?;
+    write_u64(w, neighborhood.height as u64)?;
+    write_u64(w, neighborhood.center_row as u64)?;
+    write_u64(w, neighborhood.center_col as u64)?;
+    for row in &neighborhood.mask {
+        w.write_all(row)?;
+    }
+    let kernel = neighborhood.kernel();
+    write_str(w, kernel.name())?;
+    let params = kernel.params();
+    write_u64(w, params.len() as u64)?;
+    for value in params {
+        write_f32(w, value)?;
+    }
+    Ok(())
+}
+
+fn read_neighborhood<R: Read>(r: &mut R) -> io::Result<Neighborhood> {
+    let width = read_len(r)?;
+    let height = read_len(r)?;
+    let center_row = read_len(r)?;
+    let center_col = read_len(r)?;
+    if center_row >= height || center_col >= width {
+        return Err(invalid("neighborhood center outside of its mask"));
+    }
+    // Every row holds at least one byte (the center lies in the mask), a truncated file fails early
+    let mut mask = Vec::with_capacity(height.min(MAX_PREALLOC));
+    for _ in 0..height {
+        mask.push(read_bytes(r, width)?);
+    }
+    let name = read_str(r)?;
+    let num_params = read_len(r)?;
+    let mut params = Vec::with_capacity(num_params.min(MAX_PREALLOC));
+    for _ in 0..num_params {
+        params.push(read_f32(r)?);
+    }
+    let kernel = Kernel::from_params(&name, &params, width)
+        .ok_or_else(|| invalid(format!("Invalid {} kernel", name)))?;
+    Neighborhood::new(width, height, center_row, center_col, mask)
+        .with_kernel(kernel)
+        .map_err(invalid)
+}
+
+fn write_grid<W: Write>(w: &mut W, grid: &Grid) -> io::Result<()> {
+    write_u64(w, grid.width as u64)?;
+    write_u64(w, grid.height as u64)?;
+    write_u64(w, grid.num_traits as u64)?;
+    write_str(w, grid.row_boundary.name())?;
+    write_str(w, grid.col_boundary.name())?;
+    write_str(w, grid.lattice.name())?;
+
+    // Written by blocks to avoid a second full copy of the grid in memory
+    let mut bytes = Vec::with_capacity(IO_BLOCK * 4);
+    for block in grid.data.chunks(IO_BLOCK) {
+        bytes.clear();
+        for &value in block {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        w.write_all(&bytes)?;
+    }
+
+    // 8 cells per byte, least significant bit first
+    let packed: Vec<u8> = grid.is_empty
+        .chunks(8)
+        .map(|cells| cells.iter().enumerate().fold(0u8, |byte, (bit, &empty)| byte | ((empty as u8) << bit)))
+        .collect();
+    w.write_all(&packed)
+}
+
+fn read_grid<R: Read>(r: &mut R) -> io::Result<Grid> {
+    let width = read_len(r)?;
+    let height = read_len(r)?;
+    let num_traits = read_len(r)?;
+    let row_boundary = read_boundary(r)?;
+    let col_boundary = read_boundary(r)?;
+    let lattice = read_str(r)?;
+    let lattice = Lattice::from_name(&lattice).ok_or_else(|| invalid(format!("Unknown lattice: {}", lattice)))?;
+    let num_cells = Grid::check_dimensions(width, height, num_traits).map_err(invalid)?;
+    let num_values = num_cells * num_traits;
+
+    let mut data = Vec::with_capacity(num_values.min(MAX_GRID_PREALLOC));
+    let mut bytes = vec![0u8; IO_BLOCK * 4];
+    while data.len() < num_values {
+        let count = (num_values - data.len()).min(IO_BLOCK);
+        r.read_exact(&mut bytes[..count * 4])?;
+        data.extend(
+            bytes[..count * 4]
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        );
+    }
+
+    let packed = read_bytes(r, num_cells.div_ceil(8))?;
+    let is_empty = (0..num_cells)
+        .map(|idx| packed[idx / 8] >> (idx % 8) & 1 == 1)
+        .collect();
+
+    Ok(Grid {
+        width,
+        height,
+        num_cells,
+        num_traits,
+        data,
+        is_empty,
+        row_boundary,
+        col_boundary,
+        lattice,
+    })
+}
+
+fn read_boundary<R: Read>(r: &mut R) -> io::Result<Boundary> {
+    let name = read_str(r)?;
+    Boundary::from_name(&name).ok_or_else(|| invalid(format!("Unknown boundary: {}", name)))
+}
+
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::RuleRng;
+    use crate::rules::{register_rule, ChargeParams, DiffusionParams, ExpressionParams, LifeLikeParams, Rule};
+
+    fn build_checkpoint() -> Checkpoint<'static> {
+        let mut grid = Grid::new(7, 5, 2);
+        grid.is_empty[3] = true;
+        grid.is_empty[34] = true;
+        grid.set_boundaries(Boundary::Reflective, Boundary::Absorbing);
+        grid.lattice = Lattice::Hexagonal;
+        let mask = vec![vec![0, 1, 0], vec![1, 1, 1], vec![0, 1, 0]];
+        let trait_names = vec!["Alive".to_string(), "Heat".to_string()];
+        let mut expression: Box<dyn CellRule> = Rule::Expression(ExpressionParams::parse("mean(nbr.Alive) * Heat").unwrap()).into();
+        expression.bind_traits(&trait_names).unwrap();
+        let mut charge: Box<dyn CellRule> = Rule::Charge(ChargeParams::DEFAULT).into();
+        charge.set_input("energy", "Heat");
+        charge.bind_traits(&trait_names).unwrap();
+        Checkpoint {
+            timestep: 12,
+            seed: 7,
+            randomizations: 3,
+            grid_density: 0.5,
+            initialisation_ranges: vec![(0.0, 1.0), (0.2, 0.4)],
+            active_traits: vec![1],
+            update_mode: UpdateMode::RandomIndependent(0.25),
+            trait_names,
+            rules: vec![
+                Rule::LifeLike(LifeLikeParams::parse("B36/S23").unwrap()).into(),
+                Rule::Diffusion(DiffusionParams { decay: 0.5, ..DiffusionParams::DEFAULT }).into(),
+                expression,
+                charge,
+            ],
+            domains: vec![Domain::Unbounded, Domain::Cyclic(-1.0, 1.0), Domain::default(), Domain::default()],
+            neighborhoods: vec![
+                None,
+                Some(Neighborhood::from_mask(vec![vec![1; 5]; 5]).with_kernel(Kernel::Gaussian { sigma: 1.5 }).unwrap()),
+                Some(Neighborhood::from_mask(vec![vec![1; 2]; 2]).with_kernel(Kernel::Custom { weights: vec![vec![0.5, 1.0], vec![2.0, 0.0]] }).unwrap()),
+                None,
+            ],
+            movement: Movement::Gradient,
+            movement_params: MovementParams { inputs: vec![1], descend: true, threshold: 0.25 },
+            neighborhood_traits: Neighborhood::new(3, 3, 1, 1, mask.clone()),
+            neighborhood_mvt: Neighborhood::new(3, 3, 1, 1, mask),
+            grid: Cow::Owned(grid),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let checkpoint = build_checkpoint();
+        let mut bytes = Vec::new();
+        checkpoint.write_to(&mut bytes).unwrap();
+        let restored = Checkpoint::read_from(&mut bytes.as_slice()).unwrap();
+
+        assert_eq!(restored.timestep, 12);
+        assert_eq!(restored.seed, 7);
+        assert_eq!(restored.randomizations, 3);
+        assert_eq!(restored.initialisation_ranges, checkpoint.initialisation_ranges);
+        assert_eq!(restored.active_traits, vec![1]);
+        assert_eq!(restored.update_mode, UpdateMode::RandomIndependent(0.25));
+        assert_eq!(restored.trait_names, checkpoint.trait_names);
+        let builtin = |rules: &[Box<dyn CellRule>]| -> Vec<Rule> {
+            rules.iter().map(|rule| rule.downcast_ref::<Rule>().unwrap().clone()).collect()
+        };
+        assert_eq!(builtin(&restored.rules), builtin(&checkpoint.rules));
+        assert_eq!(restored.rules[3].get_input("energy"), Some(1));
+        assert_eq!(restored.domains, checkpoint.domains);
+        assert_eq!(restored.neighborhoods, checkpoint.neighborhoods);
+        assert_eq!(restored.movement, Movement::Gradient);
+        assert_eq!(restored.movement_params, checkpoint.movement_params);
+        assert_eq!(restored.neighborhood_traits.mask, checkpoint.neighborhood_traits.mask);
+        assert_eq!(restored.grid.width, 7);
+        assert_eq!(restored.grid.height, 5);
+        assert_eq!(restored.grid.row_boundary, Boundary::Reflective);
+        assert_eq!(restored.grid.col_boundary, Boundary::Absorbing);
+        assert_eq!(restored.grid.lattice, Lattice::Hexagonal);
+        assert_eq!(restored.grid.data, checkpoint.grid.data);
+        assert_eq!(restored.grid.is_empty, checkpoint.grid.is_empty);
+    }
+
+    /// Registered rule whose state is not in its parameters, the name is used by this test only
+    /// since the catalog is shared by the tests of the process
+    #[derive(Clone, Debug, Default)]
+    struct Counter {
+        steps: u64,
+    }
+
+    impl CellRule for Counter {
+        fn name(&self) -> &'static str {
+            "checkpoint test counter"
+        }
+
+        fn apply(&self, _trait_index: usize, _cell_r: usize, _cell_c: usize, _neighborhood: &Neighborhood, _grid: &Grid, _rng: &mut RuleRng) -> f32 {
+            self.steps as f32
+        }
+
+        fn prepare(&mut self, _trait_index: usize, _grid: &Grid) {
+            self.steps += 1;
+        }
+
+        fn save_state(&self) -> Vec<u8> {
+            self.steps.to_le_bytes().to_vec()
+        }
+
+        fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+            self.steps = u64::from_le_bytes(state.try_into().map_err(|_| "bad counter state")?);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_rule_state() {
+        let mut checkpoint = build_checkpoint();
+        checkpoint.rules[1] = Box::new(Counter { steps: 41 });
+        let mut bytes = Vec::new();
+        checkpoint.write_to(&mut bytes).unwrap();
+        assert!(Checkpoint::read_from(&mut bytes.as_slice()).is_err(), "unregistered rule");
+
+        register_rule(Counter::default()).unwrap();
+        let restored = Checkpoint::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(restored.rules[1].downcast_ref::<Counter>().unwrap().steps, 41);
+
+        // Rules without state reject one
+        let mut rule = create_rule("diffusion").unwrap();
+        assert!(rule.load_state(&[]).is_ok());
+        assert!(rule.load_state(&[1]).is_err());
+    }
+
+    #[test]
+    fn test_rejects_traits_beyond_the_grid() {
+        let read = |checkpoint: &Checkpoint| {
+            let mut bytes = Vec::new();
+            checkpoint.write_to(&mut bytes).unwrap();
+            Checkpoint::read_from(&mut bytes.as_slice())
+        };
+        let mut checkpoint = build_checkpoint();
+        checkpoint.trait_names.truncate(1);
+        assert!(read(&checkpoint).is_err());
+
+        // Names of traits the grid does not have
+        let names: Vec<String> = ["Alive", "Heat", "Extra"].map(String::from).to_vec();
+        let mut checkpoint = build_checkpoint();
+        checkpoint.trait_names = names.clone();
+        assert!(read(&checkpoint).is_ok());
+        checkpoint.rules[2] = Rule::Expression(ExpressionParams::parse("Extra").unwrap()).into();
+        checkpoint.rules[2].bind_traits(&names).unwrap();
+        assert!(read(&checkpoint).is_err());
+        let mut checkpoint = build_checkpoint();
+        checkpoint.trait_names = names.clone();
+        checkpoint.rules[3].set_input("energy", "Extra");
+        checkpoint.rules[3].bind_traits(&names).unwrap();
+        assert!(read(&checkpoint).is_err());
+    }
+
+    #[test]
+    fn test_rejects_other_files() {
+        let mut bytes = Vec::new();
+        build_checkpoint().write_to(&mut bytes).unwrap();
+        bytes[0] = b'X';
+        assert!(Checkpoint::read_from(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_rejects_huge_lengths() {
+        // magic, version, timestep, seed, randomize count and density, then the lengths
+        let header = |lengths: &[u64]| {
+            let mut bytes = MAGIC.to_vec();
+            bytes.extend_from_slice(&CHECKPOINT_VERSION.to_le_bytes());
+            bytes.extend_from_slice(&[0; 28]);
+            for length in lengths {
+                bytes.extend_from_slice(&length.to_le_bytes());
+            }
+            bytes
+        };
+        // Initialisation ranges, then the name of the update mode
+        for lengths in [&[u64::MAX >> 4][..], &[0, 0, 1 << 60]] {
+            let error = Checkpoint::read_from(&mut header(lengths).as_slice()).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        }
+
+        let mut bytes = Vec::new();
+        build_checkpoint().write_to(&mut bytes).unwrap();
+        for len in [bytes.len() - 1, bytes.len() / 2] {
+            assert!(Checkpoint::read_from(&mut &bytes[..len]).is_err());
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use crate::grid::{Boundary, Grid, Lattice};
+use crate::movement::{Movement, MovementConfig};
+use crate::neighborhood::{Kernel, Neighborhood, Shape};
+use crate::rules::{CellRule, Rule, RuleSpec};
+use crate::traits::{self, TraitInfo};
+use crate::update::UpdateMode;
+use serde::Deserialize;
+
+
+// Custom deserializer for the rules, one entry per trait
+pub fn deserialize_rules<'de, D>(deserializer: D) -> Result<Vec<Box<dyn CellRule>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let specs: Vec<RuleSpec> = Vec::deserialize(deserializer)?;
+    specs
+        .iter()
+        .map(|spec| spec.to_rule().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+// Custom deserializer for Movement
+pub fn deserialize_movement<'de, D>(deserializer: D) -> Result<Movement, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    Movement::from_name(&name).ok_or_else(|| {
+        serde::de::Error::custom(format!(
+            "Unknown movement: '{}'. Valid movements are: {:?}",
+            name,
+            Movement::NAMES
+        ))
+    })
+}
+
+// Custom deserializer for Boundary
+pub fn deserialize_boundary<'de, D>(deserializer: D) -> Result<Boundary, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    Boundary::from_name(&name).ok_or_else(|| {
+        serde::de::Error::custom(format!(
+            "Unknown boundary: '{}'. Valid boundaries are: {:?}",
+            name,
+            Boundary::NAMES
+        ))
+    })
+}
+
+// Custom deserializer for Lattice
+pub fn deserialize_lattice<'de, D>(deserializer: D) -> Result<Lattice, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    Lattice::from_name(&name).ok_or_else(|| {
+        serde::de::Error::custom(format!(
+            "Unknown lattice: '{}'. Valid lattices are: {:?}",
+            name,
+            Lattice::NAMES
+        ))
+    })
+}
+
+// Custom deserializer for UpdateMode (the probability is its own key)
+pub fn deserialize_update_mode<'de, D>(deserializer: D) -> Result<UpdateMode, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    UpdateMode::from_name(&name, UpdateMode::DEFAULT_PROBABILITY).ok_or_else(|| {
+        serde::de::Error::custom(format!(
+            "Unknown update mode: '{}'. Valid update modes are: {:?}",
+            name,
+            UpdateMode::NAMES
+        ))
+    })
+}
+
+
+/// Config keys describing the simulation itself, shared by every frontend.
+/// Frontends flatten it into their own config next to their display or run settings:
+///
+/// ```ignore
+/// #[derive(Deserialize)]
+/// struct Config {
+///     #[serde(flatten)]
+///     engine: EngineConfig,
+///     timesteps: usize,
+/// }
+/// ```
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct EngineConfig {
+    // Grid settings
+    pub grid_width: usize,
+    pub grid_height: usize,
+    pub grid_density: f32,
+
+    // Boundary conditions (top/bottom edges, left/right edges)
+    #[serde(deserialize_with = "deserialize_boundary")]
+    pub row_boundary: Boundary,
+    #[serde(deserialize_with = "deserialize_boundary")]
+    pub col_boundary: Boundary,
+    // Square or hexagonal (axial coordinates) cells
+    #[serde(deserialize_with = "deserialize_lattice")]
+    pub lattice: Lattice,
+
+    // Order of the cell updates, and the update probability of "random independent"
+    #[serde(deserialize_with = "deserialize_update_mode")]
+    pub update_mode: UpdateMode,
+    pub update_probability: f32,
+
+    // Seed of every random draw (drawn from entropy when absent)
+    pub seed: Option<u64>,
+
+    // Trait metadata ([[traits]] tables, one per trait)
+    pub traits: Vec<TraitInfo>,
+
+    // Rules & movement
+    #[serde(deserialize_with = "deserialize_rules")]
+    pub rules: Vec<Box<dyn CellRule>>,
+    #[serde(deserialize_with = "deserialize_movement")]
+    pub movement: Movement,
+    // Traits read by the movement and its options ([movement_params] table)
+    pub movement_params: MovementConfig,
+
+    // Neighborhoods: presets or custom shapes (e.g. { kind = "von_neumann", radius = 2 }), the masks otherwise
+    pub neighborhood_traits: Option<Shape>,
+    pub neighborhood_mvt: Option<Shape>,
+    pub neighborhood_traits_mask: Vec<Vec<u8>>,
+    pub neighborhood_mvt_mask: Vec<Vec<u8>>,
+    // Weights of the rules neighborhood (table, e.g. { kind = "gaussian", sigma = 1.5 })
+    pub neighborhood_traits_kernel: Kernel,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            grid_width: 1000,
+            grid_height: 1000,
+            grid_density: 1.0,
+            row_boundary: Boundary::Periodic,
+            col_boundary: Boundary::Periodic,
+            lattice: Lattice::Square,
+            update_mode: UpdateMode::Synchronous,
+            update_probability: UpdateMode::DEFAULT_PROBABILITY,
+            seed: None,
+            traits: vec![TraitInfo::new("Alive")],
+            rules: vec![Rule::ConwayOptimized.into()],
+            movement: Movement::Static,
+            movement_params: MovementConfig::default(),
+            neighborhood_traits: None,
+            neighborhood_mvt: None,
+            neighborhood_traits_mask: vec![
+                vec![1, 1, 1],
+                vec![1, 1, 1],
+                vec![1, 1, 1],
+            ],
+            neighborhood_mvt_mask: vec![
+                vec![1, 1, 1],
+                vec![1, 1, 1],
+                vec![1, 1, 1],
+            ],
+            neighborhood_traits_kernel: Kernel::Uniform,
+        }
+    }
+}
+
+impl EngineConfig {
+    /// Completes a freshly deserialized config: applies the update probability, validates it
+    /// and binds the rules to the trait names
+    pub fn resolve(&mut self) -> Result<(), String> {
+        if let UpdateMode::RandomIndependent(probability) = &mut self.update_mode {
+            *probability = self.update_probability;
+        }
+        self.validate()?;
+        // Expression rules name the traits they read
+        let names = traits::names(&self.traits);
+        for rule in &mut self.rules {
+            rule.bind_traits(&names)?;
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        Grid::check_dimensions(self.grid_width, self.grid_height, self.traits.len())?;
+        if !(0.0..=1.0).contains(&self.grid_density) {
+            return Err("grid_density must be between 0.0 and 1.0");
+        }
+        self.update_mode.validate()?;
+        TraitInfo::validate(&self.traits)?;
+        // Moore masks are taken as hexagonal ones on a hexagonal lattice, other square masks are rejected
+        self.neighborhood_traits()?.check_lattice(self.lattice)?;
+        self.neighborhood_mvt()?.check_lattice(self.lattice)?;
+        for own in traits::neighborhoods(&self.traits)?.iter().flatten() {
+            own.check_lattice(self.lattice)?;
+        }
+        if self.rules.len() < self.traits.len() {
+            return Err("rules needs one entry per trait");
+        }
+        self.movement_params.resolve(self.movement, &self.traits)?;
+        Ok(())
+    }
+
+    /// Neighborhood of the rules, from `neighborhood_traits` or the mask, weighted by the kernel
+    pub fn neighborhood_traits(&self) -> Result<Neighborhood, &'static str> {
+        Self::neighborhood(self.neighborhood_traits.as_ref(), &self.neighborhood_traits_mask)?
+            .with_kernel(self.neighborhood_traits_kernel.clone())
+    }
+
+    /// Neighborhood of the movement, from `neighborhood_mvt` or the mask
+    pub fn neighborhood_mvt(&self) -> Result<Neighborhood, &'static str> {
+        Self::neighborhood(self.neighborhood_mvt.as_ref(), &self.neighborhood_mvt_mask)
+    }
+
+    fn neighborhood(shape: Option<&Shape>, mask: &[Vec<u8>]) -> Result<Neighborhood, &'static str> {
+        match shape {
+            Some(shape) => Neighborhood::from_shape(shape),
+            None => {
+                Neighborhood::check_mask(mask)?;
+                Ok(Neighborhood::from_mask(mask.to_vec()))
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Simulation;
+
+    fn parse(content: &str) -> Result<EngineConfig, String> {
+        let mut config: EngineConfig = toml::from_str(content).map_err(|e| e.to_string())?;
+        config.resolve()?;
+        Ok(config)
+    }
+
+    #[test]
+    fn test_engine_config() {
+        let config = parse(
+            r#"
+            grid_width = 12
+            grid_height = 8
+            lattice = "hexagonal"
+            update_mode = "random independent"
+            update_probability = 0.25
+            seed = 7
+            movement = "random"
+            neighborhood_traits = { kind = "moore", radius = 1 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.update_mode, UpdateMode::RandomIndependent(0.25));
+
+        let simulation = Simulation::from_config(&config).unwrap();
+        assert_eq!((simulation.grid().width, simulation.grid().height), (12, 8));
+        assert_eq!(simulation.num_traits(), 1);
+        assert_eq!(simulation.lattice(), Lattice::Hexagonal);
+        assert_eq!(simulation.update_mode(), UpdateMode::RandomIndependent(0.25));
+        assert_eq!(simulation.seed(), 7);
+        assert_eq!(simulation.movement_registry().get_movement(), Movement::Random);
+        // Moore neighborhoods follow the lattice
+        assert_eq!(simulation.neighborhood_traits(), &Neighborhood::hexagonal(1));
+
+        // The same config gives the same grid
+        let again = Simulation::from_config(&config).unwrap();
+        assert_eq!(again.grid().data, simulation.grid().data);
+    }
+
+    #[test]
+    fn test_engine_config_errors() {
+        let error = parse(r#"lattice = "triangular""#).err().unwrap();
+        assert!(error.contains("Valid lattices are"), "{}", error);
+        assert!(parse("grid_density = 1.5").is_err());
+        assert!(parse(r#"update_mode = "random independent""#).is_ok());
+        assert!(parse("update_probability = 1.5\nupdate_mode = \"random independent\"").is_err());
+        // Two traits need two rules
+        assert!(parse("[[traits]]\nname = \"A\"\n[[traits]]\nname = \"B\"").is_err());
+    }
+}
@@ -0,0 +1,345 @@
+use crate::grid::Grid;
+use rayon::prelude::*;
+use rustfft::num_complex::Complex;
+use rustfft::num_traits::Zero;
+use rustfft::{Fft, FftPlanner};
+use std::fmt;
+use std::sync::Arc;
+
+
+/// Kernels with more non-zero weights than this are convolved by FFT, the others by a direct stencil
+pub const DIRECT_MAX_TAPS: usize = 100;
+
+/// Square kernel of side 2 * radius + 1 applied to whole trait slices:
+/// output(r, c) = Σ weight(dr, dc) · value(r + dr, c + dc), the values read through the grid
+/// boundaries, empty cells and cells outside a fixed boundary counting as 0
+#[derive(Clone)]
+pub struct Convolution {
+    radius: usize,
+    /// (2 * radius + 1)², row-major, centered
+    weights: Vec<f32>,
+    /// (dr, dc, weight) of the non-zero weights
+    taps: Vec<(isize, isize, f32)>,
+    /// FFT plans and kernel spectrum of the last grid size convolved
+    spectrum: Option<Spectrum>,
+}
+
+#[derive(Clone)]
+struct Spectrum {
+    /// (height, width) of the grid
+    grid_size: (usize, usize),
+    /// FFT size, the grid padded by the radius on every side and rounded up to a fast length
+    rows: usize,
+    cols: usize,
+    row_forward: Arc<dyn Fft<f32>>,
+    row_inverse: Arc<dyn Fft<f32>>,
+    col_forward: Arc<dyn Fft<f32>>,
+    col_inverse: Arc<dyn Fft<f32>>,
+    /// Spectrum of the kernel, transposed (cols x rows) like the forward transforms
+    kernel: Vec<Complex<f32>>,
+    /// Padded slice (rows x cols) and its transpose, reused by every convolution of this size
+    buffer: Vec<Complex<f32>>,
+    transposed: Vec<Complex<f32>>,
+    /// Scratch of the FFTs, a slice per chunk of rows
+    scratch: Vec<Complex<f32>>,
+}
+
+impl fmt::Debug for Convolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Convolution")
+            .field("radius", &self.radius)
+            .field("taps", &self.taps.len())
+            .field("fft", &self.uses_fft())
+            .finish()
+    }
+}
+
+impl Convolution {
+    /// Kernel of (2 * radius + 1)² weights, row-major, centered on the cell
+    pub fn new(radius: usize, weights: Vec<f32>) -> Self {
+        let side = 2 * radius + 1;
+        assert_eq!(weights.len(), side * side, "a kernel of radius {} has {} weights", radius, side * side);
+        let taps = weights
+            .iter()
+            .enumerate()
+            .filter(|&(_, &weight)| weight != 0.0)
+            .map(|(i, &weight)| ((i / side) as isize - radius as isize, (i % side) as isize - radius as isize, weight))
+            .collect();
+        Self { radius, weights, taps, spectrum: None }
+    }
+
+    #[inline]
+    pub fn radius(&self) -> usize {
+        self.radius
+    }
+
+    #[inline]
+    pub fn weights(&self) -> &[f32] {
+        &self.weights
+    }
+
+    /// Number of non-zero weights
+    #[inline]
+    pub fn num_taps(&self) -> usize {
+        self.taps.len()
+    }
+
+    /// (dr, dc) of the non-zero weights
+    #[inline]
+    pub fn offsets(&self) -> impl Iterator<Item = (isize, isize)> + '_ {
+        self.taps.iter().map(|&(dr, dc, _)| (dr, dc))
+    }
+
+    /// Whole slices are convolved by FFT rather than by the direct stencil
+    #[inline]
+    pub fn uses_fft(&self) -> bool {
+        self.taps.len() > DIRECT_MAX_TAPS
+    }
+
+    #[inline(always)]
+    fn value(grid: &Grid, trait_index: usize, row: isize, col: isize) -> f32 {
+        match grid.get_position(row, col) {
+            Some((r, c)) if !grid.is_cell_empty(r, c) => grid.get_cell_trait(r, c, trait_index),
+            _ => 0.0,
+        }
+    }
+
+    /// Convolution of the trait at a single cell, by the direct stencil
+    #[inline]
+    pub fn at(&self, grid: &Grid, trait_index: usize, row: usize, col: usize) -> f32 {
+        let (row, col) = (row as isize, col as isize);
+        self.taps
+            .iter()
+            .map(|&(dr, dc, weight)| weight * Self::value(grid, trait_index, row + dr, col + dc))
+            .sum()
+    }
+
+    /// Convolution of the whole trait slice into `output` (resized to the grid), by FFT for large kernels
+    pub fn apply(&mut self, grid: &Grid, trait_index: usize, output: &mut Vec<f32>) {
+        output.resize(grid.num_cells, 0.0);
+        if self.uses_fft() {
+            self.apply_fft(grid, trait_index, output);
+        } else {
+            self.apply_direct(grid, trait_index, output);
+        }
+    }
+
+    /// Convolution of the whole trait slice by the direct stencil, parallel over rows
+    pub fn apply_direct(&self, grid: &Grid, trait_index: usize, output: &mut [f32]) {
+        output
+            .par_chunks_mut(grid.width)
+            .enumerate()
+            .for_each(|(row, out_row)| {
+                for (col, out) in out_row.iter_mut().enumerate() {
+                    *out = self.at(grid, trait_index, row, col);
+                }
+            });
+    }
+
+    /// Convolution of the whole trait slice by FFT: the padded slice and the kernel are multiplied
+    /// in the frequency domain, the padding keeping the circular convolution from wrapping
+    pub fn apply_fft(&mut self, grid: &Grid, trait_index: usize, output: &mut [f32]) {
+        let radius = self.radius;
+        let Spectrum {
+            rows, cols, row_forward, row_inverse, col_forward, col_inverse, kernel, buffer, transposed, scratch, ..
+        } = self.spectrum(grid.height, grid.width);
+        let (rows, cols) = (*rows, *cols);
+
+        // Every value is rewritten, the padding included, as the buffer holds the last convolution
+        let padded_height = grid.height + 2 * radius;
+        let padded_width = grid.width + 2 * radius;
+        buffer
+            .par_chunks_mut(cols)
+            .enumerate()
+            .for_each(|(i, buffer_row)| {
+                buffer_row.fill(Complex::zero());
+                if i < padded_height {
+                    let row = i as isize - radius as isize;
+                    for (j, value) in buffer_row[..padded_width].iter_mut().enumerate() {
+                        value.re = Self::value(grid, trait_index, row, j as isize - radius as isize);
+                    }
+                }
+            });
+
+        process_rows(buffer, row_forward, scratch);
+        transpose(buffer, rows, cols, transposed);
+        process_rows(transposed, col_forward, scratch);
+        transposed
+            .par_iter_mut()
+            .zip(kernel.par_iter())
+            .for_each(|(value, kernel)| *value *= kernel);
+        process_rows(transposed, col_inverse, scratch);
+        transpose(transposed, cols, rows, buffer);
+        process_rows(buffer, row_inverse, scratch);
+
+        let scale = 1.0 / (rows * cols) as f32;
+        let buffer = &*buffer;
+        output
+            .par_chunks_mut(grid.width)
+            .enumerate()
+            .for_each(|(row, out_row)| {
+                let offset = (row + radius) * cols + radius;
+                for (out, value) in out_row.iter_mut().zip(&buffer[offset..offset + grid.width]) {
+                    *out = value.re * scale;
+                }
+            });
+    }
+
+    /// Plans and kernel spectrum for the grid size, computed on the first use of a size
+    fn spectrum(&mut self, height: usize, width: usize) -> &mut Spectrum {
+        if self.spectrum.as_ref().is_none_or(|spectrum| spectrum.grid_size != (height, width)) {
+            let rows = fft_len(height + 2 * self.radius);
+            let cols = fft_len(width + 2 * self.radius);
+            let mut planner = FftPlanner::new();
+            let row_forward = planner.plan_fft_forward(cols);
+            let col_forward = planner.plan_fft_forward(rows);
+
+            // Correlation as a circular convolution: the weight of (dr, dc) goes to (-dr, -dc)
+            let mut kernel = vec![Complex::zero(); rows * cols];
+            for &(dr, dc, weight) in &self.taps {
+                let i = (-dr).rem_euclid(rows as isize) as usize;
+                let j = (-dc).rem_euclid(cols as isize) as usize;
+                kernel[i * cols + j].re += weight;
+            }
+            let mut scratch = Vec::new();
+            let mut transposed = vec![Complex::zero(); rows * cols];
+            process_rows(&mut kernel, &row_forward, &mut scratch);
+            transpose(&kernel, rows, cols, &mut transposed);
+            process_rows(&mut transposed, &col_forward, &mut scratch);
+
+            self.spectrum = Some(Spectrum {
+                grid_size: (height, width),
+                rows,
+                cols,
+                row_forward,
+                row_inverse: planner.plan_fft_inverse(cols),
+                col_forward,
+                col_inverse: planner.plan_fft_inverse(rows),
+                kernel: transposed,
+                buffer: kernel,
+                transposed: vec![Complex::zero(); rows * cols],
+                scratch,
+            });
+        }
+        self.spectrum.as_mut().unwrap()
+    }
+}
+
+/// Transforms every row of `buffer` (rows of the FFT length), a chunk of rows per thread,
+/// each with its own slice of `scratch` (grown on first use)
+fn process_rows(buffer: &mut [Complex<f32>], fft: &Arc<dyn Fft<f32>>, scratch: &mut Vec<Complex<f32>>) {
+    let len = fft.len();
+    let rows_per_chunk = (buffer.len() / len).div_ceil(rayon::current_num_threads()).max(1);
+    let chunk_len = len * rows_per_chunk;
+    let scratch_len = fft.get_inplace_scratch_len().max(1);
+    let num_chunks = buffer.len().div_ceil(chunk_len);
+    if scratch.len() < num_chunks * scratch_len {
+        scratch.resize(num_chunks * scratch_len, Complex::zero());
+    }
+    buffer
+        .par_chunks_mut(chunk_len)
+        .zip(scratch.par_chunks_mut(scratch_len))
+        .for_each(|(chunk, scratch)| fft.process_with_scratch(chunk, &mut scratch[..fft.get_inplace_scratch_len()]));
+}
+
+/// `input` (rows x cols, row-major) transposed to cols x rows into `output`
+fn transpose(input: &[Complex<f32>], rows: usize, cols: usize, output: &mut [Complex<f32>]) {
+    output
+        .par_chunks_mut(rows)
+        .enumerate()
+        .for_each(|(col, out_row)| {
+            for (row, out) in out_row.iter_mut().enumerate() {
+                *out = input[row * cols + col];
+            }
+        });
+}
+
+/// Smallest length >= n with no prime factor above 5, which the FFT handles fastest
+fn fft_len(n: usize) -> usize {
+    (n.max(1)..)
+        .find(|&len| {
+            let mut rest = len;
+            for factor in [2, 3, 5] {
+                while rest % factor == 0 {
+                    rest /= factor;
+                }
+            }
+            rest == 1
+        })
+        .unwrap()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::Boundary;
+
+    fn grid(width: usize, height: usize) -> Grid {
+        let mut grid = Grid::new(width, height, 1);
+        for (i, value) in grid.data.iter_mut().enumerate() {
+            *value = ((i * 37) % 11) as f32 / 10.0;
+        }
+        grid.is_empty[3] = true;
+        grid
+    }
+
+    fn gradient_kernel(radius: usize) -> Convolution {
+        let side = 2 * radius + 1;
+        Convolution::new(radius, (0..side * side).map(|i| 1.0 + (i % 7) as f32).collect())
+    }
+
+    #[test]
+    fn test_fft_len() {
+        assert_eq!(fft_len(1), 1);
+        assert_eq!(fft_len(7), 8);
+        assert_eq!(fft_len(31), 32);
+        assert_eq!(fft_len(61), 64);
+        assert_eq!(fft_len(121), 125);
+    }
+
+    #[test]
+    fn test_fft_matches_direct() {
+        let mut convolution = gradient_kernel(6);
+        assert!(convolution.uses_fft());
+        let mut buffers = None;
+        for boundary in [Boundary::Periodic, Boundary::Fixed, Boundary::Reflective] {
+            // Wider kernel than the grid is high, the values wrap several times when periodic
+            let mut grid = grid(23, 9);
+            grid.set_boundaries(boundary, Boundary::Periodic);
+            let mut direct = vec![0.0; grid.num_cells];
+            let mut fft = Vec::new();
+            convolution.apply_direct(&grid, 0, &mut direct);
+            convolution.apply(&grid, 0, &mut fft);
+            for (direct, fft) in direct.iter().zip(&fft) {
+                assert!((direct - fft).abs() < 1e-3 * direct.abs().max(1.0), "{} != {}", direct, fft);
+            }
+            assert_eq!(convolution.at(&grid, 0, 4, 5), direct[4 * 23 + 5]);
+
+            // The plans and buffers of the grid size are kept from one convolution to the next
+            let spectrum = convolution.spectrum.as_ref().unwrap();
+            let pointers = (spectrum.buffer.as_ptr(), spectrum.transposed.as_ptr(), spectrum.scratch.as_ptr());
+            assert_eq!(*buffers.get_or_insert(pointers), pointers);
+        }
+    }
+
+    #[test]
+    fn test_direct_stencil() {
+        let mut weights = vec![0.0; 9];
+        weights[1] = 1.0; // the cell above
+        weights[5] = 2.0; // the cell on the right
+        let mut convolution = Convolution::new(1, weights);
+        assert!(!convolution.uses_fft());
+        assert_eq!(convolution.num_taps(), 2);
+
+        let mut grid = grid(4, 3);
+        grid.set_boundaries(Boundary::Fixed, Boundary::Fixed);
+        let mut output = Vec::new();
+        convolution.apply(&grid, 0, &mut output);
+        let values = grid.get_trait_slice(0);
+        assert_eq!(output[0], 2.0 * values[1]);
+        assert_eq!(output[2], 0.0); // (0, 3) is empty and (-1, 2) outside
+        assert_eq!(output[11], values[7]);
+        assert_eq!(output[5], values[1] + 2.0 * values[6]);
+    }
+}
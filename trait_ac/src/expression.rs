@@ -0,0 +1,691 @@
+use crate::grid::Grid;
+use crate::neighborhood::Neighborhood;
+use crate::random::RuleRng;
+use rand::Rng;
+
+
+/// Deepest value stack an expression may need, deeper expressions are rejected when compiling
+const MAX_STACK: usize = 32;
+
+
+/// Instruction of a compiled expression, evaluated on a value stack
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Op {
+    Const(f32),
+    /// Value of the rule's trait at the cell (`self`)
+    Current,
+    /// Value of another trait at the cell
+    Trait(usize),
+    /// Value of the rule's trait at the neighbor (`nbr.self`), inside aggregates only
+    NeighborCurrent,
+    /// Value of another trait at the neighbor (`nbr.<Trait>`), inside aggregates only
+    NeighborTrait(usize),
+    Unary(Unary),
+    Binary(Binary),
+    /// clamp(x, min, max)
+    Clamp,
+    /// if(condition, then, else)
+    Select,
+    /// Uniform draw in [0, 1), from the random stream of the rule
+    Random,
+    /// The next `usize` instructions are evaluated for every non-empty neighbor and combined
+    Aggregate(Aggregate, usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Unary {
+    Neg,
+    Not,
+    Abs,
+    Sqrt,
+    Exp,
+    Ln,
+    Sin,
+    Cos,
+    Tan,
+    Floor,
+    Ceil,
+    Round,
+    Sign,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Binary {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Min,
+    Max,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Aggregate {
+    Sum,
+    Mean,
+    Min,
+    Max,
+    /// Number of neighbors where the expression is not 0
+    Count,
+    /// Mean weighted by 1 / (1 + distance), as the weighted average rule
+    Weighted,
+    /// Mean weighted by the kernel of the neighborhood, center included
+    Kernel,
+    /// Sum weighted by the kernel of the neighborhood, center included
+    KernelSum,
+}
+
+impl Unary {
+    const FUNCTIONS: &'static [(&'static str, Unary)] = &[
+        ("abs", Unary::Abs),
+        ("sqrt", Unary::Sqrt),
+        ("exp", Unary::Exp),
+        ("ln", Unary::Ln),
+        ("log", Unary::Ln),
+        ("sin", Unary::Sin),
+        ("cos", Unary::Cos),
+        ("tan", Unary::Tan),
+        ("floor", Unary::Floor),
+        ("ceil", Unary::Ceil),
+        ("round", Unary::Round),
+        ("sign", Unary::Sign),
+    ];
+
+    #[inline(always)]
+    fn apply(self, x: f32) -> f32 {
+        match self {
+            Unary::Neg => -x,
+            Unary::Not => (x == 0.0) as u8 as f32,
+            Unary::Abs => x.abs(),
+            Unary::Sqrt => x.sqrt(),
+            Unary::Exp => x.exp(),
+            Unary::Ln => x.ln(),
+            Unary::Sin => x.sin(),
+            Unary::Cos => x.cos(),
+            Unary::Tan => x.tan(),
+            Unary::Floor => x.floor(),
+            Unary::Ceil => x.ceil(),
+            Unary::Round => x.round(),
+            Unary::Sign => if x == 0.0 { 0.0 } else { x.signum() },
+        }
+    }
+}
+
+impl Binary {
+    #[inline(always)]
+    fn apply(self, a: f32, b: f32) -> f32 {
+        match self {
+            Binary::Add => a + b,
+            Binary::Sub => a - b,
+            Binary::Mul => a * b,
+            Binary::Div => a / b,
+            Binary::Rem => a.rem_euclid(b),
+            Binary::Pow => a.powf(b),
+            Binary::Min => a.min(b),
+            Binary::Max => a.max(b),
+            Binary::Lt => (a < b) as u8 as f32,
+            Binary::Le => (a <= b) as u8 as f32,
+            Binary::Gt => (a > b) as u8 as f32,
+            Binary::Ge => (a >= b) as u8 as f32,
+            Binary::Eq => (a == b) as u8 as f32,
+            Binary::Ne => (a != b) as u8 as f32,
+            Binary::And => (a != 0.0 && b != 0.0) as u8 as f32,
+            Binary::Or => (a != 0.0 || b != 0.0) as u8 as f32,
+        }
+    }
+}
+
+impl Aggregate {
+    fn from_name(name: &str) -> Option<Aggregate> {
+        match name {
+            "sum" => Some(Aggregate::Sum),
+            "mean" => Some(Aggregate::Mean),
+            "min" => Some(Aggregate::Min),
+            "max" => Some(Aggregate::Max),
+            "count" => Some(Aggregate::Count),
+            "weighted" => Some(Aggregate::Weighted),
+            "kernel" => Some(Aggregate::Kernel),
+            "kernel_sum" => Some(Aggregate::KernelSum),
+            _ => None,
+        }
+    }
+}
+
+
+// ============================================================
+// Parsing
+// ============================================================
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f32),
+    Ident(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: &[&str] = &[
+    "<=", ">=", "==", "!=", "&&", "||",
+    "+", "-", "*", "/", "%", "^", "(", ")", ",", ".", "<", ">", "!",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c.is_ascii_digit() || (c == '.' && rest[1..].starts_with(|d: char| d.is_ascii_digit())) {
+            let mut end = rest.find(|d: char| !(d.is_ascii_digit() || d == '.')).unwrap_or(rest.len());
+            // Exponent (1e-3)
+            if rest[end..].starts_with(['e', 'E']) {
+                let exponent = rest[end + 1..].strip_prefix(['+', '-']).unwrap_or(&rest[end + 1..]);
+                let digits = exponent.find(|d: char| !d.is_ascii_digit()).unwrap_or(exponent.len());
+                if digits > 0 {
+                    end = rest.len() - exponent.len() + digits;
+                }
+            }
+            let number = rest[..end].parse().map_err(|_| format!("invalid number '{}'", &rest[..end]))?;
+            tokens.push(Token::Number(number));
+            rest = &rest[end..];
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest.find(|d: char| !(d.is_alphanumeric() || d == '_')).unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else {
+            return Err(format!("unexpected character '{}'", c));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Number(f32),
+    Current,
+    Trait(String),
+    NeighborCurrent,
+    NeighborTrait(String),
+    Unary(Unary, Box<Expr>),
+    Binary(Binary, Box<Expr>, Box<Expr>),
+    Clamp(Box<Expr>, Box<Expr>, Box<Expr>),
+    Select(Box<Expr>, Box<Expr>, Box<Expr>),
+    Aggregate(Aggregate, Box<Expr>),
+    Random,
+}
+
+/// Binary operators by precedence level, loosest first
+const LEVELS: &[&[(&str, Binary)]] = &[
+    &[("||", Binary::Or)],
+    &[("&&", Binary::And)],
+    &[("<=", Binary::Le), (">=", Binary::Ge), ("==", Binary::Eq), ("!=", Binary::Ne), ("<", Binary::Lt), (">", Binary::Gt)],
+    &[("+", Binary::Add), ("-", Binary::Sub)],
+    &[("*", Binary::Mul), ("/", Binary::Div), ("%", Binary::Rem)],
+];
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        if self.peek() == Some(&Token::Symbol(SYMBOLS.iter().find(|s| **s == symbol).copied().unwrap_or(""))) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(format!("expected '{}'", symbol))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, String> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Ident(name)) => {
+                self.pos += 1;
+                Ok(name.clone())
+            }
+            _ => Err("expected a name".to_string()),
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        'operators: loop {
+            for &(symbol, op) in LEVELS[level] {
+                if self.eat(symbol) {
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+                    continue 'operators;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("-") {
+            Ok(Expr::Unary(Unary::Neg, Box::new(self.unary()?)))
+        } else if self.eat("!") {
+            Ok(Expr::Unary(Unary::Not, Box::new(self.unary()?)))
+        } else {
+            let base = self.primary()?;
+            // Right associative, binds tighter than the unary minus on its left (-2^2 = -4)
+            if self.eat("^") {
+                Ok(Expr::Binary(Binary::Pow, Box::new(base), Box::new(self.unary()?)))
+            } else {
+                Ok(base)
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(Token::Number(value)) => {
+                self.pos += 1;
+                Ok(Expr::Number(value))
+            }
+            Some(Token::Symbol("(")) => {
+                self.pos += 1;
+                let expr = self.binary(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => {
+                self.pos += 1;
+                if self.eat("(") {
+                    let mut args = Vec::new();
+                    if !self.eat(")") {
+                        loop {
+                            args.push(self.binary(0)?);
+                            if self.eat(")") {
+                                break;
+                            }
+                            self.expect(",")?;
+                        }
+                    }
+                    return call(&name, args);
+                }
+                match name.as_str() {
+                    "self" => Ok(Expr::Current),
+                    "pi" => Ok(Expr::Number(std::f32::consts::PI)),
+                    "nbr" => {
+                        if !self.eat(".") {
+                            return Ok(Expr::NeighborCurrent);
+                        }
+                        match self.ident()?.as_str() {
+                            "self" => Ok(Expr::NeighborCurrent),
+                            name => Ok(Expr::NeighborTrait(name.to_string())),
+                        }
+                    }
+                    _ => Ok(Expr::Trait(name)),
+                }
+            }
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+fn call(name: &str, mut args: Vec<Expr>) -> Result<Expr, String> {
+    let arg = |args: &mut Vec<Expr>| Box::new(args.remove(0));
+    match (name, args.len()) {
+        ("count", 0) => Ok(Expr::Aggregate(Aggregate::Count, Box::new(Expr::Number(1.0)))),
+        ("rand", 0) => Ok(Expr::Random),
+        (_, 1) if Aggregate::from_name(name).is_some() => {
+            Ok(Expr::Aggregate(Aggregate::from_name(name).unwrap(), arg(&mut args)))
+        }
+        (_, 1) if Unary::FUNCTIONS.iter().any(|(f, _)| *f == name) => {
+            let op = Unary::FUNCTIONS.iter().find(|(f, _)| *f == name).unwrap().1;
+            Ok(Expr::Unary(op, arg(&mut args)))
+        }
+        ("pow" | "min" | "max", 2) => {
+            let op = match name {
+                "pow" => Binary::Pow,
+                "min" => Binary::Min,
+                _ => Binary::Max,
+            };
+            Ok(Expr::Binary(op, arg(&mut args), arg(&mut args)))
+        }
+        ("clamp", 3) => Ok(Expr::Clamp(arg(&mut args), arg(&mut args), arg(&mut args))),
+        ("if", 3) => Ok(Expr::Select(arg(&mut args), arg(&mut args), arg(&mut args))),
+        _ => Err(format!("unknown function {}() with {} argument(s)", name, args.len())),
+    }
+}
+
+/// Parses `source`, an optional `next =` prefix is allowed
+fn parse(source: &str) -> Result<Expr, String> {
+    let source = source.trim();
+    let source = match source.strip_prefix("next") {
+        Some(rest) if rest.trim_start().starts_with('=') && !rest.trim_start().starts_with("==") => {
+            &rest.trim_start()[1..]
+        }
+        _ => source,
+    };
+    let mut parser = Parser { tokens: tokenize(source)?, pos: 0 };
+    let expr = parser.binary(0)?;
+    if parser.pos != parser.tokens.len() {
+        return Err(format!("unexpected {:?}", parser.tokens[parser.pos]));
+    }
+    Ok(expr)
+}
+
+
+// ============================================================
+// Compilation
+// ============================================================
+
+struct Compiler<'a> {
+    trait_names: &'a [String],
+    code: Vec<Op>,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl Compiler<'_> {
+    fn push(&mut self, op: Op, depth_change: isize) {
+        self.code.push(op);
+        self.depth = (self.depth as isize + depth_change) as usize;
+        self.max_depth = self.max_depth.max(self.depth);
+    }
+
+    fn trait_index(&self, name: &str) -> Result<usize, String> {
+        self.trait_names
+            .iter()
+            .position(|trait_name| trait_name == name)
+            .ok_or_else(|| format!("unknown trait '{}'", name))
+    }
+
+    fn emit(&mut self, expr: &Expr, in_aggregate: bool) -> Result<(), String> {
+        match expr {
+            Expr::Number(value) => self.push(Op::Const(*value), 1),
+            Expr::Current => self.push(Op::Current, 1),
+            Expr::Random => self.push(Op::Random, 1),
+            Expr::Trait(name) => self.push(Op::Trait(self.trait_index(name)?), 1),
+            Expr::NeighborCurrent | Expr::NeighborTrait(_) if !in_aggregate => {
+                return Err("nbr values are only available inside an aggregate such as mean(nbr.self)".to_string());
+            }
+            Expr::NeighborCurrent => self.push(Op::NeighborCurrent, 1),
+            Expr::NeighborTrait(name) => self.push(Op::NeighborTrait(self.trait_index(name)?), 1),
+            Expr::Unary(op, x) => {
+                self.emit(x, in_aggregate)?;
+                self.push(Op::Unary(*op), 0);
+            }
+            Expr::Binary(op, a, b) => {
+                self.emit(a, in_aggregate)?;
+                self.emit(b, in_aggregate)?;
+                self.push(Op::Binary(*op), -1);
+            }
+            Expr::Clamp(a, b, c) | Expr::Select(a, b, c) => {
+                self.emit(a, in_aggregate)?;
+                self.emit(b, in_aggregate)?;
+                self.emit(c, in_aggregate)?;
+                self.push(if matches!(expr, Expr::Clamp(..)) { Op::Clamp } else { Op::Select }, -2);
+            }
+            Expr::Aggregate(..) if in_aggregate => {
+                return Err("aggregates cannot be nested".to_string());
+            }
+            Expr::Aggregate(kind, body) => {
+                // The body runs on its own stack
+                let mut inner = Compiler { trait_names: self.trait_names, code: Vec::new(), depth: 0, max_depth: 0 };
+                inner.emit(body, true)?;
+                self.max_depth = self.max_depth.max(inner.max_depth);
+                self.push(Op::Aggregate(*kind, inner.code.len()), 1);
+                self.code.extend(inner.code);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compiles `source` into instructions, trait names are resolved to their index in `trait_names`
+pub fn compile(source: &str, trait_names: &[String]) -> Result<Vec<Op>, String> {
+    let expr = parse(source).map_err(|e| format!("Invalid expression '{}': {}", source, e))?;
+    let mut compiler = Compiler { trait_names, code: Vec::new(), depth: 0, max_depth: 0 };
+    compiler.emit(&expr, false).map_err(|e| format!("Invalid expression '{}': {}", source, e))?;
+    if compiler.max_depth > MAX_STACK {
+        return Err(format!("Expression '{}' is nested too deeply", source));
+    }
+    Ok(compiler.code)
+}
+
+/// Checks the syntax of `source` without resolving the trait names
+pub fn check_syntax(source: &str) -> Result<(), String> {
+    parse(source).map(|_| ()).map_err(|e| format!("Invalid expression '{}': {}", source, e))
+}
+
+
+// ============================================================
+// Evaluation
+// ============================================================
+
+struct Context<'a> {
+    trait_index: usize,
+    cell_r: usize,
+    cell_c: usize,
+    neighborhood: &'a Neighborhood,
+    grid: &'a Grid,
+}
+
+/// Value of the compiled expression `code` for the rule of `trait_index` at (cell_r, cell_c)
+#[inline]
+pub fn evaluate(code: &[Op], trait_index: usize, cell_r: usize, cell_c: usize, neighborhood: &Neighborhood, grid: &Grid, rng: &mut RuleRng) -> f32 {
+    let context = Context { trait_index, cell_r, cell_c, neighborhood, grid };
+    run(code, &context, cell_r * grid.width + cell_c, rng)
+}
+
+fn run(code: &[Op], context: &Context, neighbor: usize, rng: &mut RuleRng) -> f32 {
+    let grid = context.grid;
+    let mut stack = [0.0f32; MAX_STACK];
+    let mut sp = 0;
+    let mut pc = 0;
+    while pc < code.len() {
+        match code[pc] {
+            Op::Const(value) => {
+                stack[sp] = value;
+                sp += 1;
+            }
+            Op::Current => {
+                stack[sp] = grid.get_cell_trait(context.cell_r, context.cell_c, context.trait_index);
+                sp += 1;
+            }
+            Op::Trait(trait_index) => {
+                stack[sp] = grid.get_cell_trait(context.cell_r, context.cell_c, trait_index);
+                sp += 1;
+            }
+            Op::NeighborCurrent => {
+                stack[sp] = grid.get_trait_slice(context.trait_index)[neighbor];
+                sp += 1;
+            }
+            Op::NeighborTrait(trait_index) => {
+                stack[sp] = grid.get_trait_slice(trait_index)[neighbor];
+                sp += 1;
+            }
+            Op::Unary(op) => stack[sp - 1] = op.apply(stack[sp - 1]),
+            Op::Binary(op) => {
+                sp -= 1;
+                stack[sp - 1] = op.apply(stack[sp - 1], stack[sp]);
+            }
+            Op::Clamp => {
+                sp -= 2;
+                // Not f32::clamp, which panics when min > max
+                stack[sp - 1] = stack[sp - 1].max(stack[sp]).min(stack[sp + 1]);
+            }
+            Op::Select => {
+                sp -= 2;
+                stack[sp - 1] = if stack[sp - 1] != 0.0 { stack[sp] } else { stack[sp + 1] };
+            }
+            Op::Random => {
+                stack[sp] = rng.r#gen::<f32>();
+                sp += 1;
+            }
+            Op::Aggregate(kind, len) => {
+                let body = &code[pc + 1..pc + 1 + len];
+                stack[sp] = aggregate(kind, body, context, rng);
+                sp += 1;
+                pc += len;
+            }
+        }
+        pc += 1;
+    }
+    stack[0]
+}
+
+/// Combines `body` over the non-empty neighbors of the mask (center excluded),
+/// or over the non-empty cells of non-zero weight for the kernel aggregates (center included).
+/// Without neighbors, count and the sums give 0 and the others the value of `body` at the cell itself
+fn aggregate(kind: Aggregate, body: &[Op], context: &Context, rng: &mut RuleRng) -> f32 {
+    let neighborhood = context.neighborhood;
+    let grid = context.grid;
+
+    let mut total = match kind {
+        Aggregate::Min => f32::INFINITY,
+        Aggregate::Max => f32::NEG_INFINITY,
+        _ => 0.0,
+    };
+    let mut weight_sum = 0.0;
+    let mut count = 0;
+
+    let kernel = matches!(kind, Aggregate::Kernel | Aggregate::KernelSum);
+    let offsets = if kernel { neighborhood.offsets() } else { neighborhood.neighbor_offsets() };
+    neighborhood.for_each_cell(offsets, context.cell_r, context.cell_c, grid, |offset, idx| {
+        if (kernel && offset.weight == 0.0) || grid.is_empty[idx] {
+            return;
+        }
+        let value = run(body, context, idx, rng);
+        count += 1;
+        match kind {
+            Aggregate::Sum | Aggregate::Mean => total += value,
+            Aggregate::Min => total = total.min(value),
+            Aggregate::Max => total = total.max(value),
+            Aggregate::Count => total += (value != 0.0) as u8 as f32,
+            Aggregate::Weighted => {
+                total += value * offset.distance_weight;
+                weight_sum += offset.distance_weight;
+            }
+            Aggregate::Kernel | Aggregate::KernelSum => {
+                total += value * offset.weight;
+                weight_sum += offset.weight;
+            }
+        }
+    });
+
+    match kind {
+        Aggregate::Sum | Aggregate::Count | Aggregate::KernelSum => total,
+        _ if count == 0 || (kind == Aggregate::Kernel && weight_sum == 0.0) => run(body, context, context.cell_r * grid.width + context.cell_c, rng),
+        Aggregate::Mean => total / count as f32,
+        Aggregate::Weighted | Aggregate::Kernel => total / weight_sum,
+        Aggregate::Min | Aggregate::Max => total,
+    }
+}
+
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::StepRandom;
+
+    fn names() -> Vec<String> {
+        vec!["Energy".to_string(), "Charge".to_string()]
+    }
+
+    fn eval(source: &str, grid: &Grid) -> f32 {
+        let code = compile(source, &names()).unwrap();
+        let neighborhood = Neighborhood::new(3, 3, 1, 1, vec![vec![1; 3]; 3]);
+        evaluate(&code, 0, 1, 1, &neighborhood, grid, &mut RuleRng::new(StepRandom::new(0, 0), 4, 0))
+    }
+
+    fn build_grid() -> Grid {
+        let mut grid = Grid::new(3, 3, 2);
+        for r in 0..3 {
+            for c in 0..3 {
+                grid.set_cell_trait(r, c, 0, (r * 3 + c) as f32);
+                grid.set_cell_trait(r, c, 1, 0.5);
+            }
+        }
+        grid
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let grid = build_grid();
+        assert_eq!(eval("1 + 2 * 3", &grid), 7.0);
+        assert_eq!(eval("(1 + 2) * 3", &grid), 9.0);
+        assert_eq!(eval("-2^2", &grid), -4.0);
+        assert_eq!(eval("2^3^2", &grid), 512.0);
+        assert_eq!(eval("7 % 4 + 1e1", &grid), 13.0);
+        assert_eq!(eval("1 < 2 && 2 <= 2 || 0", &grid), 1.0);
+        assert_eq!(eval("!(self == 4)", &grid), 0.0);
+        assert_eq!(eval("if(Charge > 0.4, 10, 20)", &grid), 10.0);
+        assert_eq!(eval("clamp(self, 0, 1) + max(2, 3) + abs(-1)", &grid), 5.0);
+        assert_eq!(eval("next = self + Charge", &grid), 4.5);
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let mut grid = build_grid();
+        // Neighbors of the center: 0 1 2 3 5 6 7 8
+        assert_eq!(eval("sum(nbr.Energy)", &grid), 32.0);
+        assert_eq!(eval("mean(nbr)", &grid), 4.0);
+        assert_eq!(eval("min(nbr.self) + max(nbr.self)", &grid), 8.0);
+        assert_eq!(eval("count()", &grid), 8.0);
+        assert_eq!(eval("count(nbr.Energy > self)", &grid), 4.0);
+        assert_eq!(eval("sum(nbr.Charge * self)", &grid), 16.0);
+        assert!((eval("weighted(nbr.Charge)", &grid) - 0.5).abs() < 1e-6);
+        assert!((0.0..1.0).contains(&eval("rand()", &grid)));
+        // Uniform kernel: the 3x3 block, center included
+        assert_eq!(eval("kernel_sum(nbr.self)", &grid), 36.0);
+        assert_eq!(eval("kernel(nbr.self)", &grid), 4.0);
+
+        // Empty neighbors are skipped, without neighbors the cell stands for them
+        grid.is_empty[0] = true;
+        assert_eq!(eval("count()", &grid), 7.0);
+        for idx in 0..9 {
+            grid.is_empty[idx] = idx != 4;
+        }
+        assert_eq!(eval("mean(nbr.Energy)", &grid), 4.0);
+        assert_eq!(eval("sum(nbr.Energy)", &grid), 0.0);
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        for source in [
+            "", "1 +", "(1", "foo(1)", "min(1, 2, 3)", "Spin", "nbr.Energy",
+            "mean(mean(nbr))", "sum(nbr.Spin)", "1 $ 2", "1 2",
+        ] {
+            assert!(compile(source, &names()).is_err(), "{}", source);
+        }
+        assert!(check_syntax("mean(nbr.Spin)").is_ok());
+        let deep = format!("{}1{}", "(1 + ".repeat(40), ")".repeat(40));
+        assert!(compile(&deep, &names()).is_err());
+    }
+}
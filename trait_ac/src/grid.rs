@@ -1,33 +1,184 @@
 use rand::Rng;
 
 
+/// What lies beyond an edge of the grid, set independently for each axis
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Boundary {
+    /// The opposite edge (torus)
+    #[default]
+    Periodic,
+    /// Dead empty cells, and a wall that blocks movement
+    Fixed,
+    /// The grid mirrored at the edge, movement bounces back
+    Reflective,
+    /// Dead empty cells, individuals moving out are removed
+    Absorbing,
+}
+
+impl Boundary {
+    pub const ALL: &'static [Boundary] = &[Boundary::Periodic, Boundary::Fixed, Boundary::Reflective, Boundary::Absorbing];
+    pub const NAMES: &'static [&'static str] = &["periodic", "fixed", "reflective", "absorbing"];
+
+    #[inline]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Boundary::Periodic => "periodic",
+            Boundary::Fixed => "fixed",
+            Boundary::Reflective => "reflective",
+            Boundary::Absorbing => "absorbing",
+        }
+    }
+
+    #[inline]
+    pub fn from_name(name: &str) -> Option<Boundary> {
+        Boundary::ALL.iter().copied().find(|b| b.name() == name)
+    }
+
+    /// Coordinate seen by a neighborhood lookup, None when the neighbor is outside (dead)
+    #[inline(always)]
+    fn lookup(&self, v: isize, max: usize) -> Option<usize> {
+        if v >= 0 && v < max as isize {
+            return Some(v as usize);
+        }
+        match self {
+            Boundary::Periodic => Some(v.rem_euclid(max as isize) as usize),
+            Boundary::Reflective => Some(reflect(v, max)),
+            Boundary::Fixed | Boundary::Absorbing => None,
+        }
+    }
+
+    /// Coordinate reached by a move, None when the individual leaves the grid
+    #[inline(always)]
+    fn moved(&self, v: isize, max: usize) -> Option<usize> {
+        if v >= 0 && v < max as isize {
+            return Some(v as usize);
+        }
+        match self {
+            Boundary::Periodic => Some(v.rem_euclid(max as isize) as usize),
+            Boundary::Fixed => Some(v.clamp(0, max as isize - 1) as usize),
+            Boundary::Reflective => Some(reflect(v, max)),
+            Boundary::Absorbing => None,
+        }
+    }
+}
+
+/// Arrangement of the cells, both stored row-major
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Lattice {
+    /// Square cells, neighbors along the rows, the columns and the diagonals
+    #[default]
+    Square,
+    /// Hexagonal cells in axial coordinates: each row is shifted half a cell right of the row
+    /// above, so that the six neighbors of (r, c) are (r, c ± 1), (r - 1, c), (r - 1, c + 1),
+    /// (r + 1, c - 1) and (r + 1, c). The `Shape::Hexagonal` neighborhoods follow this layout
+    Hexagonal,
+}
+
+impl Lattice {
+    pub const ALL: &'static [Lattice] = &[Lattice::Square, Lattice::Hexagonal];
+    pub const NAMES: &'static [&'static str] = &["square", "hexagonal"];
+
+    #[inline]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lattice::Square => "square",
+            Lattice::Hexagonal => "hexagonal",
+        }
+    }
+
+    #[inline]
+    pub fn from_name(name: &str) -> Option<Lattice> {
+        Lattice::ALL.iter().copied().find(|l| l.name() == name)
+    }
+
+    /// Unit moves to the cells sharing an edge: 4 on the square lattice, 6 on the hexagonal one
+    #[inline]
+    pub fn directions(&self) -> &'static [(isize, isize)] {
+        match self {
+            Lattice::Square => &[(0, 1), (0, -1), (1, 0), (-1, 0)],
+            Lattice::Hexagonal => &[(0, 1), (0, -1), (1, 0), (-1, 0), (-1, 1), (1, -1)],
+        }
+    }
+
+    /// Position (y, x) of the offset (dr, dc) in the plane, in cell widths
+    #[inline(always)]
+    pub fn to_plane(&self, dr: f32, dc: f32) -> (f32, f32) {
+        match self {
+            Lattice::Square => (dr, dc),
+            Lattice::Hexagonal => (dr * 3.0f32.sqrt() / 2.0, dc + dr / 2.0),
+        }
+    }
+
+    /// Euclidean distance between the centers of two cells (dr, dc) apart
+    #[inline]
+    pub fn distance(&self, dr: isize, dc: isize) -> f32 {
+        match self {
+            Lattice::Square => ((dr * dr + dc * dc) as f32).sqrt(),
+            Lattice::Hexagonal => ((dr * dr + dc * dc + dr * dc) as f32).sqrt(),
+        }
+    }
+
+    /// Direction closest to the displacement (dr, dc) in the plane, (0, 0) for no displacement
+    pub fn nearest_direction(&self, dr: f32, dc: f32) -> (isize, isize) {
+        let (y, x) = self.to_plane(dr, dc);
+        if y == 0.0 && x == 0.0 {
+            return (0, 0);
+        }
+        let alignment = |&&(r, c): &&(isize, isize)| {
+            let (dy, dx) = self.to_plane(r as f32, c as f32);
+            y * dy + x * dx
+        };
+        *self.directions().iter().max_by(|a, b| alignment(a).total_cmp(&alignment(b))).unwrap()
+    }
+}
+
+/// Mirror at the edge line: -1 -> 0, max -> max - 1
+#[inline(always)]
+fn reflect(v: isize, max: usize) -> usize {
+    let period = 2 * max as isize;
+    let m = v.rem_euclid(period);
+    if m < max as isize { m as usize } else { (period - 1 - m) as usize }
+}
+
+
 /// Represents a 2D grid of cells (row-major, flat)
+#[derive(Clone)]
 pub struct Grid {
     pub width: usize,
     pub height: usize,
     pub num_cells: usize,
     pub num_traits: usize,
-    /// Single contiguous allocation: [trait0..., trait1..., ..., trait8...]
+    /// Single contiguous allocation: [trait0..., trait1..., ..., trait(num_traits - 1)...]
     pub data: Vec<f32>,
     pub is_empty: Vec<bool>,
+    /// Boundary of the top and bottom edges
+    pub row_boundary: Boundary,
+    /// Boundary of the left and right edges
+    pub col_boundary: Boundary,
+    /// Square or hexagonal cells, the neighborhoods and the movement follow it
+    pub lattice: Lattice,
 }
 
 impl Grid {
     /// Create a new grid with random cells
     pub fn new(width: usize, height: usize, num_traits: usize) -> Self {
         let trait_ranges = vec![(0.0, 1.0); num_traits];
-        Self::new_with_density(width, height, 1.0, num_traits, &trait_ranges)
+        Self::new_with_density(width, height, 1.0, num_traits, &trait_ranges, &mut rand::thread_rng())
     }
 
-    pub fn new_with_density(width: usize,
+    /// Create a new grid, drawing occupancy and trait values from `rng` (seed it for reproducible runs)
+    pub fn new_with_density<R: Rng>(width: usize,
                             height: usize,
                             fill_percentage: f32,
                             num_traits: usize,
-                            trait_ranges: &Vec<(f32, f32)>,  // (min, max) for each trait
+                            trait_ranges: &[(f32, f32)],  // (min, max) for each trait
+                            rng: &mut R,
                             ) -> Self {
 
+        if let Err(e) = Self::check_dimensions(width, height, num_traits) {
+            panic!("Invalid grid {}x{} with {} traits: {}", width, height, num_traits, e);
+        }
         let fill_percentage = fill_percentage.clamp(0.0, 1.0);
-        let mut rng = rand::thread_rng();
         let num_cells = width * height;
         
         // Single allocation for all traits
@@ -52,7 +203,38 @@ impl Grid {
             num_traits,
             data,
             is_empty,
+            row_boundary: Boundary::Periodic,
+            col_boundary: Boundary::Periodic,
+            lattice: Lattice::Square,
+        }
+    }
+
+    /// Check that a grid of this size can be indexed and allocated, returns its number of cells.
+    /// Cell indices are `usize` everywhere (movement reserves `usize::MAX` as a marker).
+    pub fn check_dimensions(width: usize, height: usize, num_traits: usize) -> Result<usize, &'static str> {
+        if width == 0 || height == 0 {
+            return Err("grid dimensions must be > 0");
         }
+        let num_cells = width
+            .checked_mul(height)
+            .filter(|&n| n < usize::MAX)
+            .ok_or("too many cells for this platform")?;
+        num_cells
+            .checked_mul(num_traits)
+            .and_then(|n| n.checked_mul(std::mem::size_of::<f32>()))
+            .filter(|&bytes| bytes <= isize::MAX as usize)
+            .ok_or("too many trait values for this platform")?;
+        Ok(num_cells)
+    }
+
+    pub fn set_boundaries(&mut self, row_boundary: Boundary, col_boundary: Boundary) {
+        self.row_boundary = row_boundary;
+        self.col_boundary = col_boundary;
+    }
+
+    #[inline(always)]
+    pub fn is_periodic(&self) -> bool {
+        self.row_boundary == Boundary::Periodic && self.col_boundary == Boundary::Periodic
     }
 
     #[inline(always)]
@@ -73,23 +255,22 @@ impl Grid {
         row * self.width + col
     }
 
+    /// Position of a neighbor, None when it lies beyond a non-wrapping edge (dead cell)
     #[inline(always)]
-    fn wrap(&self, v: isize, max: usize) -> usize {
-        if v < 0 {
-            (v + max as isize) as usize
-        } else if v >= max as isize {
-            (v - max as isize) as usize
-        } else {
-            v as usize
-        }
+    pub fn get_position(&self, row: isize, col: isize) -> Option<(usize, usize)> {
+        Some((
+            self.row_boundary.lookup(row, self.height)?,
+            self.col_boundary.lookup(col, self.width)?,
+        ))
     }
 
+    /// Destination of a move, None when the individual is absorbed by an edge
     #[inline(always)]
-    pub fn get_position(&self, row: isize, col: isize) -> (usize, usize) {
-        (
-            self.wrap(row, self.height),
-            self.wrap(col, self.width),
-        )
+    pub fn get_move_target(&self, row: isize, col: isize) -> Option<(usize, usize)> {
+        Some((
+            self.row_boundary.moved(row, self.height)?,
+            self.col_boundary.moved(col, self.width)?,
+        ))
     }
 
     #[inline(always)]
@@ -133,14 +314,13 @@ impl Grid {
         }
     }
 
-    pub fn randomize(&mut self) {
-        let mut rng = rand::thread_rng();
-        
-        for r in 0..self.width {
-            for c in 0..self.height {
+    /// Draw new trait values for the filled cells, each trait from its (min, max) range
+    pub fn randomize<R: Rng>(&mut self, trait_ranges: &[(f32, f32)], rng: &mut R) {
+        for r in 0..self.height {
+            for c in 0..self.width {
                 if !self.is_cell_empty(r, c) {  // false = filled
-                    for t in 0..self.num_traits {
-                        self.set_cell_trait(r, c, t, rng.gen_range(0.0..=1.0));
+                    for (t, &(min, max)) in trait_ranges.iter().enumerate().take(self.num_traits) {
+                        self.set_cell_trait(r, c, t, rng.gen_range(min..=max));
                     }
                 }
             }
@@ -155,10 +335,12 @@ impl Grid {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
 
     #[test]
     fn test_grid_creation() {
-        let grid = Grid::new(5, 5);
+        let grid = Grid::new(5, 5, 1);
         assert_eq!(grid.width, 5);
         assert_eq!(grid.height, 5);
         assert_eq!(grid.data.len(), 5*5*grid.num_traits);
@@ -166,29 +348,82 @@ mod tests {
 
     #[test]
     fn test_wrapping() {
-        let grid = Grid::new(5, 5);
+        let grid = Grid::new(5, 5, 1);
         let pos = grid.get_position(-1, -1);
-        assert_eq!(pos, (4, 4));
+        assert_eq!(pos, Some((4, 4)));
+    }
+
+    #[test]
+    fn test_check_dimensions() {
+        assert_eq!(Grid::check_dimensions(70_000, 70_000, 2), Ok(4_900_000_000));
+        assert!(Grid::check_dimensions(0, 10, 1).is_err());
+        assert!(Grid::check_dimensions(usize::MAX, 2, 1).is_err());
+        assert!(Grid::check_dimensions(1 << 20, 1 << 20, 1 << 30).is_err());
+    }
+
+    #[test]
+    fn test_boundaries() {
+        let mut grid = Grid::new(5, 4, 1);
+        grid.set_boundaries(Boundary::Fixed, Boundary::Reflective);
+        assert_eq!(grid.get_position(-1, 2), None);
+        assert_eq!(grid.get_position(2, -1), Some((2, 0)));
+        assert_eq!(grid.get_position(2, 6), Some((2, 3)));
+        assert_eq!(grid.get_move_target(4, 2), Some((3, 2)));
+
+        grid.set_boundaries(Boundary::Absorbing, Boundary::Periodic);
+        assert_eq!(grid.get_position(1, 5), Some((1, 0)));
+        assert_eq!(grid.get_move_target(-1, 0), None);
     }
 
     #[test]
     fn test_grid_with_density() {
-        let grid = Grid::new_with_density(1000, 1000, 0.5);
+        let grid = Grid::new_with_density(1000, 1000, 0.5, 1, &[(0.0, 1.0)], &mut rand::thread_rng());
         let fill_percentage = grid.get_fill_percentage();
-        assert!(fill_percentage >= 0.45 && fill_percentage <= 0.55);
+        assert!((0.45..=0.55).contains(&fill_percentage));
+    }
+
+    #[test]
+    fn test_seeded_grid_is_reproducible() {
+        let ranges = [(0.0, 1.0); 2];
+        let a = Grid::new_with_density(20, 20, 0.5, 2, &ranges, &mut StdRng::seed_from_u64(7));
+        let b = Grid::new_with_density(20, 20, 0.5, 2, &ranges, &mut StdRng::seed_from_u64(7));
+        assert_eq!(a.data, b.data);
+        assert_eq!(a.is_empty, b.is_empty);
     }
 
     #[test]
     fn test_fully_empty_grid() {
-        let grid = Grid::new_with_density(5, 5, 0.0);
+        let grid = Grid::new_with_density(5, 5, 0.0, 1, &[(0.0, 1.0)], &mut rand::thread_rng());
         assert_eq!(grid.count_filled_cells(), 0);
         assert_eq!(grid.get_fill_percentage(), 0.0);
     }
 
     #[test]
     fn test_fully_filled_grid() {
-        let grid = Grid::new_with_density(5, 5, 1.0);
+        let grid = Grid::new_with_density(5, 5, 1.0, 1, &[(0.0, 1.0)], &mut rand::thread_rng());
         assert_eq!(grid.count_filled_cells(), 25);
         assert_eq!(grid.get_fill_percentage(), 1.0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_lattice() {
+        assert_eq!(Lattice::from_name("hexagonal"), Some(Lattice::Hexagonal));
+        assert_eq!(Lattice::default().name(), "square");
+
+        // The six hexagonal neighbors are one cell width away, the left out corners farther
+        let hex = Lattice::Hexagonal;
+        for &(dr, dc) in hex.directions() {
+            assert!((hex.distance(dr, dc) - 1.0).abs() < 1e-6, "({}, {})", dr, dc);
+        }
+        assert!((hex.distance(1, 1) - 3.0f32.sqrt()).abs() < 1e-6);
+        assert_eq!(Lattice::Square.distance(1, 1), 2.0f32.sqrt());
+
+        assert_eq!(Lattice::Square.nearest_direction(0.2, -0.7), (0, -1));
+        assert_eq!(hex.nearest_direction(0.0, 0.0), (0, 0));
+        // Straight down in the plane lies between (1, -1) and (1, 0), slightly to the right goes (1, 0)
+        assert_eq!(hex.nearest_direction(1.0, -0.4), (1, 0));
+        assert_eq!(hex.nearest_direction(1.0, -0.6), (1, -1));
+        // A diagonal of the storage is not a step on the hexagonal lattice
+        assert!([(0, -1), (-1, 0)].contains(&hex.nearest_direction(-1.0, -1.0)));
+    }
+}
@@ -1,5 +1,13 @@
+pub mod checkpoint;
+pub mod config;
+pub mod convolution;
+pub mod expression;
 pub mod grid;
 pub mod movement;
 pub mod neighborhood;
+pub mod random;
 pub mod rules;
+pub mod simulation;
+pub mod traits;
+pub mod update;
 pub mod utils;
@@ -1,94 +1,33 @@
-use trait_ac::neighborhood::Neighborhood;
-use trait_ac::grid::Grid;
-use trait_ac::rules::{RulesRegistry, Rule};
-use trait_ac::movement::{MovementRegistry, Movement};
-use trait_ac::utils::{print_separator, semantic_traits_names, print_active_traits}; // print_trait_array
+use trait_ac::config::EngineConfig;
+use trait_ac::simulation::Simulation;
+use trait_ac::traits::TraitInfo;
+use trait_ac::utils::{print_separator, print_active_traits}; // print_trait_array
 use std::time::Instant;
-use rayon::prelude::*;
 use serde::Deserialize;
 use std::fs;
 
-fn deserialize_rules<'de, D>(deserializer: D) -> Result<Vec<Rule>, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    let names: Vec<String> = Vec::deserialize(deserializer)?;
-    names
-        .into_iter()
-        .map(|name| {
-            Rule::from_name(&name).ok_or_else(|| serde::de::Error::custom(format!("Unknown rule: {}", name)))
-        })
-        .collect()
-}
-
-fn deserialize_movement<'de, D>(deserializer: D) -> Result<Movement, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    let name = String::deserialize(deserializer)?;
-    Movement::from_name(&name)
-        .ok_or_else(|| serde::de::Error::custom(format!("Unknown movement: {}", name)))
-}
-
 #[derive(Deserialize)]
 #[serde(default)]
 pub struct Config {
-    // Grid settings
-    pub grid_height: usize,
-    pub grid_width: usize,
-    pub grid_density: f32,
+    // Grid, traits, rules, movement and neighborhoods
+    #[serde(flatten)]
+    pub engine: EngineConfig,
     pub timesteps: usize,
 
-    // Trait settings
-    pub num_traits: usize,
-    pub active_mask: Vec<u8>,
-    pub initialisation_ranges: Vec<(f32, f32)>,
-
-    // Rules & movement (with custom deserializers)
-    #[serde(deserialize_with = "deserialize_rules")]
-    pub rules: Vec<Rule>,
-    #[serde(deserialize_with = "deserialize_movement")]
-    pub movement: Movement,
-
-    // Neighborhood masks
-    pub neighborhood_traits_mask: Vec<Vec<u8>>,
-    pub neighborhood_mvt_mask: Vec<Vec<u8>>,
+    // Checkpoints
+    pub resume_from: Option<String>,
+    pub checkpoint_path: Option<String>,
+    pub checkpoint_interval: usize,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            grid_height: 1000,
-            grid_width: 1000,
-            grid_density: 1.0,
+            engine: EngineConfig::default(),
             timesteps: 100,
-            num_traits: 1,
-            active_mask: vec![
-                1, 0, 0,
-                0, 0, 0,
-                0, 0, 0,
-            ],
-            initialisation_ranges: vec![
-                (0.0, 1.0), (0.0, 1.0), (0.0, 1.0),
-                (0.0, 1.0), (0.0, 1.0), (0.0, 1.0),
-                (0.0, 1.0), (0.0, 1.0), (0.0, 1.0),
-            ],
-            rules: vec![
-                Rule::ConwayOptimized, Rule::ConwayOptimized, Rule::ConwayOptimized,
-                Rule::ConwayOptimized, Rule::ConwayOptimized, Rule::ConwayOptimized,
-                Rule::ConwayOptimized, Rule::ConwayOptimized, Rule::ConwayOptimized,
-            ],
-            movement: Movement::Static,
-            neighborhood_traits_mask: vec![
-                vec![1, 1, 1],
-                vec![1, 1, 1],
-                vec![1, 1, 1],
-            ],
-            neighborhood_mvt_mask: vec![
-                vec![1, 1, 1],
-                vec![1, 1, 1],
-                vec![1, 1, 1],
-            ],
+            resume_from: None,
+            checkpoint_path: None,
+            checkpoint_interval: 0,
         }
     }
 }
@@ -96,32 +35,18 @@ impl Default for Config {
 impl Config {
     pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
         let content = fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&content)?;
+        let mut config: Config = toml::from_str(&content)?;
+        config.engine.resolve()?;
         config.validate()?;
         Ok(config)
     }
 
     fn validate(&self) -> Result<(), &'static str> {
-        if self.grid_width == 0 || self.grid_height == 0 {
-            return Err("Grid dimensions must be > 0");
-        }
-        if !(0.0..=1.0).contains(&self.grid_density) {
-            return Err("Density must be between 0.0 and 1.0");
-        }
         if self.timesteps == 0 {
             return Err("Timesteps must be > 0");
         }
-        if self.num_traits == 0 {
-            return Err("num_traits must be > 0");
-        }
-        if self.active_mask.is_empty() {
-            return Err("active_mask must not be empty");
-        }
-        if self.initialisation_ranges.is_empty() {
-            return Err("initialisation_ranges must not be empty");
-        }
-        if self.rules.is_empty() {
-            return Err("rules must not be empty");
+        if self.checkpoint_interval > 0 && self.checkpoint_path.is_none() {
+            return Err("checkpoint_interval requires checkpoint_path");
         }
         Ok(())
     }
@@ -135,112 +60,80 @@ fn main() {
         std::process::exit(1);
     });
 
-    let trait_names = semantic_traits_names();
-
-    let rules_registry = RulesRegistry::custom(config.rules);
-    let mut movement_registry = MovementRegistry::custom(
-        config.grid_width,
-        config.grid_height,
-        config.movement,
-    );
-
-    let mut grid = Grid::new_with_density(
-        config.grid_width,
-        config.grid_height,
-        config.grid_density,
-        config.num_traits,
-        &config.initialisation_ranges,
-    );
-
-    let neighborhood_traits_height = config.neighborhood_traits_mask.len();
-    let neighborhood_traits_width = config.neighborhood_traits_mask[0].len();
-    let neighborhood_traits_center_row = (neighborhood_traits_height - 1) / 2;
-    let neighborhood_traits_center_col = (neighborhood_traits_width - 1) / 2;
-
-    let neighborhood_mvt_height = config.neighborhood_mvt_mask.len();
-    let neighborhood_mvt_width = config.neighborhood_mvt_mask[0].len();
-    let neighborhood_mvt_center_row = (neighborhood_mvt_height - 1) / 2;
-    let neighborhood_mvt_center_col = (neighborhood_mvt_width - 1) / 2;
-
-    let neighborhood_traits = Neighborhood::new(
-        neighborhood_traits_width,
-        neighborhood_traits_height,
-        neighborhood_traits_center_row,
-        neighborhood_traits_center_col,
-        config.neighborhood_traits_mask,
-    );
+    let mut simulation = if let Some(path) = &config.resume_from {
+        println!("Resuming from checkpoint: {}\n", path);
+        Simulation::load_checkpoint(path).unwrap_or_else(|e| {
+            eprintln!("Checkpoint error: {}", e);
+            std::process::exit(1);
+        })
+    } else {
+        Simulation::from_config(&config.engine).unwrap_or_else(|e| {
+            eprintln!("Config error: {}", e);
+            std::process::exit(1);
+        })
+    };
 
-    let neighborhood_mvt = Neighborhood::new(
-        neighborhood_mvt_width,
-        neighborhood_mvt_height,
-        neighborhood_mvt_center_row,
-        neighborhood_mvt_center_col,
-        config.neighborhood_mvt_mask,
-    );
+    // A resumed run keeps the configured metadata only if it has the same number of traits,
+    // otherwise the traits get the names saved with the rules
+    let traits = if config.engine.traits.len() == simulation.num_traits() {
+        config.engine.traits.clone()
+    } else {
+        simulation.rules_registry().get_trait_names()
+            .iter()
+            .take(simulation.num_traits())
+            .map(TraitInfo::new)
+            .collect()
+    };
 
     println!("Configuration:");
-    println!("  Grid: {}x{}", grid.width, grid.height);
+    println!("  Grid: {}x{}", simulation.grid().width, simulation.grid().height);
     println!("  Timesteps: {}", config.timesteps);
-    print_active_traits(config.num_traits, &config.active_mask, &trait_names, &rules_registry);
-
-    // Pre-allocate next grid
-    let mut next_grid = Grid {
-        width: grid.width,
-        height: grid.height,
-        num_cells: grid.num_cells,
-        num_traits: grid.num_traits,
-        data: grid.data.clone(),
-        is_empty: grid.is_empty.clone(),
-    };
-
-    // Collect active trait indices once
-    let active_traits: Vec<usize> = config.active_mask
-        .iter()
-        .enumerate()
-        .filter_map(|(i, &m)| if m != 0 { Some(i) } else { None })
+    let (row_boundary, col_boundary) = simulation.boundaries();
+    println!("  Boundaries: {} (rows), {} (cols)", row_boundary.name(), col_boundary.name());
+    println!("  Lattice: {}", simulation.lattice().name());
+    match simulation.update_mode().probability() {
+        Some(probability) => println!("  Update: {} (p = {})", simulation.update_mode().name(), probability),
+        None => println!("  Update: {}", simulation.update_mode().name()),
+    }
+    println!("  Seed: {}", simulation.seed());
+    let movement = simulation.movement_registry().get_movement();
+    let params = simulation.movement_registry().get_params();
+    let inputs: Vec<String> = movement.inputs().iter()
+        .zip(&params.inputs)
+        .map(|(input, &trait_idx)| format!("{} = {}", input, traits[trait_idx].name))
+        .collect();
+    if inputs.is_empty() {
+        println!("  Movement: {}", movement.name());
+    } else {
+        println!("  Movement: {} [{}]", movement.name(), inputs.join(", "));
+    }
+    let active_mask: Vec<u8> = (0..simulation.num_traits())
+        .map(|i| simulation.active_traits().contains(&i) as u8)
         .collect();
+    print_active_traits(&traits, &active_mask, simulation.rules_registry());
 
-    //print_trait_array(&grid, 0, &trait_names);
+    //print_trait_array(simulation.grid(), 0, &traits);
 
     // Simulation loop
     let start = Instant::now();
-    for _t in 1..=config.timesteps {
-        let width = grid.width;
-    
-        // Sequential over active traits (small number), parallel over rows
-        for &trait_idx in &active_traits {
-            let current = grid.get_trait_slice(trait_idx);
-            let next_trait = next_grid.get_trait_slice_mut(trait_idx);
-            
-            // Process rows in parallel
-            next_trait
-                .par_chunks_mut(width)
-                .enumerate()
-                .for_each(|(row, next_row)| {
-                    let row_offset = row * width;
-                    
-                    for col in 0..width {
-                        let idx = row_offset + col;
-                        
-                        next_row[col] = if grid.is_empty[idx] {
-                            current[idx]
-                        } else {
-                            rules_registry.apply_rule(trait_idx, row, col, &neighborhood_traits, &grid)
-                        };
-                    }
-                });
+    match &config.checkpoint_path {
+        Some(path) => {
+            let interval = if config.checkpoint_interval == 0 { config.timesteps } else { config.checkpoint_interval };
+            let mut remaining = config.timesteps;
+            while remaining > 0 {
+                let steps = interval.min(remaining);
+                simulation.run(steps);
+                remaining -= steps;
+                if let Err(e) = simulation.save_checkpoint(path) {
+                    eprintln!("Checkpoint error: {}", e);
+                    std::process::exit(1);
+                }
+                println!("  Checkpoint saved at timestep {}: {}", simulation.timestep(), path);
+            }
         }
-
-        // --- STEP 2: Movement ---
-        movement_registry.apply_movement(
-            &neighborhood_mvt,
-            &mut grid,
-            &mut next_grid,
-        );
-
-        // no need to swap the grids as the updates naturally comes to "grid" after the 2 steps
+        None => simulation.run(config.timesteps),
     }
-    //print_trait_array(&grid, 0, &trait_names);
+    //print_trait_array(simulation.grid(), 0, &traits);
 
     print_separator();
     println!("\nSimulation complete!");
@@ -253,6 +146,6 @@ fn main() {
     );
     println!(
         "Cells/sec: {:.2}M",
-        (grid.width * grid.height * config.timesteps) as f64 / elapsed.as_secs_f64() / 1_000_000.0
+        (simulation.grid().width * simulation.grid().height * config.timesteps) as f64 / elapsed.as_secs_f64() / 1_000_000.0
     );
 }
\ No newline at end of file
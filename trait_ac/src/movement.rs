@@ -1,115 +1,153 @@
-use crate::grid::Grid;
+use crate::grid::{Grid, Lattice};
 use crate::neighborhood::Neighborhood;
+use crate::random::{CellRng, StepRandom, STREAM_BID, STREAM_MOVEMENT};
+use crate::traits::TraitInfo;
 use rayon::prelude::*;
-use std::sync::atomic::{AtomicU64, Ordering};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
 use rand::prelude::*;
 
 
+/// Inputs and options of the movement function
+#[derive(Clone, Debug, PartialEq)]
+pub struct MovementParams {
+    /// Trait index read by every input of the movement, in the order of `Movement::inputs`
+    pub inputs: Vec<usize>,
+    /// Follow the gradient downward instead of upward
+    pub descend: bool,
+    /// Meaning depends on the movement, see `Movement::default_threshold`
+    pub threshold: f32,
+}
+
+impl MovementParams {
+    /// Input i reads trait i, with the default threshold of the movement
+    pub fn defaults(movement: Movement) -> Self {
+        Self {
+            inputs: (0..movement.inputs().len()).collect(),
+            descend: false,
+            threshold: movement.default_threshold(),
+        }
+    }
+}
+
+
+/// `[movement_params]` config table, inputs are mapped to traits by name:
+///
+/// ```toml
+/// [movement_params]
+/// inputs = { field = "Resource" }
+/// descend = true
+/// threshold = 0.1
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct MovementConfig {
+    /// Trait name read by each input, unlisted inputs read the trait at their position
+    pub inputs: BTreeMap<String, String>,
+    pub descend: bool,
+    /// Default threshold of the movement when absent
+    pub threshold: Option<f32>,
+}
+
+impl MovementConfig {
+    /// Trait indices and options for `movement` on the given traits
+    pub fn resolve(&self, movement: Movement, traits: &[TraitInfo]) -> Result<MovementParams, &'static str> {
+        if self.inputs.keys().any(|input| !movement.inputs().contains(&input.as_str())) {
+            return Err("movement_params.inputs names an input the movement does not have");
+        }
+        let mut inputs = Vec::with_capacity(movement.inputs().len());
+        for (position, input) in movement.inputs().iter().enumerate() {
+            let trait_idx = match self.inputs.get(*input) {
+                Some(name) => traits
+                    .iter()
+                    .position(|info| &info.name == name)
+                    .ok_or("movement_params.inputs names an unknown trait")?,
+                None => position,
+            };
+            if trait_idx >= traits.len() {
+                return Err("the movement has more inputs than there are traits, map them in movement_params.inputs");
+            }
+            inputs.push(trait_idx);
+        }
+        Ok(MovementParams {
+            inputs,
+            descend: self.descend,
+            threshold: self.threshold.unwrap_or(movement.default_threshold()),
+        })
+    }
+}
+
+
 pub struct MovementFunction;
 
 impl MovementFunction {
     /// No movement - cells stay in place
     #[inline(always)]
-    pub fn static_movement(_cell_r: usize, _cell_c: usize, _neighborhood_mvt: &Neighborhood, _grid: &Grid) -> (isize, isize) {
+    pub fn static_movement(_cell_r: usize, _cell_c: usize, _neighborhood_mvt: &Neighborhood, _grid: &Grid, _params: &MovementParams, _rng: &mut CellRng) -> (isize, isize) {
         (0, 0)
     }
 
     /// Random walk - move randomly to any valid position in the neighborhood mask
-    pub fn random_movement(_cell_r: usize, _cell_c: usize, neighborhood_mvt: &Neighborhood, _grid: &Grid) -> (isize, isize) {
-        let mut valid_moves = Vec::with_capacity(neighborhood_mvt.height * neighborhood_mvt.width);
-        let center_row = neighborhood_mvt.center_row;
-        let center_col = neighborhood_mvt.center_col;
-        
-        for mask_r in 0..neighborhood_mvt.height {
-            for mask_c in 0..neighborhood_mvt.width {
-                if neighborhood_mvt.is_valid(mask_r, mask_c) == 1 {                    
-                    valid_moves.push((
-                        mask_r as isize - center_row as isize,
-                        mask_c as isize - center_col as isize
-                    ));
-                }
-            }
-        }
+    pub fn random_movement(_cell_r: usize, _cell_c: usize, neighborhood_mvt: &Neighborhood, _grid: &Grid, _params: &MovementParams, rng: &mut CellRng) -> (isize, isize) {
+        let valid_moves = neighborhood_mvt.offsets();
         
         if valid_moves.is_empty() {
             return (0, 0);
         }
         
-        let mut rng = rand::thread_rng();
-        let idx = rng.gen_range(0..valid_moves.len());
-        valid_moves[idx]
+        let offset = valid_moves[rng.gen_range(0..valid_moves.len())];
+        (offset.dr, offset.dc)
     }
 
-    /// Move toward the neighbor with the highest trait value (gradient ascent)
-    /// If multiple neighbors have the same highest value, randomly choose one
-    pub fn gradient(cell_r: usize, cell_c: usize, neighborhood_mvt: &Neighborhood, grid: &Grid) -> (isize, isize) {
-        let current_val = grid.get_cell_trait(cell_r, cell_c, 0);
-        let mut best_val = current_val;
+    /// Move toward the neighbor with the highest value of the `field` input (gradient ascent),
+    /// or the lowest one when `descend` is set. The neighbor must beat the current value by `threshold`.
+    /// If multiple neighbors have the same best value, randomly choose one
+    pub fn gradient(cell_r: usize, cell_c: usize, neighborhood_mvt: &Neighborhood, grid: &Grid, params: &MovementParams, rng: &mut CellRng) -> (isize, isize) {
+        let field = grid.get_trait_slice(params.inputs[0]);
+        // Descending is ascending the opposite field
+        let sign = if params.descend { -1.0 } else { 1.0 };
+        let current_val = sign * field[cell_r * grid.width + cell_c];
+        let mut best_val = current_val + params.threshold;
         let mut best_moves = Vec::new();
-        let center_row = neighborhood_mvt.center_row;
-        let center_col = neighborhood_mvt.center_col;
         
-        for mask_r in 0..neighborhood_mvt.height {
-            for mask_c in 0..neighborhood_mvt.width {
-                if neighborhood_mvt.is_valid(mask_r, mask_c) == 1 &&
-                    !(mask_r == center_row && mask_c == center_col) {
-                    let (grid_r, grid_c) = neighborhood_mvt.get_grid_coords(mask_r, mask_c, cell_r, cell_c, grid);
-                    let neighbor_is_empty = grid.is_cell_empty(grid_r, grid_c);
-                    let neighbor_value = grid.get_cell_trait(grid_r, grid_c, 0);
-                    
-                    if !neighbor_is_empty {
-                        if neighbor_value > best_val {
-                            // Found a new best value - reset the list
-                            best_val = neighbor_value;
-                            best_moves.clear();
-                            best_moves.push((
-                                mask_r as isize - center_row as isize,
-                                mask_c as isize - center_col as isize,
-                            ));
-                        } else if neighbor_value == best_val {
-                            // Found another move with the same best value
-                            best_moves.push((
-                                mask_r as isize - center_row as isize,
-                                mask_c as isize - center_col as isize,
-                            ));
-                        }
-                    }
-                }
+        neighborhood_mvt.for_each_cell(neighborhood_mvt.neighbor_offsets(), cell_r, cell_c, grid, |offset, idx| {
+            if grid.is_empty[idx] {
+                return;
             }
-        }
+            let neighbor_value = sign * field[idx];
+            if neighbor_value > best_val {
+                // Found a new best value - reset the list
+                best_val = neighbor_value;
+                best_moves.clear();
+                best_moves.push((offset.dr, offset.dc));
+            } else if neighbor_value == best_val {
+                // Found another move with the same best value
+                best_moves.push((offset.dr, offset.dc));
+            }
+        });
         
         if best_moves.is_empty() {
             return (0, 0);
         }
         
         // Randomly choose one of the best moves
-        let mut rng = rand::thread_rng();
         let idx = rng.gen_range(0..best_moves.len());
         best_moves[idx]
     }
 
-    /// Move away from high-density areas (gradient descent on density)
-    pub fn avoid_crowding(cell_r: usize, cell_c: usize, neighborhood_mvt: &Neighborhood, grid: &Grid) -> (isize, isize) {
+    /// Move randomly when the mean `density` input of the neighbors is above `threshold`
+    pub fn avoid_crowding(cell_r: usize, cell_c: usize, neighborhood_mvt: &Neighborhood, grid: &Grid, params: &MovementParams, rng: &mut CellRng) -> (isize, isize) {
+        let density = grid.get_trait_slice(params.inputs[0]);
         let mut sum = 0.0;
         let mut count = 0;
-        let center_row = neighborhood_mvt.center_row;
-        let center_col = neighborhood_mvt.center_col;
         
-        for mask_r in 0..neighborhood_mvt.height {
-            for mask_c in 0..neighborhood_mvt.width {
-                if neighborhood_mvt.is_valid(mask_r, mask_c) == 1 &&
-                    !(mask_r == center_row && mask_c == center_col) {
-                    let (grid_r, grid_c) = neighborhood_mvt.get_grid_coords(mask_r, mask_c, cell_r, cell_c, grid);
-                    let neighbor_is_empty = grid.is_cell_empty(grid_r, grid_c);
-                    let neighbor_value = grid.get_cell_trait(grid_r, grid_c, 0);
-                    
-                    if !neighbor_is_empty {
-                        sum += neighbor_value;
-                        count += 1;
-                    }
-                }
+        neighborhood_mvt.for_each_cell(neighborhood_mvt.neighbor_offsets(), cell_r, cell_c, grid, |_, idx| {
+            if !grid.is_empty[idx] {
+                sum += density[idx];
+                count += 1;
             }
-        }
+        });
         
         if count == 0 {
             return (0, 0);
@@ -117,76 +155,50 @@ impl MovementFunction {
         
         let avg_density = sum / count as f32;
         
-        if avg_density > 0.7 {
-            let mut valid_moves = Vec::with_capacity(neighborhood_mvt.height * neighborhood_mvt.width);
-            
-            for mask_r in 0..neighborhood_mvt.height {
-                for mask_c in 0..neighborhood_mvt.width {
-                    if neighborhood_mvt.is_valid(mask_r, mask_c) == 1 {                    
-                        valid_moves.push((
-                            mask_r as isize - center_row as isize,
-                            mask_c as isize - center_col as isize
-                        ));
-                    }
-                }
-            }
-            
-            if valid_moves.is_empty() {
-                return (0, 0);
-            }
-            
-            let mut rng = rand::thread_rng();
-            let idx = rng.gen_range(0..valid_moves.len());
-            valid_moves[idx]
+        if avg_density > params.threshold {
+            Self::random_movement(cell_r, cell_c, neighborhood_mvt, grid, params, rng)
         } else {
             (0, 0)
         }
     }
 
-    pub fn energy_charge_phase(cell_r: usize, cell_c: usize, neighborhood: &Neighborhood, grid: &Grid) -> (isize, isize) {
-        let energy = grid.get_cell_trait(cell_r, cell_c, 0);
-        let phase = grid.get_cell_trait(cell_r, cell_c, 2);
-        let charge = grid.get_cell_trait(cell_r, cell_c, 1);
+    /// Charge-driven attraction gated by the phase, the `threshold` is the minimum energy to move
+    pub fn energy_charge_phase(cell_r: usize, cell_c: usize, neighborhood: &Neighborhood, grid: &Grid, params: &MovementParams, rng: &mut CellRng) -> (isize, isize) {
+        let [energy_idx, charge_idx, phase_idx] = params.inputs[..] else {
+            return (0, 0);
+        };
+        let energy = grid.get_cell_trait(cell_r, cell_c, energy_idx);
+        let phase = grid.get_cell_trait(cell_r, cell_c, phase_idx);
+        let charge = grid.get_cell_trait(cell_r, cell_c, charge_idx);
         
         // Movement gated by phase (creates pulses)
         // AND minimum energy to move
-        if phase < 0.4 || phase > 0.8 || energy < 0.2 {
+        if !(0.4..=0.8).contains(&phase) || energy < params.threshold {
             return (0, 0);
         }
         
-        let center_row = neighborhood.center_row;
-        let center_col = neighborhood.center_col;
+        let energies = grid.get_trait_slice(energy_idx);
+        let charges = grid.get_trait_slice(charge_idx);
         
         let mut move_dr: f32 = 0.0;
         let mut move_dc: f32 = 0.0;
         
-        for mask_r in 0..neighborhood.height {
-            for mask_c in 0..neighborhood.width {
-                if neighborhood.is_valid(mask_r, mask_c) == 1 
-                && !(mask_r == center_row && mask_c == center_col) {
-                    let (grid_r, grid_c) = neighborhood.get_grid_coords(mask_r, mask_c, cell_r, cell_c, grid);
-                    if !grid.is_cell_empty(grid_r, grid_c) {
-                        let neighbor_energy = grid.get_cell_trait(grid_r, grid_c, 0);
-                        let neighbor_charge = grid.get_cell_trait(grid_r, grid_c, 1);
-                        
-                        let dr = mask_r as f32 - center_row as f32;
-                        let dc = mask_c as f32 - center_col as f32;
-                        
-                        // Attract to similar charge with high energy
-                        // Repel from opposite charge
-                        let charge_diff = (charge - neighbor_charge).abs();
-                        let attraction = (0.5 - charge_diff) * neighbor_energy;
-                        
-                        move_dr += dr * attraction;
-                        move_dc += dc * attraction;
-                    }
-                }
+        neighborhood.for_each_cell(neighborhood.neighbor_offsets(), cell_r, cell_c, grid, |offset, idx| {
+            if !grid.is_empty[idx] {
+                // Attract to similar charge with high energy
+                // Repel from opposite charge
+                let charge_diff = (charge - charges[idx]).abs();
+                let attraction = (0.5 - charge_diff) * energies[idx];
+                
+                move_dr += offset.dr as f32 * attraction;
+                move_dc += offset.dc as f32 * attraction;
             }
-        }
-        
-        let mut rng = rand::thread_rng();
+        });
         
-        if move_dr.abs() > 0.05 || move_dc.abs() > 0.05 {
+        if (move_dr.abs() > 0.05 || move_dc.abs() > 0.05) && grid.lattice == Lattice::Hexagonal {
+            // The diagonals of the storage are not steps on a hexagonal lattice
+            grid.lattice.nearest_direction(move_dr, move_dc)
+        } else if move_dr.abs() > 0.05 || move_dc.abs() > 0.05 {
             let dr = if move_dr.abs() < 0.05 { 0 } else { move_dr.signum() as isize };
             let dc = if move_dc.abs() < 0.05 { 0 } else { move_dc.signum() as isize };
             
@@ -198,7 +210,7 @@ impl MovementFunction {
         } else {
             // Weak random drift
             if rng.gen_bool(0.3) {
-                let moves = [(0,1), (0,-1), (1,0), (-1,0)];
+                let moves = grid.lattice.directions();
                 moves[rng.gen_range(0..moves.len())]
             } else {
                 (0, 0)
@@ -210,7 +222,7 @@ impl MovementFunction {
 
 
 macro_rules! define_movements {
-    ($(($variant:ident, $name:expr, $func:path)),* $(,)?) => {
+    ($(($variant:ident, $name:expr, $func:path, $inputs:expr, $threshold:expr)),* $(,)?) => {
         #[derive(Clone, Copy, PartialEq, Eq, Debug)]
         pub enum Movement {
             $($variant),*
@@ -241,43 +253,73 @@ macro_rules! define_movements {
                     $(Movement::$variant => $func),*
                 }
             }
+
+            /// Names of the traits the movement reads
+            #[inline]
+            pub fn inputs(&self) -> &'static [&'static str] {
+                match self {
+                    $(Movement::$variant => $inputs),*
+                }
+            }
+
+            #[inline]
+            pub fn default_threshold(&self) -> f32 {
+                match self {
+                    $(Movement::$variant => $threshold),*
+                }
+            }
         }
     };
 }
 
 // ============================================================
 // ADD NEW MOVEMENTS HERE - Just add one line!
-// Format: (EnumVariant, "display name", MovementFunction::function_name)
+// Format: (EnumVariant, "display name", MovementFunction::function_name, &[input names], default threshold)
 // ============================================================
 define_movements!(
-    (Static,            "static",              MovementFunction::static_movement),
-    (Random,            "random",              MovementFunction::random_movement),
-    (Gradient,          "gradient",            MovementFunction::gradient),
-    (AvoidCrowding,     "avoid crowding",      MovementFunction::avoid_crowding),
-    (EnergyChargePhase, "energy charge phase", MovementFunction::energy_charge_phase),
+    (Static,            "static",              MovementFunction::static_movement,     &[],                             0.0),
+    (Random,            "random",              MovementFunction::random_movement,     &[],                             0.0),
+    (Gradient,          "gradient",            MovementFunction::gradient,            &["field"],                      0.0),
+    (AvoidCrowding,     "avoid crowding",      MovementFunction::avoid_crowding,      &["density"],                    0.7),
+    (EnergyChargePhase, "energy charge phase", MovementFunction::energy_charge_phase, &["energy", "charge", "phase"],  0.2),
     // Add new movements here:
 );
 
-#[derive(Clone, Copy, PartialEq)]
-enum ResolveState {
-    Unvisited,
-    Empty,
-    Visited,
-}
+pub type MovementFnType = fn(usize, usize, &Neighborhood, &Grid, &MovementParams, &mut CellRng) -> (isize, isize);
+
+/// Intention of an individual that moves beyond an absorbing boundary
+const OUTSIDE: usize = usize::MAX;
+/// Reservation of a cell that nobody occupies next step
+const UNRESERVED: usize = usize::MAX;
 
-pub type MovementFnType = fn(usize, usize, &Neighborhood, &Grid) -> (isize, isize);
+// Fate of a mover during the resolution
+const PENDING: u8 = 0;
+const MOVES: u8 = 1;
+const STAYS: u8 = 2;
+
+/// Bids are compared by (random priority, source index), the index breaks ties deterministically
+#[inline(always)]
+fn bid(random: &StepRandom, source_idx: usize) -> (u32, usize) {
+    (random.cell_rng(source_idx, STREAM_BID).next_u32(), source_idx)
+}
 
 pub struct MovementRegistry {
     pub movement_function: MovementFnType,
     movement: Movement,
-    // Stores bids: High 32 bits = Priority, Low 32 bits = Source Index
-    claims: Vec<AtomicU64>,
-    // Stores target (r, c) for every cell. Flattened index = r * width + c
-    intentions: Vec<(u16, u16)>,
-    // DFS helper: Flattened
-    reserved: Vec<Option<(u16, u16)>>,
-    // DFS helper: Flattened
-    states: Vec<ResolveState>,
+    params: MovementParams,
+    // Stores the winning bid of every target: source index + 1 (0 = no bid),
+    // its priority is recomputed from the counter-based generator when needed
+    claims: Vec<AtomicUsize>,
+    // Stores the flattened target (r * width + c) of every cell
+    intentions: Vec<usize>,
+    // Flattened source index of the individual landing in the cell
+    reserved: Vec<usize>,
+    // Resolution helpers: fate of every mover and the cell it currently points to
+    // (its target at first, then further along its chain, see `resolve`)
+    fates: Vec<AtomicU8>,
+    jumps: Vec<AtomicUsize>,
+    // Movers whose fate is still PENDING
+    pending: Vec<usize>,
 }
 
 impl MovementRegistry {
@@ -286,14 +328,17 @@ impl MovementRegistry {
     }
     
     pub fn custom(width: usize, height: usize, movement: Movement) -> Self {
-        let size = width * height;
+        let size = width.checked_mul(height).expect("grid dimensions overflow");
         Self {
             movement_function: movement.get_fn(),
             movement,
-            claims: (0..size).map(|_| AtomicU64::new(0)).collect(),
-            intentions: vec![(0, 0); size],
-            reserved: vec![None; size],
-            states: vec![ResolveState::Unvisited; size],
+            params: MovementParams::defaults(movement),
+            claims: (0..size).map(|_| AtomicUsize::new(0)).collect(),
+            intentions: vec![0; size],
+            reserved: vec![UNRESERVED; size],
+            fates: (0..size).map(|_| AtomicU8::new(PENDING)).collect(),
+            jumps: (0..size).map(|_| AtomicUsize::new(0)).collect(),
+            pending: Vec::new(),
         }
     }
     
@@ -302,20 +347,36 @@ impl MovementRegistry {
         let size = width * height;
         
         if self.claims.len() != size {
-            self.claims = (0..size).map(|_| AtomicU64::new(0)).collect();
-            self.intentions.resize(size, (0, 0));
-            self.reserved.resize(size, None);
-            self.states.resize(size, ResolveState::Unvisited);
+            self.claims = (0..size).map(|_| AtomicUsize::new(0)).collect();
+            self.intentions.resize(size, 0);
+            self.reserved.resize(size, UNRESERVED);
+            self.fates = (0..size).map(|_| AtomicU8::new(PENDING)).collect();
+            self.jumps = (0..size).map(|_| AtomicUsize::new(0)).collect();
         } else {
             self.claims.par_iter().for_each(|x| x.store(0, Ordering::Relaxed));
-            self.reserved.par_iter_mut().for_each(|x| *x = None);
-            self.states.par_iter_mut().for_each(|x| *x = ResolveState::Unvisited);
         }
+        // reserved, fates and jumps are fully rewritten by `resolve`
     }
     
+    /// Also resets the params to the defaults of the movement
     pub fn set_movement(&mut self, movement: Movement) {
         self.movement_function = movement.get_fn();
         self.movement = movement;
+        self.params = MovementParams::defaults(movement);
+    }
+
+    /// `params.inputs` must have one in-range trait index per input of the movement, a wrong count is refused
+    pub fn set_params(&mut self, params: MovementParams) -> Result<(), &'static str> {
+        if params.inputs.len() != self.movement.inputs().len() {
+            return Err("the movement params need one trait per movement input");
+        }
+        self.params = params;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn get_params(&self) -> &MovementParams {
+        &self.params
     }
     
     #[inline]
@@ -356,10 +417,13 @@ impl MovementRegistry {
         Movement::ALL
     }
 
+    /// `random` drives every draw of this step (movement choices and bid priorities),
+    /// each cell gets its own counter-based generator so the result does not depend on the thread count
     pub fn apply_movement(&mut self,
                           neighborhood_mvt: &Neighborhood,
                           next_grid: &mut Grid, // normal Grid
                           grid: &mut Grid, // temp next_grid from previous step (apply rule)
+                          random: StepRandom,
                           ) {
 
         if self.movement == Movement::Static {
@@ -384,10 +448,9 @@ impl MovementRegistry {
             .par_chunks_mut(chunk_len)
             .enumerate()
             .for_each(|(batch_idx, intent_chunk)| {
-                let mut rng = rand::thread_rng();
                 let start_idx = batch_idx * chunk_len;
                 
-                for i in 0..intent_chunk.len() {
+                for (i, intent) in intent_chunk.iter_mut().enumerate() {
                     let global_idx = start_idx + i;
                     if global_idx >= len {
                         break;
@@ -398,26 +461,32 @@ impl MovementRegistry {
                     
                     // Skip empty cells - BitVec: true = empty
                     if next_grid.is_empty[global_idx] { // the is_empty is never changed on the temp grid (here "grid"), the correct values are always in the normal grid (here "next_grid")
+                        // Overwrite the intention of the previous step, which may point past a resized grid
+                        *intent = global_idx;
                         continue;
                     }
                     
                     // Movement logic
-                    let (dr, dc) = (self.movement_function)(r, c, neighborhood_mvt, grid);
-                    let (tr, tc) = (
-                        ((r as isize + dr).clamp(0, height as isize - 1)) as usize,
-                        ((c as isize + dc).clamp(0, width as isize - 1)) as usize,
-                    );
+                    let mut rng = random.cell_rng(global_idx, STREAM_MOVEMENT);
+                    let (dr, dc) = (self.movement_function)(r, c, neighborhood_mvt, grid, &self.params, &mut rng);
+                    let Some((tr, tc)) = grid.get_move_target(r as isize + dr, c as isize + dc) else {
+                        *intent = OUTSIDE;
+                        continue;
+                    };
                     
-                    intent_chunk[i] = (tr as u16, tc as u16);
+                    let target_flat = tr * width + tc;
+                    *intent = target_flat;
                     
-                    if (tr, tc) != (r, c) { // (tr, tc) == (r, c) is not in bid because its managed in step 3 (it always has priority)
-                        let target_flat = tr * width + tc;
-                        let priority: u32 = rng.next_u32();
-                        let bid = ((priority as u64) << 32) | (global_idx as u64);
-                        unsafe {
-                            self.claims
-                                .get_unchecked(target_flat)
-                                .fetch_max(bid, Ordering::Relaxed);
+                    if target_flat != global_idx { // staying is not in bid because its managed in step 3 (it always has priority)
+                        let claim = unsafe { self.claims.get_unchecked(target_flat) };
+                        let my_bid = bid(&random, global_idx);
+                        // Atomic max of the bids, keyed by the source index
+                        let mut current = claim.load(Ordering::Relaxed);
+                        while current == 0 || my_bid > bid(&random, current - 1) {
+                            match claim.compare_exchange_weak(current, global_idx + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                                Ok(_) => break,
+                                Err(actual) => current = actual,
+                            }
                         }
                     }
                 }
@@ -431,77 +500,37 @@ impl MovementRegistry {
             .for_each(|(chunk_id, chunk)| {
                 let base_idx = chunk_id * prune_chunk_size;
 
-                for i in 0..chunk.len() {
+                for (i, intent) in chunk.iter_mut().enumerate() {
                     let global_idx = base_idx + i;
-                    let (tr, tc) = chunk[i];
-                    let r = global_idx / width;
-                    let c = global_idx % width;
+                    let target_flat = *intent;
 
-                    if (tr as usize, tc as usize) == (r, c) {
+                    if target_flat == global_idx || target_flat == OUTSIDE {
                         continue;
                     }
 
-                    let target_flat = tr as usize * width + tc as usize;
-                    let winning_bid = unsafe {
+                    let winner = unsafe {
                         self.claims
                             .get_unchecked(target_flat)
                             .load(Ordering::Relaxed)
                     };
-                    let winner_idx = (winning_bid & 0xFFFFFFFF) as usize;
 
-                    if winner_idx != global_idx {
-                        chunk[i] = (r as u16, c as u16);
+                    if winner != global_idx + 1 {
+                        *intent = global_idx;
                     }
                 }
             });
 
-        // --- Phase 3: Resolve (DFS) ---
-        for r in 0..height {
-            for c in 0..width {
-                let idx = r * width + c;
-
-                // We don't want to visit empty cells
-                if next_grid.is_empty[idx] {
-                    self.states[idx] = ResolveState::Empty;
-                    continue;
-                }
-
-                let (tr_u16, tc_u16) = self.intentions[idx];
-                let tr = tr_u16 as usize;
-                let tc = tc_u16 as usize;
-                let target_idx = tr * width + tc;
-
-                //print!("({},{}), ", tr, tc);
-
-                // cells that stays have the priority
-                if target_idx == idx {
-                    self.reserved[idx] = Some((r as u16, c as u16));
-                    self.states[idx] = ResolveState::Visited;
-                }
-            }
-            //println!("");
-        }
-        //println!("");
-
-        for r in 0..height {
-            for c in 0..width {
-                let idx = r * width + c;
-                if self.states[idx] == ResolveState::Unvisited {
-                    self.resolve_move(r, c, width);
-                }
-            }
-        }
+        // --- Phase 3: Resolve ---
+        self.resolve(next_grid);
 
         // --- Phase 4: Construct next grid ---
         next_grid.is_empty
             .par_iter_mut()
             .zip(self.reserved.par_iter())
-            .for_each(|(empty, reserved)| {
-                *empty = reserved.is_none();
+            .for_each(|(empty, &reserved)| {
+                *empty = reserved == UNRESERVED;
             });
 
-        let width = grid.width;
-
         for trait_idx in 0..grid.num_traits {
             let current = grid.get_trait_slice(trait_idx);
             let out_trait = next_grid.get_trait_slice_mut(trait_idx);
@@ -511,69 +540,94 @@ impl MovementRegistry {
                 .enumerate()
                 .for_each(|(idx, out_trait_val)| {
                     *out_trait_val = match self.reserved[idx] {
-                        Some((sr, sc)) => {
-                            let src_idx = sr as usize * width + sc as usize;
-                            current[src_idx]
-                        }
-                        None => 0.0,
+                        UNRESERVED => 0.0,
+                        src_idx => current[src_idx],
                     };
                 });
         }
     }
 
 
-    pub fn resolve_move(&mut self, r: usize, c: usize, w: usize) {
-        let idx = r * w + c;
-
-        // loop 
-        // (the only possible loop is a circle (end is start, start is end)
-        // (because a cell has a maximum of 1 individual (that is not already in the cell) that want this cell)
-        if self.states[idx] == ResolveState::Visited {
-            return
-        }
-
-        self.states[idx] = ResolveState::Visited;
-
-        let (tr_u16, tc_u16) = self.intentions[idx];
-        let tr = tr_u16 as usize;
-        let tc = tc_u16 as usize;
-        let target_idx = tr * w + tc;
-
-        //println!("intention ({}, {}) -> ({}, {})", r, c, tr, tc);
-
-        if self.reserved[target_idx].is_some() {
-            // we can't go because its already reserved so we stay in place
-            // (because of the tie breaker of phase 2, the target is reserved only if the individual inside it does not move,
-            // ties in resolve_move are only between an individual that want to move and an individual that want to stay)
-            self.reserved[idx] = Some((r as u16, c as u16));
-            //println!("reserved ({}, {}) -> ({}, {})", r, c, r, c);
-            return;
+    /// Fills `reserved` from the pruned intentions and the winning claims.
+    ///
+    /// After pruning every cell is the target of at most one mover, so the movers form disjoint
+    /// chains and cycles. A whole chain moves if its last mover targets an empty cell or a cell
+    /// left by an individual moving outside, and stays if it targets an individual that stays.
+    /// Cycles rotate. The fates are found by pointer jumping: every round, each pending mover
+    /// takes the fate of the cell it points to or jumps to where that cell points, which halves
+    /// the distance to the end of its chain. A round that settles nothing leaves only cycles.
+    /// Fates do not depend on the evaluation order, so the rounds run in parallel.
+    fn resolve(&mut self, next_grid: &Grid) {
+        let len = self.intentions.len();
+        let intentions = &self.intentions;
+        let is_empty = &next_grid.is_empty;
+        let fates = &self.fates;
+        let jumps = &self.jumps;
+
+        let is_mover = |idx: usize| {
+            !is_empty[idx] && intentions[idx] != idx && intentions[idx] != OUTSIDE
+        };
+
+        // Movers next to the end of their chain are settled right away
+        self.pending = (0..len)
+            .into_par_iter()
+            .filter(|&idx| {
+                let target = intentions[idx];
+                let fate = if !is_mover(idx) {
+                    STAYS // unused, only movers are looked up
+                } else if is_empty[target] || intentions[target] == OUTSIDE {
+                    MOVES
+                } else if intentions[target] == target {
+                    STAYS
+                } else {
+                    jumps[idx].store(target, Ordering::Relaxed);
+                    PENDING
+                };
+                fates[idx].store(fate, Ordering::Relaxed);
+                fate == PENDING
+            })
+            .collect();
+
+        // Pointer jumping (reading values already updated in this round only shortens the chains)
+        loop {
+            let before = self.pending.len();
+            self.pending.par_iter().for_each(|&idx| {
+                let next = jumps[idx].load(Ordering::Relaxed);
+                match fates[next].load(Ordering::Relaxed) {
+                    PENDING => jumps[idx].store(jumps[next].load(Ordering::Relaxed), Ordering::Relaxed),
+                    fate => fates[idx].store(fate, Ordering::Relaxed),
+                }
+            });
+            self.pending.retain(|&idx| fates[idx].load(Ordering::Relaxed) == PENDING);
+            if self.pending.len() == before {
+                break;
+            }
         }
 
-        if self.states[target_idx] == ResolveState::Empty {
-            // we can go
-            self.reserved[target_idx] = Some((r as u16, c as u16));
-            //println!("empty ({}, {}) -> ({}, {})", r, c, tr, tc);
-            return;
+        // Only cycles are left
+        for &idx in &self.pending {
+            fates[idx].store(MOVES, Ordering::Relaxed);
         }
 
-        // the target cell has an individual inside it
-        // so we need to check if the individual will move or not
-
-        // propagate
-        self.resolve_move(tr, tc, w); 
-        // (tr, tc) != (r, c) because cells that want to stay as their first wish because resolve_move
-        // are set to Visited earlier in step 3
-
-        if self.reserved[target_idx].is_some() {
-            // we can't go
-            self.reserved[idx] = Some((r as u16, c as u16));
-            //println!("staying ({}, {}) -> ({}, {})", r, c, r, c);
-        } else {
-            // we can go
-            self.reserved[target_idx] = Some((r as u16, c as u16));
-            //println!("going to target ({}, {}) -> ({}, {})", r, c, tr, tc);
-        }
+        // The occupant keeps its cell if it does not move, otherwise the winning mover takes it
+        let claims = &self.claims;
+        self.reserved
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(idx, reserved)| {
+                let occupant_stays = !is_empty[idx]
+                    && (intentions[idx] == idx
+                        || (intentions[idx] != OUTSIDE && fates[idx].load(Ordering::Relaxed) == STAYS));
+                *reserved = if occupant_stays {
+                    idx
+                } else {
+                    match claims[idx].load(Ordering::Relaxed) {
+                        0 => UNRESERVED,
+                        winner if fates[winner - 1].load(Ordering::Relaxed) == MOVES => winner - 1,
+                        _ => UNRESERVED,
+                    }
+                };
+            });
     }
 }
 
@@ -584,11 +638,11 @@ impl MovementRegistry {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::grid::Grid;
+    use crate::grid::{Boundary, Grid};
     use crate::neighborhood::Neighborhood;
 
     fn build_test_grid() -> Grid {
-        let mut grid = Grid::new(3, 3);
+        let mut grid = Grid::new(3, 3, 1);
         for r in 0..3 {
             for c in 0..3 {
                 for index in 0..grid.num_traits {
@@ -603,13 +657,13 @@ mod tests {
     fn test_static_movement() {
         let grid = build_test_grid();
         let mask = vec![
-            vec![true, true, true],
-            vec![true, true, true],
-            vec![true, true, true],
+            vec![1, 1, 1],
+            vec![1, 1, 1],
+            vec![1, 1, 1],
         ];
         let neighborhood_mvt = Neighborhood::new(3, 3, 1, 1, mask);
-        let cell = &grid.cells[1][1];
-        let mv = MovementFunction::static_movement(cell, &neighborhood_mvt, &grid);
+        let mut rng = StepRandom::new(0, 0).cell_rng(4, STREAM_MOVEMENT);
+        let mv = MovementFunction::static_movement(1, 1, &neighborhood_mvt, &grid, &MovementParams::defaults(Movement::Static), &mut rng);
         assert_eq!(mv, (0, 0), "Static movement should not move");
     }
 
@@ -617,17 +671,17 @@ mod tests {
     fn test_random_movement_with_full_mask() {
         let grid = build_test_grid();
         let mask = vec![
-            vec![true, true, true],
-            vec![true, true, true],
-            vec![true, true, true],
+            vec![1, 1, 1],
+            vec![1, 1, 1],
+            vec![1, 1, 1],
         ];
         let neighborhood_mvt = Neighborhood::new(3, 3, 1, 1, mask);
-        let cell = &grid.cells[1][1];
+        let mut rng = StepRandom::new(0, 0).cell_rng(4, STREAM_MOVEMENT);
         for _ in 0..10 {
-            let mv = MovementFunction::random_movement(cell, &neighborhood_mvt, &grid);
+            let mv = MovementFunction::random_movement(1, 1, &neighborhood_mvt, &grid, &MovementParams::defaults(Movement::Random), &mut rng);
             let dr = mv.0 + neighborhood_mvt.center_row as isize;
             let dc = mv.1 + neighborhood_mvt.center_col as isize;
-            assert!(dr >= 0 && dr < 3 && dc >= 0 && dc < 3, "Random movement must stay within neighborhood_mvt");
+            assert!((0..3).contains(&dr) && (0..3).contains(&dc), "Random movement must stay within neighborhood_mvt");
         }
     }
 
@@ -637,46 +691,265 @@ mod tests {
         // Set a neighbor with higher trait
         grid.set_cell_trait(0, 1, 0, 0.9);
         let mask = vec![
-            vec![true, true, true],
-            vec![true, true, true],
-            vec![true, true, true],
+            vec![1, 1, 1],
+            vec![1, 1, 1],
+            vec![1, 1, 1],
         ];
         let neighborhood_mvt = Neighborhood::new(3, 3, 1, 1, mask);
-        let cell = &grid.cells[1][1];
-        let mv = MovementFunction::gradient(cell, &neighborhood_mvt, &grid);
+        let mut rng = StepRandom::new(0, 0).cell_rng(4, STREAM_MOVEMENT);
+        let mv = MovementFunction::gradient(1, 1, &neighborhood_mvt, &grid, &MovementParams::defaults(Movement::Gradient), &mut rng);
         assert_eq!(mv, (-1, 0), "Gradient should move toward highest trait neighbor");
     }
 
+    #[test]
+    fn test_gradient_on_named_trait() {
+        let mut grid = Grid::new(3, 3, 2);
+        for r in 0..3 {
+            for c in 0..3 {
+                grid.set_cell_trait(r, c, 0, 0.5);
+                grid.set_cell_trait(r, c, 1, 0.5);
+            }
+        }
+        grid.set_cell_trait(0, 1, 0, 0.9);
+        grid.set_cell_trait(2, 1, 1, 0.1);
+        let traits = [TraitInfo::new("Noise"), TraitInfo::new("Resource")];
+        let mask = vec![vec![1; 3]; 3];
+        let neighborhood_mvt = Neighborhood::new(3, 3, 1, 1, mask);
+        let mut rng = StepRandom::new(0, 0).cell_rng(4, STREAM_MOVEMENT);
+
+        let mut config: MovementConfig = toml::from_str(r#"
+            inputs = { field = "Resource" }
+            descend = true
+        "#).unwrap();
+        let params = config.resolve(Movement::Gradient, &traits).unwrap();
+        assert_eq!(params.inputs, vec![1]);
+        let mv = MovementFunction::gradient(1, 1, &neighborhood_mvt, &grid, &params, &mut rng);
+        assert_eq!(mv, (1, 0), "Descending Resource should ignore the peak of Noise");
+
+        // The only lower neighbor is not low enough
+        config.threshold = Some(0.5);
+        let params = config.resolve(Movement::Gradient, &traits).unwrap();
+        let mv = MovementFunction::gradient(1, 1, &neighborhood_mvt, &grid, &params, &mut rng);
+        assert_eq!(mv, (0, 0));
+    }
+
+    #[test]
+    fn test_movement_config_resolve() {
+        let traits = [TraitInfo::new("Energy"), TraitInfo::new("Charge")];
+        let default = MovementConfig::default();
+        assert_eq!(default.resolve(Movement::Gradient, &traits), Ok(MovementParams::defaults(Movement::Gradient)));
+        assert_eq!(default.resolve(Movement::AvoidCrowding, &traits).unwrap().threshold, 0.7);
+        // Three inputs but two traits
+        assert!(default.resolve(Movement::EnergyChargePhase, &traits).is_err());
+
+        let mut config = MovementConfig::default();
+        config.inputs.insert("phase".to_string(), "Charge".to_string());
+        assert_eq!(config.resolve(Movement::EnergyChargePhase, &traits).unwrap().inputs, vec![0, 1, 1]);
+        assert!(config.resolve(Movement::Gradient, &traits).is_err());
+        config.inputs.insert("phase".to_string(), "Spin".to_string());
+        assert!(config.resolve(Movement::EnergyChargePhase, &traits).is_err());
+
+        // The registry refuses params resolved for another movement
+        let mut registry = MovementRegistry::custom(3, 3, Movement::Gradient);
+        let params = default.resolve(Movement::EnergyChargePhase, &[TraitInfo::new("A"), TraitInfo::new("B"), TraitInfo::new("C")]).unwrap();
+        assert!(registry.set_params(params).is_err());
+        assert_eq!(registry.get_params(), &MovementParams::defaults(Movement::Gradient));
+    }
+
     #[test]
     fn test_avoid_crowding_stays_put_if_low_density() {
         let grid = build_test_grid();
         let mask = vec![
-            vec![true, true, true],
-            vec![true, true, true],
-            vec![true, true, true],
+            vec![1, 1, 1],
+            vec![1, 1, 1],
+            vec![1, 1, 1],
         ];
         let neighborhood_mvt = Neighborhood::new(3, 3, 1, 1, mask);
-        let cell = &grid.cells[1][1];
-        let mv = MovementFunction::avoid_crowding(cell, &neighborhood_mvt, &grid);
+        let mut rng = StepRandom::new(0, 0).cell_rng(4, STREAM_MOVEMENT);
+        let mv = MovementFunction::avoid_crowding(1, 1, &neighborhood_mvt, &grid, &MovementParams::defaults(Movement::AvoidCrowding), &mut rng);
         assert_eq!(mv, (0, 0), "Avoid crowding should stay put if density is low");
     }
 
+    #[test]
+    fn test_absorbing_boundary_removes_leaving_cells() {
+        let mut grid = build_test_grid();
+        grid.set_boundaries(Boundary::Absorbing, Boundary::Absorbing);
+        let mut next_grid = grid.clone();
+        // Only moves up are allowed, so the top row leaves the grid and the others follow
+        let mask = vec![
+            vec![0, 1, 0],
+            vec![0, 0, 0],
+            vec![0, 0, 0],
+        ];
+        let neighborhood_mvt = Neighborhood::new(3, 3, 1, 1, mask);
+        let mut registry = MovementRegistry::custom(3, 3, Movement::Random);
+        registry.apply_movement(&neighborhood_mvt, &mut grid, &mut next_grid, StepRandom::new(0, 0));
+        assert_eq!(grid.count_filled_cells(), 6);
+        for c in 0..3 {
+            assert!(grid.is_cell_empty(2, c));
+        }
+    }
+
+    #[test]
+    fn test_fixed_boundary_blocks_leaving_cells() {
+        let mut grid = build_test_grid();
+        grid.set_boundaries(Boundary::Fixed, Boundary::Fixed);
+        let mut next_grid = grid.clone();
+        let mask = vec![
+            vec![0, 1, 0],
+            vec![0, 0, 0],
+            vec![0, 0, 0],
+        ];
+        let neighborhood_mvt = Neighborhood::new(3, 3, 1, 1, mask);
+        let mut registry = MovementRegistry::custom(3, 3, Movement::Random);
+        registry.apply_movement(&neighborhood_mvt, &mut grid, &mut next_grid, StepRandom::new(0, 0));
+        assert_eq!(grid.count_filled_cells(), 9);
+    }
+
+    #[test]
+    fn test_energy_charge_phase_on_hexagonal_lattice() {
+        let mut grid = Grid::new(5, 5, 3);
+        grid.is_empty.fill(true);
+        for (r, c) in [(2, 2), (3, 3)] {
+            grid.is_empty[r * 5 + c] = false;
+            grid.set_cell_trait(r, c, 0, 1.0);
+            grid.set_cell_trait(r, c, 1, 0.5);
+            grid.set_cell_trait(r, c, 2, 0.5);
+        }
+        grid.lattice = Lattice::Hexagonal;
+        let neighborhood = Neighborhood::moore(1);
+        let params = MovementParams::defaults(Movement::EnergyChargePhase);
+        let moves = |grid: &Grid| -> Vec<(isize, isize)> {
+            (0..200)
+                .map(|seed| {
+                    let mut rng = StepRandom::new(seed, 0).cell_rng(12, STREAM_MOVEMENT);
+                    MovementFunction::energy_charge_phase(2, 2, &neighborhood, grid, &params, &mut rng)
+                })
+                .collect()
+        };
+
+        // Pulled along a diagonal of the storage, which is two steps away on the lattice
+        assert!(moves(&grid).iter().all(|mv| [(0, 1), (1, 0)].contains(mv)));
+        // Without any pull the drift takes the six directions
+        grid.is_empty[18] = true;
+        let drift: std::collections::HashSet<_> = moves(&grid).into_iter().collect();
+        assert_eq!(drift.len(), 7);
+        assert!(drift.iter().all(|mv| *mv == (0, 0) || Lattice::Hexagonal.directions().contains(mv)));
+    }
+
+    #[test]
+    fn test_movement_on_grid_wider_than_u16() {
+        use rand::SeedableRng;
+        let width = 70_000;
+        let mut grid = Grid::new_with_density(width, 2, 0.5, 1, &[(0.0, 1.0)], &mut rand::rngs::StdRng::seed_from_u64(3));
+        let mut next_grid = grid.clone();
+        let filled = grid.count_filled_cells();
+        let ids: Vec<usize> = (0..grid.num_cells).filter(|&idx| !grid.is_empty[idx]).collect();
+        // Label every individual with its starting index to follow it
+        for &idx in &ids {
+            next_grid.data[idx] = idx as f32;
+        }
+        let mask = vec![vec![1; 3]; 3];
+        let neighborhood_mvt = Neighborhood::new(3, 3, 1, 1, mask);
+        let mut registry = MovementRegistry::custom(width, 2, Movement::Random);
+        registry.apply_movement(&neighborhood_mvt, &mut grid, &mut next_grid, StepRandom::new(0, 0));
+
+        assert_eq!(grid.count_filled_cells(), filled);
+        let mut labels: Vec<usize> = (0..grid.num_cells)
+            .filter(|&idx| !grid.is_empty[idx])
+            .map(|idx| grid.data[idx] as usize)
+            .collect();
+        labels.sort_unstable();
+        assert_eq!(labels, ids);
+    }
+
+    /// Resolves hand-written (already pruned) intentions on a single row
+    fn resolve_row(filled: &[bool], intentions: &[usize]) -> Vec<usize> {
+        let len = filled.len();
+        let mut grid = Grid::new(len, 1, 1);
+        let mut registry = MovementRegistry::custom(len, 1, Movement::Random);
+        for idx in 0..len {
+            grid.is_empty[idx] = !filled[idx];
+            registry.intentions[idx] = intentions[idx];
+            if filled[idx] && intentions[idx] != idx && intentions[idx] != OUTSIDE {
+                registry.claims[intentions[idx]].store(idx + 1, Ordering::Relaxed);
+            }
+        }
+        registry.resolve(&grid);
+        registry.reserved
+    }
+
+    #[test]
+    fn test_resolve_chains_and_cycles() {
+        const U: usize = UNRESERVED;
+        // Chain ending on an empty cell
+        assert_eq!(resolve_row(&[true, true, false], &[1, 2, 2]), vec![U, 0, 1]);
+        // Chain ending on an individual that stays
+        assert_eq!(resolve_row(&[true, true, true], &[1, 2, 2]), vec![0, 1, 2]);
+        // Chain ending on an individual leaving the grid
+        assert_eq!(resolve_row(&[true, true, true], &[1, 2, OUTSIDE]), vec![U, 0, 1]);
+        // Cycle next to a blocked chain
+        assert_eq!(
+            resolve_row(&[true, true, true, true, true], &[1, 2, 0, 4, 4]),
+            vec![2, 0, 1, 3, 4]
+        );
+    }
+
+    #[test]
+    fn test_resolve_long_chain() {
+        // Far deeper than the stack allows for a recursive resolution
+        let len = 200_000;
+        let filled: Vec<bool> = (0..len).map(|idx| idx + 1 < len).collect();
+        let intentions: Vec<usize> = (0..len).map(|idx| (idx + 1).min(len - 1)).collect();
+        let reserved = resolve_row(&filled, &intentions);
+        assert_eq!(reserved[0], UNRESERVED);
+        assert!((1..len).all(|idx| reserved[idx] == idx - 1));
+
+        // Same chain closed into a cycle
+        let filled = vec![true; len];
+        let intentions: Vec<usize> = (0..len).map(|idx| (idx + 1) % len).collect();
+        let reserved = resolve_row(&filled, &intentions);
+        assert_eq!(reserved[0], len - 1);
+        assert!((1..len).all(|idx| reserved[idx] == idx - 1));
+    }
+
     #[test]
     fn test_movement_registry_static() {
-        let grid = build_test_grid();
+        let mut grid = build_test_grid();
+        let mut next_grid = grid.clone();
         let mask = vec![
-            vec![true, true, true],
-            vec![true, true, true],
-            vec![true, true, true],
+            vec![1, 1, 1],
+            vec![1, 1, 1],
+            vec![1, 1, 1],
         ];
         let neighborhood_mvt = Neighborhood::new(3, 3, 1, 1, mask);
-        let registry = MovementRegistry::default();
-        let new_cells = registry.apply_movement(&neighborhood_mvt, &grid);
+        let mut registry = MovementRegistry::new(3, 3);
+        registry.apply_movement(&neighborhood_mvt, &mut grid, &mut next_grid, StepRandom::new(0, 0));
         // All cells should remain in place
         for r in 0..3 {
             for c in 0..3 {
-                assert_eq!(new_cells[r][c].position, (r, c));
+                assert!(!grid.is_cell_empty(r, c));
+                assert_eq!(grid.get_cell_trait(r, c, 0), 0.5);
             }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_movement_registry_after_shrinking_the_grid() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let neighborhood_mvt = Neighborhood::moore(1);
+        let mut registry = MovementRegistry::custom(20, 20, Movement::Random);
+        let mut grid = Grid::new_with_density(20, 20, 0.5, 1, &[(0.0, 1.0)], &mut rng);
+        let mut next_grid = grid.clone();
+        registry.apply_movement(&neighborhood_mvt, &mut grid, &mut next_grid, StepRandom::new(0, 0));
+
+        // The empty cells of the smaller grid must not reuse the intentions of the larger one
+        let mut grid = Grid::new_with_density(4, 4, 0.5, 1, &[(0.0, 1.0)], &mut rng);
+        let mut next_grid = grid.clone();
+        let filled = grid.count_filled_cells();
+        for step in 1..4 {
+            registry.apply_movement(&neighborhood_mvt, &mut grid, &mut next_grid, StepRandom::new(0, step));
+            assert_eq!(grid.count_filled_cells(), filled);
+        }
+    }
+}
@@ -1,16 +1,260 @@
-use crate::grid::Grid;
+use crate::grid::{Grid, Lattice};
+use serde::Deserialize;
 
 
+/// Weight given to each offset of a neighborhood mask, `{ kind = "gaussian", sigma = 1.5 }` in the configs
+#[derive(Clone, PartialEq, Debug, Default, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Kernel {
+    /// Every offset of the mask weighs 1
+    #[default]
+    Uniform,
+    /// exp(-d² / 2σ²), d being the distance to the center
+    Gaussian { sigma: f32 },
+    /// Gaussian shell of the given radius and width, exp(-((d - radius) / width)² / 2)
+    Ring { radius: f32, width: f32 },
+    /// 1 / (1 + d), as the weighted average rule
+    InverseDistance,
+    /// One weight per cell of the mask, rows of the same size as the mask
+    Custom { weights: Vec<Vec<f32>> },
+}
+
+impl Kernel {
+    pub const NAMES: &'static [&'static str] = &["uniform", "gaussian", "ring", "inverse_distance", "custom"];
+
+    #[inline]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kernel::Uniform => "uniform",
+            Kernel::Gaussian { .. } => "gaussian",
+            Kernel::Ring { .. } => "ring",
+            Kernel::InverseDistance => "inverse_distance",
+            Kernel::Custom { .. } => "custom",
+        }
+    }
+
+    /// Numbers describing the kernel (the weights row by row for a custom one)
+    pub fn params(&self) -> Vec<f32> {
+        match self {
+            Kernel::Uniform | Kernel::InverseDistance => Vec::new(),
+            Kernel::Gaussian { sigma } => vec![*sigma],
+            Kernel::Ring { radius, width } => vec![*radius, *width],
+            Kernel::Custom { weights } => weights.concat(),
+        }
+    }
+
+    /// Inverse of `name` and `params`, custom weights are split in rows of `width`
+    pub fn from_params(name: &str, params: &[f32], width: usize) -> Option<Kernel> {
+        match (name, params) {
+            ("uniform", []) => Some(Kernel::Uniform),
+            ("gaussian", &[sigma]) => Some(Kernel::Gaussian { sigma }),
+            ("ring", &[radius, width]) => Some(Kernel::Ring { radius, width }),
+            ("inverse_distance", []) => Some(Kernel::InverseDistance),
+            ("custom", weights) if width > 0 && weights.len() % width == 0 => Some(Kernel::Custom {
+                weights: weights.chunks(width).map(<[f32]>::to_vec).collect(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        match self {
+            Kernel::Gaussian { sigma } if sigma.is_nan() || *sigma <= 0.0 => Err("gaussian kernel sigma must be > 0"),
+            Kernel::Ring { radius, width } if width.is_nan() || *width <= 0.0 || radius.is_nan() || *radius < 0.0 => {
+                Err("ring kernel needs radius >= 0 and width > 0")
+            }
+            Kernel::Custom { weights } if weights.iter().flatten().any(|w| !w.is_finite()) => {
+                Err("custom kernel weights must be finite")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    #[inline]
+    fn weight(&self, mask_r: usize, mask_c: usize, distance: f32) -> f32 {
+        match self {
+            Kernel::Uniform => 1.0,
+            Kernel::Gaussian { sigma } => (-distance * distance / (2.0 * sigma * sigma)).exp(),
+            Kernel::Ring { radius, width } => {
+                let x = (distance - radius) / width;
+                (-0.5 * x * x).exp()
+            }
+            Kernel::InverseDistance => 1.0 / (1.0 + distance),
+            Kernel::Custom { weights } => weights[mask_r][mask_c],
+        }
+    }
+}
+
+
+/// Named neighborhood, `{ kind = "von_neumann", radius = 2 }` in the configs. The presets span
+/// 2 * radius + 1 cells on each axis around the center, which they include
+#[derive(Clone, PartialEq, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Shape {
+    /// The square, max(|dr|, |dc|) <= radius
+    Moore { radius: usize },
+    /// The diamond, |dr| + |dc| <= radius
+    VonNeumann { radius: usize },
+    /// The disc of radius + 1/2, dr² + dc² <= radius² + radius
+    Circular { radius: usize },
+    /// Hexagonal lattice in axial coordinates, max(|dr|, |dc|, |dr + dc|) <= radius:
+    /// six neighbors at radius 1, the top-left and bottom-right corners being left out
+    Hexagonal { radius: usize },
+    /// The center row and column, dr = 0 or dc = 0
+    Cross { radius: usize },
+    /// Any mask, with the [row, col] of its center
+    Custom { mask: Vec<Vec<u8>>, center: [usize; 2] },
+}
+
+impl Shape {
+    pub const NAMES: &'static [&'static str] = &["moore", "von_neumann", "circular", "hexagonal", "cross", "custom"];
+
+    #[inline]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Shape::Moore { .. } => "moore",
+            Shape::VonNeumann { .. } => "von_neumann",
+            Shape::Circular { .. } => "circular",
+            Shape::Hexagonal { .. } => "hexagonal",
+            Shape::Cross { .. } => "cross",
+            Shape::Custom { .. } => "custom",
+        }
+    }
+
+    /// Preset of the given name and radius (None for custom)
+    pub fn preset(name: &str, radius: usize) -> Option<Shape> {
+        match name {
+            "moore" => Some(Shape::Moore { radius }),
+            "von_neumann" => Some(Shape::VonNeumann { radius }),
+            "circular" => Some(Shape::Circular { radius }),
+            "hexagonal" => Some(Shape::Hexagonal { radius }),
+            "cross" => Some(Shape::Cross { radius }),
+            _ => None,
+        }
+    }
+
+    /// Radius of a preset
+    #[inline]
+    pub fn radius(&self) -> Option<usize> {
+        match *self {
+            Shape::Moore { radius }
+            | Shape::VonNeumann { radius }
+            | Shape::Circular { radius }
+            | Shape::Hexagonal { radius }
+            | Shape::Cross { radius } => Some(radius),
+            Shape::Custom { .. } => None,
+        }
+    }
+
+    /// The preset the neighborhood's mask and center match, custom otherwise
+    pub fn of(neighborhood: &Neighborhood) -> Shape {
+        let radius = neighborhood.width / 2;
+        let centered = neighborhood.width == neighborhood.height
+            && neighborhood.width % 2 == 1
+            && (neighborhood.center_row, neighborhood.center_col) == (radius, radius);
+        Shape::NAMES
+            .iter()
+            .filter_map(|name| Shape::preset(name, radius))
+            .find(|preset| centered && preset.mask().as_ref() == Some(&neighborhood.mask))
+            .unwrap_or_else(|| Shape::Custom {
+                mask: neighborhood.mask.clone(),
+                center: [neighborhood.center_row, neighborhood.center_col],
+            })
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        match self {
+            Shape::Custom { mask, center } => {
+                Neighborhood::check_mask(mask)?;
+                if center[0] >= mask.len() || center[1] >= mask[0].len() {
+                    return Err("neighborhood center must be inside the mask");
+                }
+                Ok(())
+            }
+            _ if self.radius() == Some(0) => Err("neighborhood radius must be > 0"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Offset (dr, dc) belongs to a preset
+    #[inline]
+    fn contains(&self, dr: isize, dc: isize) -> bool {
+        let radius = self.radius().unwrap_or(0) as isize;
+        match self {
+            Shape::Moore { .. } => dr.abs().max(dc.abs()) <= radius,
+            Shape::VonNeumann { .. } => dr.abs() + dc.abs() <= radius,
+            Shape::Circular { .. } => dr * dr + dc * dc <= radius * radius + radius,
+            Shape::Hexagonal { .. } => dr.abs().max(dc.abs()).max((dr + dc).abs()) <= radius,
+            Shape::Cross { .. } => (dr == 0 || dc == 0) && dr.abs().max(dc.abs()) <= radius,
+            Shape::Custom { .. } => false,
+        }
+    }
+
+    /// Mask of a preset, centered
+    fn mask(&self) -> Option<Vec<Vec<u8>>> {
+        let radius = self.radius()? as isize;
+        Some(
+            (-radius..=radius)
+                .map(|dr| (-radius..=radius).map(|dc| self.contains(dr, dc) as u8).collect())
+                .collect(),
+        )
+    }
+}
+
+
+/// Cell of a neighborhood mask, precompiled relative to the center
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Offset {
+    pub dr: isize,
+    pub dc: isize,
+    /// dr * width + dc for the grid width the neighborhood is compiled for
+    pub flat: isize,
+    /// Kernel weight
+    pub weight: f32,
+    /// 1 / (1 + distance to the center)
+    pub distance_weight: f32,
+}
+
+
+/// Cells read around a cell: a mask with its center, and the weight of each offset.
+/// The cells of the mask are precompiled into offsets, which the rules and movements iterate
+/// with `for_each_cell` (flat indices away from the borders, boundary lookups near them)
 #[derive(Clone, Debug)]
 pub struct Neighborhood {
     pub width: usize,
     pub height: usize,
     pub center_row: usize,
     pub center_col: usize,
+    /// Offsets of the neighborhood, change them with `set_cell` so that the weights follow
     pub mask: Vec<Vec<u8>>,
+    kernel: Kernel,
+    /// Kernel weight of each cell of the mask, row-major, 0 outside of the mask
+    weights: Vec<f32>,
+    /// 1 / (1 + distance) of each cell of the mask, row-major, 0 outside of the mask
+    distance_weights: Vec<f32>,
+    /// Cells of the mask, row-major
+    offsets: Vec<Offset>,
+    /// The same without the center
+    neighbor_offsets: Vec<Offset>,
+    /// Extent of the mask above, below, left and right of the center
+    reach: [usize; 4],
+    /// Grid width of the flat offsets
+    grid_width: usize,
+    /// Lattice of the distances to the center
+    lattice: Lattice,
+}
+
+impl PartialEq for Neighborhood {
+    /// Same mask, center and kernel, whatever the grid the offsets are compiled for
+    fn eq(&self, other: &Self) -> bool {
+        (self.width, self.height, self.center_row, self.center_col) == (other.width, other.height, other.center_row, other.center_col)
+            && self.mask == other.mask
+            && self.kernel == other.kernel
+    }
 }
 
 impl Neighborhood {
+    /// Neighborhood with a uniform kernel
     pub fn new(
         width: usize,
         height: usize,
@@ -18,17 +262,254 @@ impl Neighborhood {
         center_col: usize,
         mask: Vec<Vec<u8>>,
     ) -> Self {
-        Self {
+        let mut neighborhood = Self {
             width,
             height,
             center_row,
             center_col,
             mask,
+            kernel: Kernel::Uniform,
+            weights: Vec::new(),
+            distance_weights: Vec::new(),
+            offsets: Vec::new(),
+            neighbor_offsets: Vec::new(),
+            reach: [0; 4],
+            grid_width: 0,
+            lattice: Lattice::Square,
+        };
+        neighborhood.compute_weights();
+        neighborhood
+    }
+
+    /// Neighborhood of the mask centered on its middle cell ((len - 1) / 2 on each axis)
+    pub fn from_mask(mask: Vec<Vec<u8>>) -> Self {
+        let height = mask.len();
+        let width = mask.first().map_or(0, Vec::len);
+        Self::new(width, height, (height.saturating_sub(1)) / 2, (width.saturating_sub(1)) / 2, mask)
+    }
+
+    /// Neighborhood of a preset or custom shape, with a uniform kernel
+    pub fn from_shape(shape: &Shape) -> Result<Self, &'static str> {
+        shape.validate()?;
+        Ok(match shape {
+            Shape::Custom { mask, center } => {
+                Self::new(mask[0].len(), mask.len(), center[0], center[1], mask.clone())
+            }
+            _ => Self::from_mask(shape.mask().expect("presets have a mask")),
+        })
+    }
+
+    /// Square of side 2 * radius + 1
+    pub fn moore(radius: usize) -> Self {
+        Self::from_mask(Shape::Moore { radius }.mask().expect("presets have a mask"))
+    }
+
+    /// Diamond of the cells within `radius` steps along the axes
+    pub fn von_neumann(radius: usize) -> Self {
+        Self::from_mask(Shape::VonNeumann { radius }.mask().expect("presets have a mask"))
+    }
+
+    /// Disc of the cells within euclidean distance `radius` + 1/2
+    pub fn circular(radius: usize) -> Self {
+        Self::from_mask(Shape::Circular { radius }.mask().expect("presets have a mask"))
+    }
+
+    /// Hexagon of the cells within `radius` steps on a hexagonal lattice in axial coordinates
+    pub fn hexagonal(radius: usize) -> Self {
+        Self::from_mask(Shape::Hexagonal { radius }.mask().expect("presets have a mask"))
+    }
+
+    /// Center row and column, `radius` cells on each side
+    pub fn cross(radius: usize) -> Self {
+        Self::from_mask(Shape::Cross { radius }.mask().expect("presets have a mask"))
+    }
+
+    /// The neighborhood standing for this one on `lattice`: the Moore square, every cell within the
+    /// radius on a square lattice, is the hexagonal preset on a hexagonal lattice and back.
+    /// None for the other shapes, whose masks are read as they are
+    pub fn for_lattice(&self, lattice: Lattice) -> Option<Self> {
+        let neighborhood = match (Shape::of(self), lattice) {
+            (Shape::Moore { radius }, Lattice::Hexagonal) => Self::hexagonal(radius),
+            (Shape::Hexagonal { radius }, Lattice::Square) => Self::moore(radius),
+            _ => return None,
+        };
+        neighborhood.with_kernel(self.kernel.clone()).ok()
+    }
+
+    /// Fails if the mask, once adapted with `for_lattice`, reaches cells farther on the lattice
+    /// than its radius (largest |dr| or |dc|): on a hexagonal lattice the square diagonal
+    /// (-1, -1) / (1, 1) is two steps away
+    pub fn check_lattice(&self, lattice: Lattice) -> Result<(), &'static str> {
+        let adapted = self.for_lattice(lattice);
+        let offsets = adapted.as_ref().unwrap_or(self).offsets.iter();
+        let radius = offsets.clone().map(|offset| offset.dr.abs().max(offset.dc.abs())).max().unwrap_or(0);
+        match lattice {
+            Lattice::Square => Ok(()),
+            Lattice::Hexagonal if offsets.clone().all(|offset| (offset.dr + offset.dc).abs() <= radius) => Ok(()),
+            Lattice::Hexagonal => Err("neighborhood masks on a hexagonal lattice must not reach along the (-1, -1) / (1, 1) diagonal, use the hexagonal shape"),
+        }
+    }
+
+    pub fn check_mask(mask: &[Vec<u8>]) -> Result<(), &'static str> {
+        match mask.first() {
+            Some(row) if !row.is_empty() && mask.iter().all(|other| other.len() == row.len()) => Ok(()),
+            _ => Err("neighborhood masks must be non-empty rectangles"),
         }
     }
 
+    /// Same neighborhood weighted by `kernel`, a custom kernel must have the size of the mask
+    pub fn with_kernel(mut self, kernel: Kernel) -> Result<Self, &'static str> {
+        kernel.validate()?;
+        if let Kernel::Custom { weights } = &kernel
+            && (weights.len() != self.height || weights.iter().any(|row| row.len() != self.width)) {
+            return Err("custom kernel weights must have the size of the neighborhood mask");
+        }
+        self.kernel = kernel;
+        self.compute_weights();
+        Ok(self)
+    }
+
+    #[inline]
+    pub fn kernel(&self) -> &Kernel {
+        &self.kernel
+    }
+
+    /// Adds (non-zero) or removes a cell of the mask
+    pub fn set_cell(&mut self, mask_r: usize, mask_c: usize, value: u8) {
+        self.mask[mask_r][mask_c] = value;
+        self.compute_weights();
+    }
+
+    /// Weights and offsets of the cells of the mask
+    fn compute_weights(&mut self) {
+        self.weights = vec![0.0; self.width * self.height];
+        self.distance_weights = vec![0.0; self.width * self.height];
+        self.offsets.clear();
+        self.neighbor_offsets.clear();
+        self.reach = [0; 4];
+        for mask_r in 0..self.height {
+            for mask_c in 0..self.width {
+                if self.mask[mask_r][mask_c] == 0 {
+                    continue;
+                }
+                let dr = mask_r as isize - self.center_row as isize;
+                let dc = mask_c as isize - self.center_col as isize;
+                let distance = self.lattice.distance(dr, dc);
+                let idx = mask_r * self.width + mask_c;
+                self.weights[idx] = self.kernel.weight(mask_r, mask_c, distance);
+                self.distance_weights[idx] = 1.0 / (1.0 + distance);
+
+                let offset = Offset {
+                    dr,
+                    dc,
+                    flat: dr * self.grid_width as isize + dc,
+                    weight: self.weights[idx],
+                    distance_weight: self.distance_weights[idx],
+                };
+                self.offsets.push(offset);
+                if (dr, dc) != (0, 0) {
+                    self.neighbor_offsets.push(offset);
+                }
+                self.reach = [
+                    self.reach[0].max((-dr).max(0) as usize),
+                    self.reach[1].max(dr.max(0) as usize),
+                    self.reach[2].max((-dc).max(0) as usize),
+                    self.reach[3].max(dc.max(0) as usize),
+                ];
+            }
+        }
+    }
+
+    /// Precomputes the flat offsets for the width of the grid, and the distance weights for its
+    /// lattice (a no-op if they already are)
+    pub fn compile(&mut self, grid: &Grid) {
+        if self.lattice != grid.lattice {
+            self.lattice = grid.lattice;
+            self.grid_width = grid.width;
+            self.compute_weights();
+        }
+        if self.grid_width == grid.width {
+            return;
+        }
+        self.grid_width = grid.width;
+        for offset in self.offsets.iter_mut().chain(self.neighbor_offsets.iter_mut()) {
+            offset.flat = offset.dr * grid.width as isize + offset.dc;
+        }
+    }
+
+    /// Cells of the mask, center included if set, row-major
+    #[inline(always)]
+    pub fn offsets(&self) -> &[Offset] {
+        &self.offsets
+    }
+
+    /// Cells of the mask other than the center, row-major
+    #[inline(always)]
+    pub fn neighbor_offsets(&self) -> &[Offset] {
+        &self.neighbor_offsets
+    }
+
+    /// Calls `f(offset, idx)` for each offset of `offsets` (from `offsets()` or `neighbor_offsets()`)
+    /// whose cell around (cell_r, cell_c) is in the grid, `idx` being its row-major index.
+    /// Away from the borders the flat offsets give the indices without any boundary lookup
+    #[inline(always)]
+    pub fn for_each_cell(&self, offsets: &[Offset], cell_r: usize, cell_c: usize, grid: &Grid, mut f: impl FnMut(&Offset, usize)) {
+        let [up, down, left, right] = self.reach;
+        if self.grid_width == grid.width
+            && cell_r >= up
+            && cell_r + down < grid.height
+            && cell_c >= left
+            && cell_c + right < grid.width
+        {
+            let base = (cell_r * grid.width + cell_c) as isize;
+            for offset in offsets {
+                f(offset, (base + offset.flat) as usize);
+            }
+        } else {
+            for offset in offsets {
+                if let Some((grid_r, grid_c)) = grid.get_position(cell_r as isize + offset.dr, cell_c as isize + offset.dc) {
+                    f(offset, grid_r * grid.width + grid_c);
+                }
+            }
+        }
+    }
+
+    /// Kernel weight of a cell of the mask (0 outside of the mask)
+    #[inline(always)]
+    pub fn weight(&self, mask_r: usize, mask_c: usize) -> f32 {
+        unsafe { *self.weights.get_unchecked(mask_r * self.width + mask_c) }
+    }
+
+    /// 1 / (1 + distance to the center) of a cell of the mask (0 outside of the mask)
+    #[inline(always)]
+    pub fn distance_weight(&self, mask_r: usize, mask_c: usize) -> f32 {
+        unsafe { *self.distance_weights.get_unchecked(mask_r * self.width + mask_c) }
+    }
+
+    /// (Σ weight × value, Σ weight) of the trait over the non-empty cells of the kernel, center included
+    #[inline]
+    pub fn kernel_sum(&self, trait_index: usize, cell_r: usize, cell_c: usize, grid: &Grid) -> (f32, f32) {
+        let values = grid.get_trait_slice(trait_index);
+        let mut sum = 0.0;
+        let mut weight_sum = 0.0;
+        self.for_each_cell(&self.offsets, cell_r, cell_c, grid, |offset, idx| {
+            if offset.weight != 0.0 && !grid.is_empty[idx] {
+                sum += offset.weight * values[idx];
+                weight_sum += offset.weight;
+            }
+        });
+        (sum, weight_sum)
+    }
+
+    /// Kernel-weighted mean of the trait, None without weighted non-empty cells
+    #[inline]
+    pub fn kernel_mean(&self, trait_index: usize, cell_r: usize, cell_c: usize, grid: &Grid) -> Option<f32> {
+        let (sum, weight_sum) = self.kernel_sum(trait_index, cell_r, cell_c, grid);
+        (weight_sum != 0.0).then(|| sum / weight_sum)
+    }
+
     #[inline(always)]
-    pub fn get_grid_coords(&self, mask_r: usize, mask_c: usize, cell_r: usize, cell_c: usize, grid: &Grid) -> (usize, usize) {
+    pub fn get_grid_coords(&self, mask_r: usize, mask_c: usize, cell_r: usize, cell_c: usize, grid: &Grid) -> Option<(usize, usize)> {
         let dr = mask_r as isize - self.center_row as isize;
         let dc = mask_c as isize - self.center_col as isize;
 
@@ -41,4 +522,185 @@ impl Neighborhood {
             *self.mask.get_unchecked(mask_r).get_unchecked(mask_c)
         }
     }
-}
\ No newline at end of file
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kernel_weights() {
+        let mask = vec![vec![1, 1, 1], vec![1, 1, 1], vec![1, 1, 0]];
+        let gaussian = Neighborhood::from_mask(mask.clone()).with_kernel(Kernel::Gaussian { sigma: 1.0 }).unwrap();
+        assert_eq!(gaussian.weight(1, 1), 1.0);
+        assert!((gaussian.weight(0, 1) - (-0.5f32).exp()).abs() < 1e-6);
+        assert!((gaussian.weight(0, 0) - (-1.0f32).exp()).abs() < 1e-6);
+        // Outside of the mask
+        assert_eq!(gaussian.weight(2, 2), 0.0);
+        assert_eq!(gaussian.distance_weight(2, 2), 0.0);
+        assert_eq!(gaussian.distance_weight(0, 1), 0.5);
+
+        let ring = Neighborhood::from_mask(mask.clone()).with_kernel(Kernel::Ring { radius: 1.0, width: 0.5 }).unwrap();
+        assert_eq!(ring.weight(1, 0), 1.0);
+        assert!(ring.weight(1, 1) < 0.2);
+
+        // Removing a cell of the mask zeroes its weight
+        let mut uniform = Neighborhood::from_mask(mask);
+        assert_eq!(uniform.weight(0, 0), 1.0);
+        uniform.set_cell(0, 0, 0);
+        assert_eq!(uniform.weight(0, 0), 0.0);
+
+        assert!(Neighborhood::from_mask(vec![vec![1; 3]; 3]).with_kernel(Kernel::Gaussian { sigma: 0.0 }).is_err());
+        assert!(Neighborhood::from_mask(vec![vec![1; 3]; 3]).with_kernel(Kernel::Custom { weights: vec![vec![1.0; 3]; 2] }).is_err());
+    }
+
+    #[test]
+    fn test_kernel_params() {
+        for kernel in [
+            Kernel::Uniform,
+            Kernel::Gaussian { sigma: 2.0 },
+            Kernel::Ring { radius: 3.0, width: 0.5 },
+            Kernel::InverseDistance,
+            Kernel::Custom { weights: vec![vec![1.0, 2.0], vec![3.0, 4.0], vec![5.0, 6.0]] },
+        ] {
+            assert_eq!(Kernel::from_params(kernel.name(), &kernel.params(), 2), Some(kernel.clone()));
+        }
+        assert_eq!(Kernel::from_params("gaussian", &[], 2), None);
+
+        #[derive(Deserialize)]
+        struct Config {
+            kernel: Kernel,
+        }
+        let config: Config = toml::from_str(r#"kernel = { kind = "ring", radius = 2.0, width = 1.0 }"#).unwrap();
+        assert_eq!(config.kernel, Kernel::Ring { radius: 2.0, width: 1.0 });
+        assert!(toml::from_str::<Config>(r#"kernel = { kind = "gaussian" }"#).is_err());
+    }
+
+    #[test]
+    fn test_shapes() {
+        let count = |neighborhood: &Neighborhood| neighborhood.mask.iter().flatten().filter(|&&cell| cell != 0).count();
+        assert_eq!(Neighborhood::moore(1).mask, vec![vec![1; 3]; 3]);
+        assert_eq!(Neighborhood::von_neumann(1).mask, vec![vec![0, 1, 0], vec![1, 1, 1], vec![0, 1, 0]]);
+        assert_eq!(Neighborhood::hexagonal(1).mask, vec![vec![0, 1, 1], vec![1, 1, 1], vec![1, 1, 0]]);
+        assert_eq!(count(&Neighborhood::moore(2)), 25);
+        assert_eq!(count(&Neighborhood::von_neumann(2)), 13);
+        assert_eq!(count(&Neighborhood::circular(2)), 21);
+        assert_eq!(count(&Neighborhood::circular(3)), 37);
+        assert_eq!(count(&Neighborhood::hexagonal(2)), 19);
+        assert_eq!(count(&Neighborhood::cross(2)), 9);
+        let cross = Neighborhood::cross(3);
+        assert_eq!((cross.width, cross.height, cross.center_row, cross.center_col), (7, 7, 3, 3));
+
+        // The presets are recognized from their mask, the other masks are custom
+        for name in Shape::NAMES {
+            if let Some(preset) = Shape::preset(name, 3) {
+                assert_eq!(Shape::of(&Neighborhood::from_shape(&preset).unwrap()), preset);
+            }
+        }
+        let custom = Shape::Custom { mask: vec![vec![1, 1, 1], vec![0, 1, 0]], center: [0, 1] };
+        let neighborhood = Neighborhood::from_shape(&custom).unwrap();
+        assert_eq!((neighborhood.width, neighborhood.height, neighborhood.center_row, neighborhood.center_col), (3, 2, 0, 1));
+        assert_eq!(Shape::of(&neighborhood), custom);
+        assert_eq!(Shape::of(&Neighborhood::from_mask(vec![vec![1, 0, 0], vec![1, 1, 0], vec![0, 0, 0]])).name(), "custom");
+
+        assert!(Neighborhood::from_shape(&Shape::Moore { radius: 0 }).is_err());
+
+        // Moore squares become hexagons on a hexagonal lattice, with their kernel
+        let gaussian = Kernel::Gaussian { sigma: 2.0 };
+        let hexagon = Neighborhood::moore(2).with_kernel(gaussian.clone()).unwrap().for_lattice(Lattice::Hexagonal).unwrap();
+        assert_eq!(hexagon, Neighborhood::hexagonal(2).with_kernel(gaussian).unwrap());
+        assert_eq!(hexagon.for_lattice(Lattice::Square).unwrap().mask, Neighborhood::moore(2).mask);
+        assert!(Neighborhood::moore(1).for_lattice(Lattice::Square).is_none());
+        assert!(Neighborhood::von_neumann(1).for_lattice(Lattice::Hexagonal).is_none());
+        for neighborhood in [Neighborhood::moore(1), Neighborhood::von_neumann(3), Neighborhood::hexagonal(2), Neighborhood::cross(1)] {
+            assert!(neighborhood.check_lattice(Lattice::Hexagonal).is_ok());
+        }
+        for neighborhood in [Neighborhood::circular(2), Neighborhood::circular(3), Neighborhood::from_mask(vec![vec![1, 0], vec![1, 1]])] {
+            assert!(neighborhood.check_lattice(Lattice::Square).is_ok());
+            assert!(neighborhood.check_lattice(Lattice::Hexagonal).is_err());
+        }
+        assert!(Neighborhood::from_shape(&Shape::Custom { mask: vec![vec![1; 3]], center: [1, 1] }).is_err());
+
+        #[derive(Deserialize)]
+        struct Config {
+            neighborhood: Shape,
+        }
+        let config: Config = toml::from_str(r#"neighborhood = { kind = "von_neumann", radius = 2 }"#).unwrap();
+        assert_eq!(config.neighborhood, Shape::VonNeumann { radius: 2 });
+        let config: Config = toml::from_str(r#"neighborhood = { kind = "custom", mask = [[1, 1]], center = [0, 0] }"#).unwrap();
+        assert_eq!(config.neighborhood, Shape::Custom { mask: vec![vec![1, 1]], center: [0, 0] });
+        assert!(toml::from_str::<Config>(r#"neighborhood = { kind = "moore" }"#).is_err());
+    }
+
+    #[test]
+    fn test_offsets() {
+        let mut neighborhood = Neighborhood::from_shape(&Shape::Custom {
+            mask: vec![vec![1, 1, 0, 0], vec![0, 1, 0, 1]],
+            center: [1, 1],
+        })
+        .unwrap();
+        assert_eq!(neighborhood.offsets().len(), 4);
+        assert_eq!(neighborhood.neighbor_offsets().len(), 3);
+        assert_eq!(neighborhood.reach, [1, 0, 1, 2]);
+        let offsets: Vec<(isize, isize)> = neighborhood.offsets().iter().map(|offset| (offset.dr, offset.dc)).collect();
+        assert_eq!(offsets, vec![(-1, -1), (-1, 0), (0, 0), (0, 2)]);
+
+        // The flat offsets of the interior and the boundary lookups give the cells of the mask
+        let mut grid = Grid::new(7, 5, 1);
+        grid.set_boundaries(crate::grid::Boundary::Fixed, crate::grid::Boundary::Periodic);
+        let visit = |neighborhood: &Neighborhood, cell_r: usize, cell_c: usize| {
+            let mut cells = Vec::new();
+            neighborhood.for_each_cell(neighborhood.neighbor_offsets(), cell_r, cell_c, &grid, |_, idx| cells.push(idx));
+            cells
+        };
+        let uncompiled = neighborhood.clone();
+        neighborhood.compile(&grid);
+        assert_eq!(neighborhood.neighbor_offsets()[2].flat, 2);
+        assert_eq!(neighborhood.offsets()[0].flat, -8);
+        for cell_r in 0..grid.height {
+            for cell_c in 0..grid.width {
+                let expected: Vec<usize> = [(-1, -1), (-1, 0), (0, 2)]
+                    .iter()
+                    .filter_map(|&(dr, dc)| grid.get_position(cell_r as isize + dr, cell_c as isize + dc))
+                    .map(|(r, c)| r * grid.width + c)
+                    .collect();
+                assert_eq!(visit(&neighborhood, cell_r, cell_c), expected);
+                assert_eq!(visit(&uncompiled, cell_r, cell_c), expected);
+            }
+        }
+        // Compiled for another width, the boundary lookups are used
+        neighborhood.compile(&Grid::new(4, 5, 1));
+        assert_eq!(visit(&neighborhood, 2, 2), visit(&uncompiled, 2, 2));
+        assert_eq!(neighborhood, uncompiled);
+
+        // On a hexagonal lattice the distances are those between the cell centers
+        assert_eq!(neighborhood.offsets()[0].distance_weight, 1.0 / (1.0 + 2.0f32.sqrt()));
+        grid.lattice = Lattice::Hexagonal;
+        neighborhood.compile(&grid);
+        assert_eq!(neighborhood.offsets()[0].distance_weight, 1.0 / (1.0 + 3.0f32.sqrt()));
+        assert_eq!(neighborhood.offsets()[0].flat, -8);
+    }
+
+    #[test]
+    fn test_kernel_mean() {
+        let mut grid = Grid::new(3, 3, 1);
+        for idx in 0..9 {
+            grid.data[idx] = idx as f32;
+        }
+        let neighborhood = Neighborhood::from_mask(vec![vec![0, 1, 0], vec![1, 1, 1], vec![0, 1, 0]])
+            .with_kernel(Kernel::Custom { weights: vec![vec![0.0, 1.0, 0.0], vec![0.0, 2.0, 0.0], vec![0.0, 1.0, 0.0]] })
+            .unwrap();
+        // (1 + 2 × 4 + 7) / 4
+        assert_eq!(neighborhood.kernel_sum(0, 1, 1, &grid), (16.0, 4.0));
+        assert_eq!(neighborhood.kernel_mean(0, 1, 1, &grid), Some(4.0));
+        grid.is_empty[1] = true;
+        assert_eq!(neighborhood.kernel_sum(0, 1, 1, &grid), (15.0, 3.0));
+        for idx in 0..9 {
+            grid.is_empty[idx] = true;
+        }
+        assert_eq!(neighborhood.kernel_mean(0, 1, 1, &grid), None);
+    }
+}
@@ -0,0 +1,170 @@
+use rand::RngCore;
+
+
+// Independent random streams, so that two consumers of the same cell never share draws
+pub const STREAM_MOVEMENT: u64 = 0;
+pub const STREAM_BID: u64 = 1;
+/// Which cells the asynchronous update modes update
+pub const STREAM_UPDATE: u64 = 2;
+/// First stream of the rules, the rule of trait `t` draws from `STREAM_RULE + t`
+pub const STREAM_RULE: u64 = 3;
+/// Draws of `Simulation::randomize`, away from the open-ended rule streams
+pub const STREAM_RANDOMIZE: u64 = u64::MAX;
+
+
+/// Draw a fresh seed from the OS entropy source (used when no seed is configured)
+pub fn random_seed() -> u64 {
+    rand::thread_rng().next_u64()
+}
+
+/// Derive an independent sub-seed from a seed and a stream number (SplitMix64 finalizer)
+#[inline(always)]
+pub fn derive_seed(seed: u64, stream: u64) -> u64 {
+    let mut z = seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+
+/// Randomness of one timestep.
+/// Every draw is a pure function of (seed, timestep, cell index, stream), so results
+/// do not depend on how the work is split between threads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StepRandom {
+    key: u64,
+}
+
+impl StepRandom {
+    #[inline]
+    pub fn new(seed: u64, timestep: u64) -> Self {
+        Self { key: derive_seed(seed, timestep) }
+    }
+
+    /// Generator dedicated to one cell and one stream for this timestep
+    #[inline(always)]
+    pub fn cell_rng(&self, cell_idx: usize, stream: u64) -> CellRng {
+        CellRng {
+            key: derive_seed(derive_seed(self.key, stream), cell_idx as u64),
+            counter: 0,
+        }
+    }
+}
+
+
+/// Generator of one cell for the rule of one trait, keyed on its first draw
+/// so that the deterministic rules do not pay for it
+#[derive(Clone, Debug)]
+pub struct RuleRng {
+    random: StepRandom,
+    cell_idx: usize,
+    trait_index: usize,
+    rng: Option<CellRng>,
+}
+
+impl RuleRng {
+    #[inline(always)]
+    pub fn new(random: StepRandom, cell_idx: usize, trait_index: usize) -> Self {
+        Self { random, cell_idx, trait_index, rng: None }
+    }
+
+    #[inline(always)]
+    fn rng(&mut self) -> &mut CellRng {
+        self.rng.get_or_insert_with(|| {
+            self.random.cell_rng(self.cell_idx, STREAM_RULE + self.trait_index as u64)
+        })
+    }
+}
+
+impl RngCore for RuleRng {
+    #[inline(always)]
+    fn next_u32(&mut self) -> u32 {
+        self.rng().next_u32()
+    }
+
+    #[inline(always)]
+    fn next_u64(&mut self) -> u64 {
+        self.rng().next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng().fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng().try_fill_bytes(dest)
+    }
+}
+
+
+/// Counter-based generator: the n-th output is a hash of (key, n), no hidden state
+#[derive(Clone, Debug)]
+pub struct CellRng {
+    key: u64,
+    counter: u64,
+}
+
+impl RngCore for CellRng {
+    #[inline(always)]
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    #[inline(always)]
+    fn next_u64(&mut self) -> u64 {
+        let out = derive_seed(self.key, self.counter);
+        self.counter += 1;
+        out
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derive_seed_is_stable() {
+        assert_eq!(derive_seed(42, 7), derive_seed(42, 7));
+        assert_ne!(derive_seed(42, 7), derive_seed(42, 8));
+        assert_ne!(derive_seed(42, 7), derive_seed(43, 7));
+    }
+
+    #[test]
+    fn test_cell_rng_depends_only_on_its_coordinates() {
+        let random = StepRandom::new(1, 5);
+        let a: Vec<u64> = (0..4).map({ let mut rng = random.cell_rng(10, STREAM_MOVEMENT); move |_| rng.next_u64() }).collect();
+        let b: Vec<u64> = (0..4).map({ let mut rng = random.cell_rng(10, STREAM_MOVEMENT); move |_| rng.next_u64() }).collect();
+        assert_eq!(a, b);
+        assert_ne!(random.cell_rng(10, STREAM_MOVEMENT).next_u64(), random.cell_rng(11, STREAM_MOVEMENT).next_u64());
+        assert_ne!(random.cell_rng(10, STREAM_MOVEMENT).next_u64(), random.cell_rng(10, STREAM_BID).next_u64());
+        assert_ne!(random.cell_rng(10, STREAM_MOVEMENT).next_u64(), StepRandom::new(1, 6).cell_rng(10, STREAM_MOVEMENT).next_u64());
+    }
+
+    #[test]
+    fn test_rule_rng_is_a_cell_stream() {
+        let random = StepRandom::new(3, 9);
+        let mut rule_rng = RuleRng::new(random, 10, 1);
+        let mut cell_rng = random.cell_rng(10, STREAM_RULE + 1);
+        for _ in 0..3 {
+            assert_eq!(rule_rng.next_u64(), cell_rng.next_u64());
+        }
+        assert_ne!(RuleRng::new(random, 10, 0).next_u64(), RuleRng::new(random, 10, 1).next_u64());
+    }
+}
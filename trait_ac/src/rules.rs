@@ -1,483 +1,2061 @@
+use crate::convolution::Convolution;
 use crate::neighborhood::Neighborhood;
-use crate::grid::Grid;
+use crate::grid::{Grid, Lattice};
+use crate::traits::Domain;
+use crate::update::UpdateMode;
+use crate::expression::{self, Op};
+use crate::random::{RuleRng, StepRandom};
+use rand::Rng;
+use crate::utils::default_traits_names;
+use serde::Deserialize;
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::{LazyLock, RwLock};
+
+
+/// Parameters carried by a rule: f32 constants addressed by name and, for the rules
+/// defined by a rulestring (e.g. "B36/S23"), the rulestring
+pub trait RuleParams {
+    /// Names of the f32 parameters
+    const NAMES: &'static [&'static str];
+
+    fn get(&self, name: &str) -> Option<f32>;
+
+    /// False if there is no parameter with this name
+    fn set(&mut self, name: &str, value: f32) -> bool;
+
+    fn rulestring(&self) -> Option<String> {
+        None
+    }
 
+    fn set_rulestring(&mut self, _rulestring: &str) -> Result<(), String> {
+        Err("this rule has no rulestring".to_string())
+    }
 
+    /// Resolves the trait names used by the rule to trait indices
+    fn bind_traits(&mut self, _trait_names: &[String]) -> Result<(), String> {
+        Ok(())
+    }
 
-pub struct RuleFunction;
+    /// Input roles, the traits read by the rule besides the one it updates
+    const INPUTS: &'static [&'static str] = &[];
 
-impl RuleFunction {
-    /// No change - cells maintain their trait value
-    #[inline(always)]
-    pub fn static_rule(trait_index: usize, cell_r: usize, cell_c: usize, _neighborhood_traits: &Neighborhood, grid: &Grid) -> f32 {
-        grid.get_cell_trait(cell_r, cell_c, trait_index)
+    /// Index of the trait read for `role`
+    fn get_input(&self, _role: &str) -> Option<usize> {
+        None
     }
 
-    /// Average of neighbors' trait values
-    pub fn average(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood_traits: &Neighborhood, grid: &Grid) -> f32 {
-        let mut sum = 0.0;
-        let mut count = 0;
+    /// Reads `role` from the trait named `trait_name` once bound, false if there is no such role
+    fn set_input(&mut self, _role: &str, _trait_name: &str) -> bool {
+        false
+    }
+}
 
-        for mask_r in 0..neighborhood_traits.height {
-            for mask_c in 0..neighborhood_traits.width {
-                if neighborhood_traits.is_valid(mask_r, mask_c) == 1 {
 
-                    let (grid_r, grid_c) = neighborhood_traits.get_grid_coords(mask_r, mask_c, cell_r, cell_c, grid);
+/// Traits read by a coupled rule, one per input role. A role reads its default trait
+/// index until it is given a trait name, resolved by `bind`. Inputs reading the same traits are equal
+#[derive(Clone, Debug)]
+pub struct RuleInputs<const N: usize> {
+    names: [Option<String>; N],
+    indices: [usize; N],
+}
 
-                    let neighbor_is_empty = grid.is_cell_empty(grid_r, grid_c);
-                    let neighbor_value = grid.get_cell_trait(grid_r, grid_c, trait_index);
-                    
-                    if !neighbor_is_empty {
-                        sum += neighbor_value;
-                        count += 1;
-                    }
-                }
+impl<const N: usize> RuleInputs<N> {
+    pub const fn new(defaults: [usize; N]) -> Self {
+        Self { names: [const { None }; N], indices: defaults }
+    }
+
+    pub fn set(&mut self, role_index: usize, trait_name: &str) {
+        self.names[role_index] = Some(trait_name.to_string());
+    }
+
+    /// Resolves the trait names, every role must read an existing trait
+    pub fn bind(&mut self, roles: &[&str], trait_names: &[String]) -> Result<(), String> {
+        let mut indices = self.indices;
+        for (role_index, name) in self.names.iter().enumerate() {
+            if let Some(name) = name {
+                indices[role_index] = trait_names
+                    .iter()
+                    .position(|trait_name| trait_name == name)
+                    .ok_or_else(|| format!("unknown trait '{}' for input '{}'", name, roles[role_index]))?;
+            } else if indices[role_index] >= trait_names.len() {
+                return Err(format!(
+                    "input '{}' reads trait {} by default, there are {} traits",
+                    roles[role_index], indices[role_index], trait_names.len()
+                ));
             }
         }
+        self.indices = indices;
+        Ok(())
+    }
+}
 
-        if count == 0 {
-            grid.get_cell_trait(cell_r, cell_c, trait_index)
-        } else {
-            (sum / count as f32).clamp(0.0, 1.0)
-        }
+impl<const N: usize> PartialEq for RuleInputs<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.indices == other.indices
     }
+}
 
-    /// Conway's Game of Life style
-    pub fn conway(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood_traits: &Neighborhood, grid: &Grid) -> f32 {
-        let mut alive_neighbors = 0;
+impl<const N: usize> std::ops::Index<usize> for RuleInputs<N> {
+    type Output = usize;
+
+    #[inline(always)]
+    fn index(&self, role_index: usize) -> &usize {
+        &self.indices[role_index]
+    }
+}
 
-        let center_row = neighborhood_traits.center_row;
-        let center_col = neighborhood_traits.center_col;
+/// Declares the tunable constants of a rule, a struct of f32 fields with their defaults
+/// that the config files and the UI address by field name.
+/// Coupled rules add `inputs { role: default_trait_index, ... }`, read as `params.inputs[role_position]`
+macro_rules! define_params {
+    (
+        $(#[$meta:meta])* $params:ident { $($(#[$field_meta:meta])* $field:ident: $default:expr),* $(,)? }
+        $(inputs { $($role:ident: $trait_index:expr),* $(,)? })?
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, PartialEq, Debug)]
+        pub struct $params {
+            $($(#[$field_meta])* pub $field: f32,)*
+            $(
+                /// Traits read besides the updated one
+                pub inputs: RuleInputs<{ [$(stringify!($role)),*].len() }>,
+            )?
+        }
+
+        impl $params {
+            pub const DEFAULT: Self = Self {
+                $($field: $default,)*
+                $(inputs: RuleInputs::new([$($trait_index),*]),)?
+            };
+        }
 
-        for mask_r in 0..neighborhood_traits.height {
-            for mask_c in 0..neighborhood_traits.width {
-                if neighborhood_traits.is_valid(mask_r, mask_c) == 1 &&
-                   !(mask_r == center_row && mask_c == center_col) {
+        impl RuleParams for $params {
+            const NAMES: &'static [&'static str] = &[$(stringify!($field)),*];
 
-                    let (grid_r, grid_c) = neighborhood_traits.get_grid_coords(mask_r, mask_c, cell_r, cell_c, grid);
-                    let neighbor_is_empty = grid.is_cell_empty(grid_r, grid_c);
-                    let neighbor_value = grid.get_cell_trait(grid_r, grid_c, trait_index);
+            $(
+                const INPUTS: &'static [&'static str] = &[$(stringify!($role)),*];
 
-                    if !neighbor_is_empty && neighbor_value > 0.5 {
-                        alive_neighbors += 1;
-                    }
+                fn get_input(&self, role: &str) -> Option<usize> {
+                    Self::INPUTS.iter().position(|input| *input == role).map(|i| self.inputs[i])
+                }
+
+                fn set_input(&mut self, role: &str, trait_name: &str) -> bool {
+                    let Some(i) = Self::INPUTS.iter().position(|input| *input == role) else { return false };
+                    self.inputs.set(i, trait_name);
+                    true
+                }
+
+                fn bind_traits(&mut self, trait_names: &[String]) -> Result<(), String> {
+                    self.inputs.bind(Self::INPUTS, trait_names)
+                }
+            )?
+
+            fn get(&self, name: &str) -> Option<f32> {
+                match name {
+                    $(stringify!($field) => Some(self.$field),)*
+                    _ => None,
                 }
             }
-        }
 
-        let current = grid.get_cell_trait(cell_r, cell_c, trait_index);
+            fn set(&mut self, name: &str, value: f32) -> bool {
+                match name {
+                    $(stringify!($field) => { self.$field = value; true })*
+                    _ => false,
+                }
+            }
+        }
 
-        if current > 0.5 {
-            if alive_neighbors == 2 || alive_neighbors == 3 { 1.0 } else { 0.0 }
-        } else {
-            if alive_neighbors == 3 { 1.0 } else { 0.0 }
+        impl Default for $params {
+            fn default() -> Self {
+                Self::DEFAULT
+            }
         }
+    };
+}
+
+define_params!(
+    /// Parameters of `RuleFunction::diffusion`
+    DiffusionParams {
+        /// Weight of the current value
+        self_weight: 0.3,
+        /// Weight of the neighbor average
+        neighbor_weight: 0.7,
+        /// Factor applied after mixing
+        decay: 0.98,
+        /// Factor applied when there is no neighbor
+        isolated_decay: 0.95,
     }
+);
 
-    #[inline(always)]
-    pub fn conway_optimized(
-        trait_index: usize,
-        cell_r: usize,
-        cell_c: usize,
-        _neighborhood: &Neighborhood,
-        grid: &Grid,
-    ) -> f32 {
-        let w = grid.width;
-        let h = grid.height;
-        
-        // Branchless wrap - avoids expensive modulo
-        let r_prev = if cell_r == 0 { h - 1 } else { cell_r - 1 };
-        let r_next = if cell_r == h - 1 { 0 } else { cell_r + 1 };
-        let c_prev = if cell_c == 0 { w - 1 } else { cell_c - 1 };
-        let c_next = if cell_c == w - 1 { 0 } else { cell_c + 1 };
-        
-        let row_prev = r_prev * w;
-        let row_curr = cell_r * w;
-        let row_next = r_next * w;
-        
-        let t = grid.get_trait_slice(trait_index);
-        
-        unsafe {
-            let t = t.as_ptr();
-            
-            let alive = 
-                (*t.add(row_prev + c_prev) > 0.5) as u8 +
-                (*t.add(row_prev + cell_c) > 0.5) as u8 +
-                (*t.add(row_prev + c_next) > 0.5) as u8 +
-                (*t.add(row_curr + c_prev) > 0.5) as u8 +
-                (*t.add(row_curr + c_next) > 0.5) as u8 +
-                (*t.add(row_next + c_prev) > 0.5) as u8 +
-                (*t.add(row_next + cell_c) > 0.5) as u8 +
-                (*t.add(row_next + c_next) > 0.5) as u8;
-            
-            let is_alive = *t.add(row_curr + cell_c) > 0.5;
-            
-            const RESULT: [f32; 18] = [
-                0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0,
-                0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0,
-            ];
-            *RESULT.get_unchecked(alive as usize + 9 * is_alive as usize)
-        }
+define_params!(
+    /// Parameters of `RuleFunction::kernel_diffusion`
+    KernelDiffusionParams {
+        /// Fraction of the way to the kernel mean covered per step
+        rate: 0.5,
+        /// Factor applied after mixing
+        decay: 1.0,
+    }
+);
+
+define_params!(
+    /// Parameters of `Lenia`
+    LeniaParams {
+        /// Radius of the ring kernel, in cells (rounded)
+        radius: 13.0,
+        /// Potential of maximal growth
+        mu: 0.15,
+        /// Width of the growth bump
+        sigma: 0.015,
+        /// Time step, fraction of the growth added per step
+        dt: 0.1,
     }
+);
 
-    /// Diffusion with decay
-    pub fn diffusion(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood_traits: &Neighborhood, grid: &Grid) -> f32 {
-        let mut sum = 0.0;
-        let mut count = 0;
+define_params!(
+    /// Parameters of `RuleFunction::energy_update`
+    EnergyParams {
+        /// Constant loss per step
+        decay: 0.02,
+        /// Energy generated per unit of mean phase difference
+        friction: 0.15,
+        /// Weight of the current energy in the diffusion
+        self_weight: 0.7,
+        /// Weight of the neighbor average in the diffusion
+        neighbor_weight: 0.3,
+    }
+    inputs { phase: 2 }
+);
 
-        let center_row = neighborhood_traits.center_row;
-        let center_col = neighborhood_traits.center_col;
+define_params!(
+    /// Parameters of `RuleFunction::charge_update`
+    ChargeParams {
+        /// Pull toward the charge of higher-energy neighbors
+        attraction: 0.1,
+        /// Push away from the charge of lower-energy neighbors
+        repulsion: 0.05,
+    }
+    inputs { energy: 0 }
+);
 
-        for mask_r in 0..neighborhood_traits.height {
-            for mask_c in 0..neighborhood_traits.width {
-                if neighborhood_traits.is_valid(mask_r, mask_c) == 1 &&
-                   !(mask_r == center_row && mask_c == center_col) {
+define_params!(
+    /// Parameters of `RuleFunction::phase_update`
+    PhaseParams {
+        /// Natural advance per step
+        advance: 0.07,
+        /// Strength of the (anti-)synchronisation with the neighbors
+        coupling: 0.12,
+    }
+    inputs { charge: 1 }
+);
 
-                    let (grid_r, grid_c) = neighborhood_traits.get_grid_coords(mask_r, mask_c, cell_r, cell_c, grid);
-                    let neighbor_is_empty = grid.is_cell_empty(grid_r, grid_c);
-                    let neighbor_value = grid.get_cell_trait(grid_r, grid_c, trait_index);
 
-                    if !neighbor_is_empty {
-                        sum += neighbor_value;
-                        count += 1;
-                    }
-                }
-            }
+/// Birth and survival sets of a life-like rule, bit `n` is set when `n` live neighbors
+/// give birth (dead cell) or survival (live cell)
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LifeLikeParams {
+    birth: u64,
+    survival: u64,
+    /// Outcome indexed by `alive_neighbors + 9 * is_alive` for the cells adjacent on the lattice (at most 8)
+    result: [f32; 18],
+}
+
+impl LifeLikeParams {
+    /// B3/S23
+    pub const DEFAULT: Self = Self::new(1 << 3, (1 << 2) | (1 << 3));
+
+    pub const fn new(birth: u64, survival: u64) -> Self {
+        let mut result = [0.0; 18];
+        let mut n = 0;
+        while n < 9 {
+            result[n] = ((birth >> n) & 1) as f32;
+            result[n + 9] = ((survival >> n) & 1) as f32;
+            n += 1;
         }
+        Self { birth, survival, result }
+    }
 
-        if count == 0 {
-            return (grid.get_cell_trait(cell_r, cell_c, trait_index) * 0.95).clamp(0.0, 1.0);
+    /// "B36/S23" (case insensitive, either order, a set may be empty as in "B2/S").
+    /// Counts above 9 are written as comma separated lists ("B3,10/S2,3")
+    pub fn parse(rulestring: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid life-like rulestring: '{}' (expected e.g. \"B36/S23\")", rulestring);
+        let mut birth = None;
+        let mut survival = None;
+        for part in rulestring.trim().split('/') {
+            let mut chars = part.trim().chars();
+            let set = match chars.next().map(|c| c.to_ascii_uppercase()) {
+                Some('B') => &mut birth,
+                Some('S') => &mut survival,
+                _ => return Err(invalid()),
+            };
+            if set.is_some() {
+                return Err(invalid());
+            }
+            *set = Some(parse_counts(chars.as_str()).ok_or_else(invalid)?);
         }
+        match (birth, survival) {
+            (Some(birth), Some(survival)) => Ok(Self::new(birth, survival)),
+            _ => Err(invalid()),
+        }
+    }
 
-        let current = grid.get_cell_trait(cell_r, cell_c, trait_index);
-        let avg = sum / count as f32;
+    #[inline(always)]
+    pub fn births(&self, alive_neighbors: usize) -> bool {
+        alive_neighbors < 64 && (self.birth >> alive_neighbors) & 1 == 1
+    }
+
+    #[inline(always)]
+    pub fn survives(&self, alive_neighbors: usize) -> bool {
+        alive_neighbors < 64 && (self.survival >> alive_neighbors) & 1 == 1
+    }
+}
 
-        ((0.3 * current + 0.7 * avg) * 0.98).clamp(0.0, 1.0)
+impl Default for LifeLikeParams {
+    fn default() -> Self {
+        Self::DEFAULT
     }
+}
 
-    /// Maximum of neighbors
-    pub fn maximum(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood_traits: &Neighborhood, grid: &Grid) -> f32 {
-        let mut max_val = grid.get_cell_trait(cell_r, cell_c, trait_index);
+impl RuleParams for LifeLikeParams {
+    const NAMES: &'static [&'static str] = &[];
 
-        for mask_r in 0..neighborhood_traits.height {
-            for mask_c in 0..neighborhood_traits.width {
-                if neighborhood_traits.is_valid(mask_r, mask_c) == 1 {
+    fn get(&self, _name: &str) -> Option<f32> {
+        None
+    }
 
-                    let (grid_r, grid_c) = neighborhood_traits.get_grid_coords(mask_r, mask_c, cell_r, cell_c, grid);
-                    let neighbor_is_empty = grid.is_cell_empty(grid_r, grid_c);
-                    let neighbor_value = grid.get_cell_trait(grid_r, grid_c, trait_index);
+    fn set(&mut self, _name: &str, _value: f32) -> bool {
+        false
+    }
 
-                    if !neighbor_is_empty {
-                        max_val = max_val.max(neighbor_value);
-                    }
-                }
-            }
+    fn rulestring(&self) -> Option<String> {
+        Some(format!("B{}/S{}", format_counts(self.birth), format_counts(self.survival)))
+    }
+
+    fn set_rulestring(&mut self, rulestring: &str) -> Result<(), String> {
+        *self = Self::parse(rulestring)?;
+        Ok(())
+    }
+}
+
+/// Bit set of neighbor counts: one digit per count, or a comma separated list
+fn parse_counts(counts: &str) -> Option<u64> {
+    let mut set = 0u64;
+    if counts.contains(',') {
+        for count in counts.split(',') {
+            let n: u32 = count.trim().parse().ok()?;
+            set |= 1u64.checked_shl(n)?;
         }
+    } else {
+        for c in counts.chars() {
+            set |= 1 << c.to_digit(10)?;
+        }
+    }
+    Some(set)
+}
 
-        max_val
+fn format_counts(set: u64) -> String {
+    let counts: Vec<String> = (0..64).filter(|n| (set >> n) & 1 == 1).map(|n| n.to_string()).collect();
+    if set >> 10 == 0 {
+        counts.concat()
+    } else {
+        counts.join(",")
     }
+}
 
-    /// Minimum of neighbors
-    pub fn minimum(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood_traits: &Neighborhood, grid: &Grid) -> f32 {
-        let mut min_val = grid.get_cell_trait(cell_r, cell_c, trait_index);
 
-        for mask_r in 0..neighborhood_traits.height {
-            for mask_c in 0..neighborhood_traits.width {
-                if neighborhood_traits.is_valid(mask_r, mask_c) == 1 {
+/// Discrete states of the multi-state rules, stored in the trait as evenly spaced levels:
+/// dead (state 0) is 0, alive (state 1) is 1 and the refractory states step down toward 0,
+/// so the color schemes show the decay as bands
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct States(u32);
 
-                    let (grid_r, grid_c) = neighborhood_traits.get_grid_coords(mask_r, mask_c, cell_r, cell_c, grid);
-                    let neighbor_is_empty = grid.is_cell_empty(grid_r, grid_c);
-                    let neighbor_value = grid.get_cell_trait(grid_r, grid_c, trait_index);
+impl States {
+    /// At least 2 states (dead and alive)
+    pub fn new(count: u32) -> Option<Self> {
+        (count >= 2).then_some(Self(count))
+    }
 
-                    if !neighbor_is_empty {
-                        min_val = min_val.min(neighbor_value);
-                    }
+    #[inline(always)]
+    pub fn count(&self) -> u32 {
+        self.0
+    }
+
+    #[inline(always)]
+    pub fn level(&self, state: u32) -> f32 {
+        if state == 0 { 0.0 } else { (self.0 - state) as f32 / (self.0 - 1) as f32 }
+    }
+
+    /// State of the nearest level
+    #[inline(always)]
+    pub fn state(&self, value: f32) -> u32 {
+        let steps = (value.clamp(0.0, 1.0) * (self.0 - 1) as f32).round() as u32;
+        if steps == 0 { 0 } else { self.0 - steps }
+    }
+
+    /// Dead cells are born, live cells survive or start dying, dying cells keep dying
+    #[inline(always)]
+    pub fn next(&self, state: u32, born: bool, survives: bool) -> u32 {
+        match state {
+            0 => born as u32,
+            1 if survives => 1,
+            _ if state + 1 >= self.0 => 0,
+            _ => state + 1,
+        }
+    }
+}
+
+
+/// Life-like birth and survival sets with refractory states ("B2/S/C3" is Brian's Brain)
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GenerationsParams {
+    life: LifeLikeParams,
+    states: States,
+}
+
+impl GenerationsParams {
+    /// Brian's Brain
+    pub const DEFAULT: Self = Self { life: LifeLikeParams::new(1 << 2, 0), states: States(3) };
+
+    /// "B2/S/C3" (the B/S part as in `LifeLikeParams::parse`, C is the number of states)
+    pub fn parse(rulestring: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid generations rulestring: '{}' (expected e.g. \"B2/S/C3\")", rulestring);
+        let mut life_like = Vec::new();
+        let mut states = None;
+        for part in rulestring.trim().split('/') {
+            let part = part.trim();
+            if let Some(count) = part.strip_prefix(['C', 'c']) {
+                if states.is_some() {
+                    return Err(invalid());
                 }
+                states = Some(count.parse().ok().and_then(States::new).ok_or_else(invalid)?);
+            } else {
+                life_like.push(part);
             }
         }
+        let life = LifeLikeParams::parse(&life_like.join("/")).map_err(|_| invalid())?;
+        Ok(Self { life, states: states.ok_or_else(invalid)? })
+    }
+}
 
-        min_val
+impl Default for GenerationsParams {
+    fn default() -> Self {
+        Self::DEFAULT
     }
+}
 
-    /// Weighted average by distance
-    pub fn weighted_average(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood_traits: &Neighborhood, grid: &Grid) -> f32 {
-        let mut sum = 0.0;
-        let mut weight_sum = 0.0;
+impl RuleParams for GenerationsParams {
+    const NAMES: &'static [&'static str] = &[];
 
-        let center_row = neighborhood_traits.center_row;
-        let center_col = neighborhood_traits.center_col;
+    fn get(&self, _name: &str) -> Option<f32> {
+        None
+    }
 
-        for mask_r in 0..neighborhood_traits.height {
-            for mask_c in 0..neighborhood_traits.width {
-                if neighborhood_traits.is_valid(mask_r, mask_c) == 1 &&
-                   !(mask_r == center_row && mask_c == center_col) {
+    fn set(&mut self, _name: &str, _value: f32) -> bool {
+        false
+    }
 
-                    let (grid_r, grid_c) = neighborhood_traits.get_grid_coords(mask_r, mask_c, cell_r, cell_c, grid);
-                    let neighbor_is_empty = grid.is_cell_empty(grid_r, grid_c);
-                    let neighbor_value = grid.get_cell_trait(grid_r, grid_c, trait_index);
+    fn rulestring(&self) -> Option<String> {
+        Some(format!("{}/C{}", self.life.rulestring()?, self.states.count()))
+    }
 
-                    if !neighbor_is_empty {
-                        let dr = (mask_r as isize - center_row as isize).abs() as f32;
-                        let dc = (mask_c as isize - center_col as isize).abs() as f32;
-                        let w = 1.0 / (1.0 + (dr * dr + dc * dc).sqrt());
+    fn set_rulestring(&mut self, rulestring: &str) -> Result<(), String> {
+        *self = Self::parse(rulestring)?;
+        Ok(())
+    }
+}
 
-                        sum += neighbor_value * w;
-                        weight_sum += w;
-                    }
-                }
-            }
-        }
 
-        if weight_sum == 0.0 {
-            grid.get_cell_trait(cell_r, cell_c, trait_index)
-        } else {
-            (sum / weight_sum).clamp(0.0, 1.0)
-        }
+/// Life-like rule whose births and survivals only happen with a probability
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ProbabilisticLifeParams {
+    pub life: LifeLikeParams,
+    /// Probability that a dead cell meeting the birth condition comes alive
+    pub birth_probability: f32,
+    /// Probability that a live cell meeting the survival condition survives
+    pub survival_probability: f32,
+}
+
+impl ProbabilisticLifeParams {
+    /// Conway with 10% failed births and deaths
+    pub const DEFAULT: Self = Self { life: LifeLikeParams::DEFAULT, birth_probability: 0.9, survival_probability: 0.9 };
+}
+
+impl Default for ProbabilisticLifeParams {
+    fn default() -> Self {
+        Self::DEFAULT
     }
+}
 
-    pub fn energy_update(_trait_index: usize, cell_r: usize, cell_c: usize, neighborhood: &Neighborhood, grid: &Grid) -> f32 {
-        let energy = grid.get_cell_trait(cell_r, cell_c, 0);
-        let phase = grid.get_cell_trait(cell_r, cell_c, 2);
-        
-        let center_row = neighborhood.center_row;
-        let center_col = neighborhood.center_col;
-        
-        let mut friction: f32 = 0.0;
-        let mut neighbor_energy_sum: f32 = 0.0;
-        let mut neighbor_count = 0;
-        
-        for mask_r in 0..neighborhood.height {
-            for mask_c in 0..neighborhood.width {
-                if neighborhood.is_valid(mask_r, mask_c) == 1 
-                && !(mask_r == center_row && mask_c == center_col) {
-                    let (grid_r, grid_c) = neighborhood.get_grid_coords(mask_r, mask_c, cell_r, cell_c, grid);
-                    if !grid.is_cell_empty(grid_r, grid_c) {
-                        neighbor_count += 1;
-                        
-                        let neighbor_phase = grid.get_cell_trait(grid_r, grid_c, 2);
-                        let neighbor_energy = grid.get_cell_trait(grid_r, grid_c, 0);
-                        
-                        // Phase difference creates friction (energy generation)
-                        let phase_diff = (phase - neighbor_phase).abs();
-                        let cyclic_diff = phase_diff.min(1.0 - phase_diff);
-                        friction += cyclic_diff;
-                        
-                        neighbor_energy_sum += neighbor_energy;
-                    }
-                }
-            }
+impl RuleParams for ProbabilisticLifeParams {
+    const NAMES: &'static [&'static str] = &["birth_probability", "survival_probability"];
+
+    fn get(&self, name: &str) -> Option<f32> {
+        match name {
+            "birth_probability" => Some(self.birth_probability),
+            "survival_probability" => Some(self.survival_probability),
+            _ => None,
         }
-        
-        let mut new_energy = energy;
-        
-        // Small constant decay
-        new_energy -= 0.02;
-        
-        if neighbor_count > 0 {
-            // Friction generates energy (phase differences)
-            let avg_friction = friction / neighbor_count as f32;
-            new_energy += avg_friction * 0.15;
-            
-            // Diffusion: average with neighbors
-            let avg_neighbor_energy = neighbor_energy_sum / neighbor_count as f32;
-            new_energy = new_energy * 0.7 + avg_neighbor_energy * 0.3;
+    }
+
+    fn set(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "birth_probability" => self.birth_probability = value,
+            "survival_probability" => self.survival_probability = value,
+            _ => return false,
         }
-        
-        new_energy.clamp(0.0, 1.0)
+        true
     }
 
-    pub fn charge_update(_trait_index: usize, cell_r: usize, cell_c: usize, neighborhood: &Neighborhood, grid: &Grid) -> f32 {
-        let charge = grid.get_cell_trait(cell_r, cell_c, 1);
-        let energy = grid.get_cell_trait(cell_r, cell_c, 0);
-        
-        let center_row = neighborhood.center_row;
-        let center_col = neighborhood.center_col;
-        
-        let mut high_energy_charge_sum: f32 = 0.0;
-        let mut high_energy_count = 0;
-        let mut low_energy_charge_sum: f32 = 0.0;
-        let mut low_energy_count = 0;
-        
-        for mask_r in 0..neighborhood.height {
-            for mask_c in 0..neighborhood.width {
-                if neighborhood.is_valid(mask_r, mask_c) == 1 
-                && !(mask_r == center_row && mask_c == center_col) {
-                    let (grid_r, grid_c) = neighborhood.get_grid_coords(mask_r, mask_c, cell_r, cell_c, grid);
-                    if !grid.is_cell_empty(grid_r, grid_c) {
-                        let neighbor_charge = grid.get_cell_trait(grid_r, grid_c, 1);
-                        let neighbor_energy = grid.get_cell_trait(grid_r, grid_c, 0);
-                        
-                        if neighbor_energy > energy {
-                            high_energy_charge_sum += neighbor_charge;
-                            high_energy_count += 1;
-                        } else {
-                            low_energy_charge_sum += neighbor_charge;
-                            low_energy_count += 1;
-                        }
-                    }
+    fn rulestring(&self) -> Option<String> {
+        self.life.rulestring()
+    }
+
+    fn set_rulestring(&mut self, rulestring: &str) -> Result<(), String> {
+        self.life.set_rulestring(rulestring)
+    }
+}
+
+define_params!(
+    /// Parameters of `RuleFunction::voter`
+    VoterParams {
+        /// Probability of taking a uniform random value in [0, 1) instead of a neighbor's
+        noise: 0.01,
+    }
+);
+
+define_params!(
+    /// Parameters of `RuleFunction::random_decay`
+    RandomDecayParams {
+        /// Probability of decaying at each step
+        probability: 0.05,
+        /// Factor applied on decay (0 resets the value)
+        factor: 0.0,
+    }
+);
+
+
+/// Larger than Life: range-r totalistic rule with birth and survival intervals
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LargerThanLifeParams {
+    radius: u32,
+    states: States,
+    /// The cell counts itself
+    include_center: bool,
+    /// Diamond (|dr| + |dc| <= r) instead of square window
+    von_neumann: bool,
+    birth: (u32, u32),
+    survival: (u32, u32),
+}
+
+impl LargerThanLifeParams {
+    /// Bosco's rule
+    pub const DEFAULT: Self = Self {
+        radius: 5,
+        states: States(2),
+        include_center: true,
+        von_neumann: false,
+        birth: (34, 45),
+        survival: (34, 58),
+    };
+    /// Keeps the window (2r + 1)^2 reasonable
+    pub const MAX_RADIUS: u32 = 50;
+
+    /// Golly notation "R5,C0,M1,S34..58,B34..45,NM": radius, states (C0 and C2 both mean 2),
+    /// middle cell included (M1) or not, survival and birth intervals, Moore (NM) or von Neumann (NN) window.
+    /// C, M and N default to C0, M0 and NM
+    pub fn parse(rulestring: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid Larger than Life rulestring: '{}' (expected e.g. \"R5,C0,M1,S34..58,B34..45,NM\")", rulestring);
+        let interval = |range: &str| -> Option<(u32, u32)> {
+            let (min, max) = range.split_once("..")?;
+            let (min, max) = (min.parse().ok()?, max.parse().ok()?);
+            (min <= max).then_some((min, max))
+        };
+        let mut radius = None;
+        let mut params = Self { radius: 0, states: States(2), include_center: false, von_neumann: false, birth: (0, 0), survival: (0, 0) };
+        let mut birth = None;
+        let mut survival = None;
+        for token in rulestring.trim().split(',') {
+            let token = token.trim().to_ascii_uppercase();
+            let (key, value) = token.split_at_checked(1).ok_or_else(invalid)?;
+            match key {
+                "R" => radius = value.parse().ok().filter(|r| (1..=Self::MAX_RADIUS).contains(r)),
+                "C" => {
+                    let count: u32 = value.parse().map_err(|_| invalid())?;
+                    params.states = States::new(count.max(2)).ok_or_else(invalid)?;
                 }
+                "M" => params.include_center = match value { "0" => false, "1" => true, _ => return Err(invalid()) },
+                "S" => survival = interval(value),
+                "B" => birth = interval(value),
+                "N" => params.von_neumann = match value { "M" => false, "N" => true, _ => return Err(invalid()) },
+                _ => return Err(invalid()),
             }
         }
-        
-        let mut new_charge = charge;
-        
-        // Move toward charge of higher-energy neighbors
-        // Move away from charge of lower-energy neighbors
-        if high_energy_count > 0 {
-            let high_avg = high_energy_charge_sum / high_energy_count as f32;
-            new_charge += (high_avg - charge) * 0.1;
-        }
-        
-        if low_energy_count > 0 {
-            let low_avg = low_energy_charge_sum / low_energy_count as f32;
-            new_charge -= (low_avg - charge) * 0.05; // Weaker repulsion
-        }
-        
-        new_charge.clamp(0.0, 1.0)
+        params.radius = radius.ok_or_else(invalid)?;
+        params.birth = birth.ok_or_else(invalid)?;
+        params.survival = survival.ok_or_else(invalid)?;
+        Ok(params)
     }
+}
 
-    pub fn phase_update(_trait_index: usize, cell_r: usize, cell_c: usize, neighborhood: &Neighborhood, grid: &Grid) -> f32 {
-        let phase = grid.get_cell_trait(cell_r, cell_c, 2);
-        let charge = grid.get_cell_trait(cell_r, cell_c, 1);
-        
-        let center_row = neighborhood.center_row;
-        let center_col = neighborhood.center_col;
-        
-        let mut sync_pull: f32 = 0.0;
-        let mut neighbor_count = 0;
-        
-        for mask_r in 0..neighborhood.height {
-            for mask_c in 0..neighborhood.width {
-                if neighborhood.is_valid(mask_r, mask_c) == 1 
-                && !(mask_r == center_row && mask_c == center_col) {
-                    let (grid_r, grid_c) = neighborhood.get_grid_coords(mask_r, mask_c, cell_r, cell_c, grid);
-                    if !grid.is_cell_empty(grid_r, grid_c) {
-                        neighbor_count += 1;
-                        
-                        let neighbor_phase = grid.get_cell_trait(grid_r, grid_c, 2);
-                        let neighbor_charge = grid.get_cell_trait(grid_r, grid_c, 1);
-                        
-                        // Phase difference (cyclic)
-                        let diff = neighbor_phase - phase;
-                        let cyclic_diff = if diff > 0.5 {
-                            diff - 1.0
-                        } else if diff < -0.5 {
-                            diff + 1.0
-                        } else {
-                            diff
-                        };
-                        
-                        // Similar charge = sync together
-                        // Opposite charge = anti-sync (push phases apart)
-                        let charge_similarity = 1.0 - (charge - neighbor_charge).abs();
-                        let coupling = (charge_similarity - 0.5) * 2.0; // -1 to 1
-                        
-                        sync_pull += cyclic_diff * coupling;
+impl Default for LargerThanLifeParams {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl RuleParams for LargerThanLifeParams {
+    const NAMES: &'static [&'static str] = &[];
+
+    fn get(&self, _name: &str) -> Option<f32> {
+        None
+    }
+
+    fn set(&mut self, _name: &str, _value: f32) -> bool {
+        false
+    }
+
+    fn rulestring(&self) -> Option<String> {
+        let states = if self.states.count() == 2 { 0 } else { self.states.count() };
+        Some(format!(
+            "R{},C{},M{},S{}..{},B{}..{},N{}",
+            self.radius, states, self.include_center as u8,
+            self.survival.0, self.survival.1, self.birth.0, self.birth.1,
+            if self.von_neumann { 'N' } else { 'M' }
+        ))
+    }
+
+    fn set_rulestring(&mut self, rulestring: &str) -> Result<(), String> {
+        *self = Self::parse(rulestring)?;
+        Ok(())
+    }
+}
+
+
+/// User rule written as an expression of the cell, its other traits and its neighbors,
+/// e.g. "clamp(0.7*self + 0.3*mean(nbr.Energy) - 0.02, 0, 1)" (see `expression`).
+/// The source is compiled once the trait names are known, until then the rule keeps the value
+#[derive(Clone, PartialEq, Debug)]
+pub struct ExpressionParams {
+    source: Cow<'static, str>,
+    code: Cow<'static, [Op]>,
+}
+
+impl ExpressionParams {
+    pub const DEFAULT: Self = Self { source: Cow::Borrowed("self"), code: Cow::Borrowed(&[Op::Current]) };
+
+    /// Unbound expression, after a syntax check
+    pub fn parse(source: &str) -> Result<Self, String> {
+        expression::check_syntax(source)?;
+        Ok(Self { source: Cow::Owned(source.trim().to_string()), code: Cow::Borrowed(&[]) })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn is_bound(&self) -> bool {
+        !self.code.is_empty()
+    }
+}
+
+impl Default for ExpressionParams {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl RuleParams for ExpressionParams {
+    const NAMES: &'static [&'static str] = &[];
+
+    fn get(&self, _name: &str) -> Option<f32> {
+        None
+    }
+
+    fn set(&mut self, _name: &str, _value: f32) -> bool {
+        false
+    }
+
+    fn rulestring(&self) -> Option<String> {
+        Some(self.source.to_string())
+    }
+
+    fn set_rulestring(&mut self, rulestring: &str) -> Result<(), String> {
+        *self = Self::parse(rulestring)?;
+        Ok(())
+    }
+
+    fn bind_traits(&mut self, trait_names: &[String]) -> Result<(), String> {
+        self.code = Cow::Owned(expression::compile(&self.source, trait_names)?);
+        Ok(())
+    }
+}
+
+
+pub struct RuleFunction;
+
+impl RuleFunction {
+    /// No change - cells maintain their trait value
+    #[inline(always)]
+    pub fn static_rule(trait_index: usize, cell_r: usize, cell_c: usize, _neighborhood_traits: &Neighborhood, grid: &Grid, _rng: &mut RuleRng) -> f32 {
+        grid.get_cell_trait(cell_r, cell_c, trait_index)
+    }
+
+    /// Average of neighbors' trait values
+    pub fn average(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood_traits: &Neighborhood, grid: &Grid, _rng: &mut RuleRng) -> f32 {
+        let values = grid.get_trait_slice(trait_index);
+        let mut sum = 0.0;
+        let mut count = 0;
+
+        neighborhood_traits.for_each_cell(neighborhood_traits.offsets(), cell_r, cell_c, grid, |_, idx| {
+            if !grid.is_empty[idx] {
+                sum += values[idx];
+                count += 1;
+            }
+        });
+
+        if count == 0 {
+            values[cell_r * grid.width + cell_c]
+        } else {
+            sum / count as f32
+        }
+    }
+
+    /// Conway's Game of Life style
+    pub fn conway(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood_traits: &Neighborhood, grid: &Grid, _rng: &mut RuleRng) -> f32 {
+        let values = grid.get_trait_slice(trait_index);
+        let mut alive_neighbors = 0;
+
+        neighborhood_traits.for_each_cell(neighborhood_traits.neighbor_offsets(), cell_r, cell_c, grid, |_, idx| {
+            if !grid.is_empty[idx] && values[idx] > 0.5 {
+                alive_neighbors += 1;
+            }
+        });
+
+        let current = values[cell_r * grid.width + cell_c];
+
+        if current > 0.5 {
+            if alive_neighbors == 2 || alive_neighbors == 3 { 1.0 } else { 0.0 }
+        } else {
+            if alive_neighbors == 3 { 1.0 } else { 0.0 }
+        }
+    }
+
+    #[inline(always)]
+    pub fn conway_optimized(
+        trait_index: usize,
+        cell_r: usize,
+        cell_c: usize,
+        _neighborhood: &Neighborhood,
+        grid: &Grid,
+        _rng: &mut RuleRng,
+    ) -> f32 {
+        Self::adjacent_outcome(trait_index, cell_r, cell_c, grid, &LifeLikeParams::DEFAULT.result)
+    }
+
+    /// Outcome of the live cells adjacent on the lattice, the 3x3 Moore neighborhood on a square one
+    /// and its six cells other than (-1, -1) and (1, 1) on a hexagonal one, `result` being indexed by
+    /// `alive_neighbors + 9 * is_alive`. Border cells of a non-periodic grid go through the
+    /// boundary-aware lookup, neighbors beyond the boundary being dead
+    #[inline(always)]
+    fn adjacent_outcome(trait_index: usize, cell_r: usize, cell_c: usize, grid: &Grid, result: &[f32; 18]) -> f32 {
+        let w = grid.width;
+        let h = grid.height;
+        let t = grid.get_trait_slice(trait_index);
+        let square = grid.lattice == Lattice::Square;
+
+        if (cell_r == 0 || cell_r == h - 1 || cell_c == 0 || cell_c == w - 1) && !grid.is_periodic() {
+            let mut alive = 0;
+            for dr in -1..=1 {
+                for dc in -1..=1 {
+                    if (dr, dc) != (0, 0)
+                        && (square || dr != dc)
+                        && let Some((r, c)) = grid.get_position(cell_r as isize + dr, cell_c as isize + dc)
+                        && t[r * w + c] > 0.5 {
+                        alive += 1;
+                    }
+                }
+            }
+            let is_alive = t[cell_r * w + cell_c] > 0.5;
+            return result[alive + 9 * is_alive as usize];
+        }
+
+        // Branchless wrap - avoids expensive modulo
+        let r_prev = if cell_r == 0 { h - 1 } else { cell_r - 1 };
+        let r_next = if cell_r == h - 1 { 0 } else { cell_r + 1 };
+        let c_prev = if cell_c == 0 { w - 1 } else { cell_c - 1 };
+        let c_next = if cell_c == w - 1 { 0 } else { cell_c + 1 };
+
+        let row_prev = r_prev * w;
+        let row_curr = cell_r * w;
+        let row_next = r_next * w;
+
+        unsafe {
+            let t = t.as_ptr();
+
+            let alive =
+                (*t.add(row_prev + cell_c) > 0.5) as u8 +
+                (*t.add(row_prev + c_next) > 0.5) as u8 +
+                (*t.add(row_curr + c_prev) > 0.5) as u8 +
+                (*t.add(row_curr + c_next) > 0.5) as u8 +
+                (*t.add(row_next + c_prev) > 0.5) as u8 +
+                (*t.add(row_next + cell_c) > 0.5) as u8 +
+                // Branchless: the diagonal is not adjacent on a hexagonal lattice
+                square as u8 * (
+                    (*t.add(row_prev + c_prev) > 0.5) as u8 +
+                    (*t.add(row_next + c_next) > 0.5) as u8
+                );
+
+            let is_alive = *t.add(row_curr + cell_c) > 0.5;
+
+            *result.get_unchecked(alive as usize + 9 * is_alive as usize)
+        }
+    }
+
+    /// Life-like rule (birth and survival sets) counting the live cells of the neighborhood mask
+    pub fn life_like(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood_traits: &Neighborhood, grid: &Grid, params: &LifeLikeParams, _rng: &mut RuleRng) -> f32 {
+        let values = grid.get_trait_slice(trait_index);
+        let mut alive_neighbors = 0;
+
+        neighborhood_traits.for_each_cell(neighborhood_traits.neighbor_offsets(), cell_r, cell_c, grid, |_, idx| {
+            if !grid.is_empty[idx] && values[idx] > 0.5 {
+                alive_neighbors += 1;
+            }
+        });
+
+        let alive = if values[cell_r * grid.width + cell_c] > 0.5 {
+            params.survives(alive_neighbors)
+        } else {
+            params.births(alive_neighbors)
+        };
+        alive as u8 as f32
+    }
+
+    /// Life-like rule on the cells adjacent on the lattice (the mask is ignored), same lookup as `conway_optimized`
+    #[inline(always)]
+    pub fn life_like_optimized(
+        trait_index: usize,
+        cell_r: usize,
+        cell_c: usize,
+        _neighborhood: &Neighborhood,
+        grid: &Grid,
+        params: &LifeLikeParams,
+        _rng: &mut RuleRng,
+    ) -> f32 {
+        Self::adjacent_outcome(trait_index, cell_r, cell_c, grid, &params.result)
+    }
+
+    /// Generations: life-like counting of the live neighbors in the mask, dying cells go through the refractory states
+    pub fn generations(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood_traits: &Neighborhood, grid: &Grid, params: &GenerationsParams, _rng: &mut RuleRng) -> f32 {
+        let states = params.states;
+        let values = grid.get_trait_slice(trait_index);
+        let mut alive_neighbors = 0;
+
+        neighborhood_traits.for_each_cell(neighborhood_traits.neighbor_offsets(), cell_r, cell_c, grid, |_, idx| {
+            if !grid.is_empty[idx] && states.state(values[idx]) == 1 {
+                alive_neighbors += 1;
+            }
+        });
+
+        let state = states.state(values[cell_r * grid.width + cell_c]);
+        let next = states.next(state, params.life.births(alive_neighbors), params.life.survives(alive_neighbors));
+        states.level(next)
+    }
+
+    /// Diffusion with decay
+    pub fn diffusion(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood_traits: &Neighborhood, grid: &Grid, params: &DiffusionParams, _rng: &mut RuleRng) -> f32 {
+        let values = grid.get_trait_slice(trait_index);
+        let mut sum = 0.0;
+        let mut count = 0;
+
+        neighborhood_traits.for_each_cell(neighborhood_traits.neighbor_offsets(), cell_r, cell_c, grid, |_, idx| {
+            if !grid.is_empty[idx] {
+                sum += values[idx];
+                count += 1;
+            }
+        });
+
+        let current = values[cell_r * grid.width + cell_c];
+
+        if count == 0 {
+            return current * params.isolated_decay;
+        }
+
+        let avg = sum / count as f32;
+
+        (params.self_weight * current + params.neighbor_weight * avg) * params.decay
+    }
+
+    /// Maximum of neighbors
+    pub fn maximum(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood_traits: &Neighborhood, grid: &Grid, _rng: &mut RuleRng) -> f32 {
+        let values = grid.get_trait_slice(trait_index);
+        let mut max_val = values[cell_r * grid.width + cell_c];
+
+        neighborhood_traits.for_each_cell(neighborhood_traits.offsets(), cell_r, cell_c, grid, |_, idx| {
+            if !grid.is_empty[idx] {
+                max_val = max_val.max(values[idx]);
+            }
+        });
+
+        max_val
+    }
+
+    /// Minimum of neighbors
+    pub fn minimum(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood_traits: &Neighborhood, grid: &Grid, _rng: &mut RuleRng) -> f32 {
+        let values = grid.get_trait_slice(trait_index);
+        let mut min_val = values[cell_r * grid.width + cell_c];
+
+        neighborhood_traits.for_each_cell(neighborhood_traits.offsets(), cell_r, cell_c, grid, |_, idx| {
+            if !grid.is_empty[idx] {
+                min_val = min_val.min(values[idx]);
+            }
+        });
+
+        min_val
+    }
+
+    /// Weighted average by distance
+    pub fn weighted_average(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood_traits: &Neighborhood, grid: &Grid, _rng: &mut RuleRng) -> f32 {
+        let values = grid.get_trait_slice(trait_index);
+        let mut sum = 0.0;
+        let mut weight_sum = 0.0;
+
+        neighborhood_traits.for_each_cell(neighborhood_traits.neighbor_offsets(), cell_r, cell_c, grid, |offset, idx| {
+            if !grid.is_empty[idx] {
+                sum += values[idx] * offset.distance_weight;
+                weight_sum += offset.distance_weight;
+            }
+        });
+
+        if weight_sum == 0.0 {
+            values[cell_r * grid.width + cell_c]
+        } else {
+            sum / weight_sum
+        }
+    }
+
+    /// Mean weighted by the kernel of the neighborhood (center included)
+    #[inline(always)]
+    pub fn kernel_average(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood_traits: &Neighborhood, grid: &Grid, _rng: &mut RuleRng) -> f32 {
+        neighborhood_traits.kernel_mean(trait_index, cell_r, cell_c, grid)
+            .unwrap_or_else(|| grid.get_cell_trait(cell_r, cell_c, trait_index))
+    }
+
+    /// Smooth diffusion: the value moves toward the kernel-weighted mean at `rate`
+    #[inline(always)]
+    pub fn kernel_diffusion(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood_traits: &Neighborhood, grid: &Grid, params: &KernelDiffusionParams, _rng: &mut RuleRng) -> f32 {
+        let current = grid.get_cell_trait(cell_r, cell_c, trait_index);
+        let mean = neighborhood_traits.kernel_mean(trait_index, cell_r, cell_c, grid).unwrap_or(current);
+        (current + params.rate * (mean - current)) * params.decay
+    }
+
+    /// Energy of the updated trait, heated by the phase differences of the `phase` input
+    pub fn energy_update(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood: &Neighborhood, grid: &Grid, params: &EnergyParams, _rng: &mut RuleRng) -> f32 {
+        let phase_trait = params.inputs[0];
+        let energies = grid.get_trait_slice(trait_index);
+        let phases = grid.get_trait_slice(phase_trait);
+        let energy = energies[cell_r * grid.width + cell_c];
+        let phase = phases[cell_r * grid.width + cell_c];
+        
+        let mut friction: f32 = 0.0;
+        let mut neighbor_energy_sum: f32 = 0.0;
+        let mut neighbor_count = 0;
+        
+        neighborhood.for_each_cell(neighborhood.neighbor_offsets(), cell_r, cell_c, grid, |_, idx| {
+            if !grid.is_empty[idx] {
+                neighbor_count += 1;
+                
+                // Phase difference creates friction (energy generation)
+                let phase_diff = (phase - phases[idx]).abs();
+                let cyclic_diff = phase_diff.min(1.0 - phase_diff);
+                friction += cyclic_diff;
+                
+                neighbor_energy_sum += energies[idx];
+            }
+        });
+        
+        let mut new_energy = energy;
+        
+        // Small constant decay
+        new_energy -= params.decay;
+        
+        if neighbor_count > 0 {
+            // Friction generates energy (phase differences)
+            let avg_friction = friction / neighbor_count as f32;
+            new_energy += avg_friction * params.friction;
+            
+            // Diffusion: average with neighbors
+            let avg_neighbor_energy = neighbor_energy_sum / neighbor_count as f32;
+            new_energy = new_energy * params.self_weight + avg_neighbor_energy * params.neighbor_weight;
+        }
+        
+        new_energy
+    }
+
+    /// Charge of the updated trait, driven by the neighbors of higher and lower `energy` input
+    pub fn charge_update(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood: &Neighborhood, grid: &Grid, params: &ChargeParams, _rng: &mut RuleRng) -> f32 {
+        let energy_trait = params.inputs[0];
+        let charges = grid.get_trait_slice(trait_index);
+        let energies = grid.get_trait_slice(energy_trait);
+        let charge = charges[cell_r * grid.width + cell_c];
+        let energy = energies[cell_r * grid.width + cell_c];
+        
+        let mut high_energy_charge_sum: f32 = 0.0;
+        let mut high_energy_count = 0;
+        let mut low_energy_charge_sum: f32 = 0.0;
+        let mut low_energy_count = 0;
+        
+        neighborhood.for_each_cell(neighborhood.neighbor_offsets(), cell_r, cell_c, grid, |_, idx| {
+            if !grid.is_empty[idx] {
+                if energies[idx] > energy {
+                    high_energy_charge_sum += charges[idx];
+                    high_energy_count += 1;
+                } else {
+                    low_energy_charge_sum += charges[idx];
+                    low_energy_count += 1;
+                }
+            }
+        });
+        
+        let mut new_charge = charge;
+        
+        // Move toward charge of higher-energy neighbors
+        // Move away from charge of lower-energy neighbors
+        if high_energy_count > 0 {
+            let high_avg = high_energy_charge_sum / high_energy_count as f32;
+            new_charge += (high_avg - charge) * params.attraction;
+        }
+        
+        if low_energy_count > 0 {
+            let low_avg = low_energy_charge_sum / low_energy_count as f32;
+            new_charge -= (low_avg - charge) * params.repulsion; // Weaker repulsion by default
+        }
+        
+        new_charge
+    }
+
+    /// Phase of the updated trait, synchronised by similar values of the `charge` input.
+    /// Phases live on a cycle of length 1, give this trait a cyclic [0, 1] domain
+    pub fn phase_update(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood: &Neighborhood, grid: &Grid, params: &PhaseParams, _rng: &mut RuleRng) -> f32 {
+        let charge_trait = params.inputs[0];
+        let phases = grid.get_trait_slice(trait_index);
+        let charges = grid.get_trait_slice(charge_trait);
+        let phase = phases[cell_r * grid.width + cell_c];
+        let charge = charges[cell_r * grid.width + cell_c];
+        
+        let mut sync_pull: f32 = 0.0;
+        let mut neighbor_count = 0;
+        
+        neighborhood.for_each_cell(neighborhood.neighbor_offsets(), cell_r, cell_c, grid, |_, idx| {
+            if !grid.is_empty[idx] {
+                neighbor_count += 1;
+                
+                // Phase difference (cyclic)
+                let diff = phases[idx] - phase;
+                let cyclic_diff = if diff > 0.5 {
+                    diff - 1.0
+                } else if diff < -0.5 {
+                    diff + 1.0
+                } else {
+                    diff
+                };
+                
+                // Similar charge = sync together
+                // Opposite charge = anti-sync (push phases apart)
+                let charge_similarity = 1.0 - (charge - charges[idx]).abs();
+                let coupling = (charge_similarity - 0.5) * 2.0; // -1 to 1
+                
+                sync_pull += cyclic_diff * coupling;
+            }
+        });
+        
+        // Natural advance
+        let mut new_phase = phase + params.advance;
+        
+        // Apply sync/anti-sync
+        if neighbor_count > 0 {
+            new_phase += (sync_pull / neighbor_count as f32) * params.coupling;
+        }
+        
+        new_phase
+    }
+
+    /// Life-like rule (counting over the mask) where each birth and survival happens with its probability
+    pub fn probabilistic_life(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood_traits: &Neighborhood, grid: &Grid, params: &ProbabilisticLifeParams, rng: &mut RuleRng) -> f32 {
+        if Self::life_like(trait_index, cell_r, cell_c, neighborhood_traits, grid, &params.life, rng) == 0.0 {
+            return 0.0;
+        }
+        let probability = if grid.get_cell_trait(cell_r, cell_c, trait_index) > 0.5 {
+            params.survival_probability
+        } else {
+            params.birth_probability
+        };
+        (rng.r#gen::<f32>() < probability) as u8 as f32
+    }
+
+    /// Noisy voter model: copies the value of a random non-empty neighbor of the mask,
+    /// or with probability `noise` takes a uniform random value in [0, 1)
+    pub fn voter(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood_traits: &Neighborhood, grid: &Grid, params: &VoterParams, rng: &mut RuleRng) -> f32 {
+        if rng.r#gen::<f32>() < params.noise {
+            return rng.r#gen::<f32>();
+        }
+
+        let values = grid.get_trait_slice(trait_index);
+
+        // Reservoir sampling, a single pass over the mask
+        let mut value = values[cell_r * grid.width + cell_c];
+        let mut neighbor_count = 0;
+        neighborhood_traits.for_each_cell(neighborhood_traits.neighbor_offsets(), cell_r, cell_c, grid, |_, idx| {
+            if !grid.is_empty[idx] {
+                neighbor_count += 1;
+                if rng.gen_range(0..neighbor_count) == 0 {
+                    value = values[idx];
+                }
+            }
+        });
+        value
+    }
+
+    /// The value is multiplied by `factor` with probability `probability`, and kept otherwise
+    #[inline(always)]
+    pub fn random_decay(trait_index: usize, cell_r: usize, cell_c: usize, _neighborhood_traits: &Neighborhood, grid: &Grid, params: &RandomDecayParams, rng: &mut RuleRng) -> f32 {
+        let value = grid.get_cell_trait(cell_r, cell_c, trait_index);
+        if rng.r#gen::<f32>() < params.probability {
+            value * params.factor
+        } else {
+            value
+        }
+    }
+
+    /// Compiled user expression, an unbound one keeps the value
+    #[inline(always)]
+    pub fn expression(trait_index: usize, cell_r: usize, cell_c: usize, neighborhood: &Neighborhood, grid: &Grid, params: &ExpressionParams, rng: &mut RuleRng) -> f32 {
+        if !params.is_bound() {
+            return grid.get_cell_trait(cell_r, cell_c, trait_index);
+        }
+        expression::evaluate(&params.code, trait_index, cell_r, cell_c, neighborhood, grid, rng)
+    }
+}
+
+
+
+
+/// Evaluates `$body` with `$p` bound to the parameters of the `$variant` rule `$rule`,
+/// or `$none` for a rule declared without parameters
+macro_rules! with_params {
+    ($rule:expr, $variant:ident, $p:ident => $body:expr, $none:expr) => {
+        $none
+    };
+    ($rule:expr, $variant:ident, $p:ident => $body:expr, $none:expr, $params:ident) => {{
+        let Rule::$variant($p) = $rule else { unreachable!() };
+        $body
+    }};
+}
+
+/// Writes `cell(col, rng)` to the non-empty cells of a row, each cell drawing from its own stream
+#[inline(always)]
+fn apply_cells(trait_index: usize, row: usize, grid: &Grid, random: StepRandom, next_row: &mut [f32], mut cell: impl FnMut(usize, &mut RuleRng) -> f32) {
+    let row_offset = row * grid.width;
+    for (col, next_val) in next_row.iter_mut().enumerate() {
+        if !grid.is_empty[row_offset + col] {
+            let mut rng = RuleRng::new(random, row_offset + col, trait_index);
+            *next_val = cell(col, &mut rng);
+        }
+    }
+}
+
+macro_rules! define_rules {
+    ($(($variant:ident, $name:expr, $func:path $(, $params:ident)?)),* $(,)?) => {
+        /// Update rule of a trait, parameterised rules carry their constants
+        #[derive(Clone, PartialEq, Debug)]
+        pub enum Rule {
+            $($variant $(($params))?),*
+        }
+        
+        impl Rule {
+            /// Every rule, with its default parameters
+            pub const ALL: &'static [Rule] = &[$(Rule::$variant $(($params::DEFAULT))?),*];
+            pub const NAMES: &'static [&'static str] = &[$($name),*];
+            
+            #[inline]
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Rule::$variant { .. } => $name),*
+                }
+            }
+            
+            /// Rule with its default parameters
+            #[inline]
+            pub fn from_name(name: &str) -> Option<Rule> {
+                match name {
+                    $($name => Some(Rule::$variant $(($params::DEFAULT))?)),*,
+                    _ => None,
+                }
+            }
+            
+            /// Raw new value of the trait at (cell_r, cell_c)
+            #[inline(always)]
+            pub fn apply(&self, trait_index: usize, cell_r: usize, cell_c: usize, neighborhood: &Neighborhood, grid: &Grid, rng: &mut RuleRng) -> f32 {
+                match self {
+                    $(Rule::$variant { .. } => with_params!(
+                        self, $variant,
+                        p => $func(trait_index, cell_r, cell_c, neighborhood, grid, p, rng),
+                        $func(trait_index, cell_r, cell_c, neighborhood, grid, rng)
+                        $(, $params)?
+                    )),*
+                }
+            }
+
+            /// New values of the non-empty cells of a row, the rule is matched once per row
+            #[inline]
+            pub fn apply_row(&self, trait_index: usize, row: usize, neighborhood: &Neighborhood, grid: &Grid, random: StepRandom, next_row: &mut [f32]) {
+                match self {
+                    $(Rule::$variant { .. } => with_params!(
+                        self, $variant,
+                        p => apply_cells(trait_index, row, grid, random, next_row, |col, rng| $func(trait_index, row, col, neighborhood, grid, p, rng)),
+                        apply_cells(trait_index, row, grid, random, next_row, |col, rng| $func(trait_index, row, col, neighborhood, grid, rng))
+                        $(, $params)?
+                    )),*
+                }
+            }
+
+            /// Names of the parameters, empty for rules without any
+            pub fn param_names(&self) -> &'static [&'static str] {
+                match self {
+                    $(Rule::$variant { .. } => with_params!(self, $variant, _p => $($params)?::NAMES, &[] $(, $params)?)),*
+                }
+            }
+
+            pub fn get_param(&self, name: &str) -> Option<f32> {
+                match self {
+                    $(Rule::$variant { .. } => with_params!(self, $variant, p => p.get(name), None $(, $params)?)),*
+                }
+            }
+
+            /// False if the rule has no parameter with this name
+            pub fn set_param(&mut self, name: &str, value: f32) -> bool {
+                match self {
+                    $(Rule::$variant { .. } => with_params!(self, $variant, p => p.set(name, value), false $(, $params)?)),*
+                }
+            }
+
+            /// Rulestring of the rules defined by one (e.g. "B36/S23" for life-like)
+            pub fn rulestring(&self) -> Option<String> {
+                match self {
+                    $(Rule::$variant { .. } => with_params!(self, $variant, p => p.rulestring(), None $(, $params)?)),*
+                }
+            }
+
+            pub fn set_rulestring(&mut self, rulestring: &str) -> Result<(), String> {
+                match self {
+                    $(Rule::$variant { .. } => with_params!(
+                        self, $variant,
+                        p => p.set_rulestring(rulestring),
+                        Err(format!("rule '{}' has no rulestring", $name))
+                        $(, $params)?
+                    )),*
+                }
+            }
+
+            /// Resolves the trait names used by the rule (expressions, inputs) to trait indices
+            pub fn bind_traits(&mut self, trait_names: &[String]) -> Result<(), String> {
+                match self {
+                    $(Rule::$variant { .. } => with_params!(self, $variant, p => p.bind_traits(trait_names), Ok(()) $(, $params)?)),*
+                }
+            }
+
+            /// Input roles of the coupled rules, empty for the others
+            pub fn input_names(&self) -> &'static [&'static str] {
+                match self {
+                    $(Rule::$variant { .. } => with_params!(self, $variant, _p => $($params)?::INPUTS, &[] $(, $params)?)),*
+                }
+            }
+
+            pub fn get_input(&self, role: &str) -> Option<usize> {
+                match self {
+                    $(Rule::$variant { .. } => with_params!(self, $variant, p => p.get_input(role), None $(, $params)?)),*
+                }
+            }
+
+            /// False if the rule has no input with this role
+            pub fn set_input(&mut self, role: &str, trait_name: &str) -> bool {
+                match self {
+                    $(Rule::$variant { .. } => with_params!(self, $variant, p => p.set_input(role, trait_name), false $(, $params)?)),*
+                }
+            }
+        }
+    };
+}
+
+// ============================================================
+// ADD NEW RULES HERE - Just add one line!
+// Format: (EnumVariant, "display name", RuleFunction::function_name[, ParamsStruct])
+// A params struct (see `define_params!`) is passed to the function as last argument
+// Other crates implement `CellRule` instead and call `register_rule`
+// ============================================================
+define_rules!(
+    (Static,          "static",           RuleFunction::static_rule),
+    (Average,         "average",          RuleFunction::average),
+    (Conway,          "conway",           RuleFunction::conway),
+    (ConwayOptimized, "conway optimized", RuleFunction::conway_optimized),
+    (Diffusion,       "diffusion",        RuleFunction::diffusion,       DiffusionParams),
+    (Maximum,         "maximum",          RuleFunction::maximum),
+    (Minimum,         "minimum",          RuleFunction::minimum),
+    (WeightedAverage, "weighted_average", RuleFunction::weighted_average),
+    (Energy,          "energy",           RuleFunction::energy_update,   EnergyParams),
+    (Charge,          "charge",           RuleFunction::charge_update,   ChargeParams),
+    (Phase,           "phase",            RuleFunction::phase_update,    PhaseParams),
+    (LifeLike,          "life-like",           RuleFunction::life_like,           LifeLikeParams),
+    (LifeLikeOptimized, "life-like optimized", RuleFunction::life_like_optimized, LifeLikeParams),
+    (Generations,       "generations",         RuleFunction::generations,         GenerationsParams),
+    (Expression,        "expression",          RuleFunction::expression,          ExpressionParams),
+    (ProbabilisticLife, "probabilistic life",  RuleFunction::probabilistic_life,  ProbabilisticLifeParams),
+    (Voter,             "voter",               RuleFunction::voter,               VoterParams),
+    (RandomDecay,       "random decay",        RuleFunction::random_decay,        RandomDecayParams),
+    (KernelAverage,     "kernel average",      RuleFunction::kernel_average),
+    (KernelDiffusion,   "kernel diffusion",    RuleFunction::kernel_diffusion,    KernelDiffusionParams),
+    // Add new rules here:
+);
+
+
+/// Update rule of a trait as stored by the registry. The built-in `Rule`s implement it,
+/// rules of other crates too: `register_rule` then makes them available by name to the
+/// config files, the checkpoints and the UI. A rule value holds its parameters and any state
+/// it needs, `prepare` refreshes that state once per timestep
+pub trait CellRule: Any + Debug + Send + Sync + CloneRule {
+    /// Key of the rule in the config files and checkpoints
+    fn name(&self) -> &'static str;
+
+    /// Raw new value of the trait at (cell_r, cell_c), the registry maps it into the trait domain.
+    /// Stochastic rules draw from `rng`, reproducible for a given seed, timestep, cell and trait
+    fn apply(&self, trait_index: usize, cell_r: usize, cell_c: usize, neighborhood: &Neighborhood, grid: &Grid, rng: &mut RuleRng) -> f32;
+
+    /// Raw new values of the non-empty cells of row `row`, the other entries of `next_row` are left as is.
+    /// The registry updates the grid row by row, so the per-cell calls are not dynamic
+    #[inline]
+    fn apply_row(&self, trait_index: usize, row: usize, neighborhood: &Neighborhood, grid: &Grid, random: StepRandom, next_row: &mut [f32]) {
+        apply_cells(trait_index, row, grid, random, next_row, |col, rng| self.apply(trait_index, row, col, neighborhood, grid, rng));
+    }
+
+    /// Called before the cells of the trait are updated, every timestep
+    fn prepare(&mut self, _trait_index: usize, _grid: &Grid) {}
+
+    /// Called instead of `prepare` when the update mode only calls `apply`, cell by cell,
+    /// rules can skip there what only `apply_row` reads
+    fn prepare_cells(&mut self, trait_index: usize, grid: &Grid) {
+        self.prepare(trait_index, grid);
+    }
+
+    /// Names of the f32 parameters
+    fn param_names(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    fn get_param(&self, _name: &str) -> Option<f32> {
+        None
+    }
+
+    /// False if the rule has no parameter with this name
+    fn set_param(&mut self, _name: &str, _value: f32) -> bool {
+        false
+    }
+
+    fn rulestring(&self) -> Option<String> {
+        None
+    }
+
+    fn set_rulestring(&mut self, _rulestring: &str) -> Result<(), String> {
+        Err(format!("rule '{}' has no rulestring", self.name()))
+    }
+
+    /// Resolves the trait names used by the rule to trait indices
+    fn bind_traits(&mut self, _trait_names: &[String]) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Input roles, the traits read by the rule besides the one it updates
+    fn input_names(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Index of the trait read for `role`
+    fn get_input(&self, _role: &str) -> Option<usize> {
+        None
+    }
+
+    /// Reads `role` from the trait named `trait_name` once bound, false if there is no such role
+    fn set_input(&mut self, _role: &str, _trait_name: &str) -> bool {
+        false
+    }
+
+    /// (dr, dc) of the cells read around the updated one, those of `neighborhood` unless the rule
+    /// reads a window of its own. The checkerboard mode never updates together two cells one of them reads
+    fn read_offsets(&self, neighborhood: &Neighborhood) -> Vec<(isize, isize)> {
+        neighborhood.offsets().iter().map(|offset| (offset.dr, offset.dc)).collect()
+    }
+
+    /// State a checkpoint must keep besides the parameters, rulestring and inputs to resume the run exactly.
+    /// Rules that rebuild everything else in `prepare` keep the default, empty state
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restores the state written by `save_state`, on a new instance with the saved parameters
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        if state.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("rule '{}' has no state to load", self.name()))
+        }
+    }
+
+    /// Name and value of every parameter
+    fn params(&self) -> Vec<(&'static str, f32)> {
+        self.param_names()
+            .iter()
+            .filter_map(|&name| self.get_param(name).map(|value| (name, value)))
+            .collect()
+    }
+}
+
+/// Boxed copy of a rule, implemented for every `Clone` rule
+pub trait CloneRule {
+    fn clone_rule(&self) -> Box<dyn CellRule>;
+}
+
+impl<T: CellRule + Clone> CloneRule for T {
+    fn clone_rule(&self) -> Box<dyn CellRule> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn CellRule> {
+    fn clone(&self) -> Self {
+        (**self).clone_rule()
+    }
+}
+
+impl dyn CellRule {
+    /// The rule as its concrete type, e.g. `downcast_ref::<Rule>()` for the built-in rules
+    pub fn downcast_ref<T: CellRule>(&self) -> Option<&T> {
+        (self as &dyn Any).downcast_ref()
+    }
+}
+
+impl From<Rule> for Box<dyn CellRule> {
+    fn from(rule: Rule) -> Self {
+        Box::new(rule)
+    }
+}
+
+impl CellRule for Rule {
+    #[inline]
+    fn name(&self) -> &'static str {
+        Rule::name(self)
+    }
+
+    #[inline(always)]
+    fn apply(&self, trait_index: usize, cell_r: usize, cell_c: usize, neighborhood: &Neighborhood, grid: &Grid, rng: &mut RuleRng) -> f32 {
+        Rule::apply(self, trait_index, cell_r, cell_c, neighborhood, grid, rng)
+    }
+
+    fn apply_row(&self, trait_index: usize, row: usize, neighborhood: &Neighborhood, grid: &Grid, random: StepRandom, next_row: &mut [f32]) {
+        Rule::apply_row(self, trait_index, row, neighborhood, grid, random, next_row)
+    }
+
+    /// The optimized life rules read the cells adjacent on the lattice whatever the neighborhood,
+    /// within the 3x3 Moore window
+    fn read_offsets(&self, neighborhood: &Neighborhood) -> Vec<(isize, isize)> {
+        let neighborhood = match self {
+            Rule::ConwayOptimized | Rule::LifeLikeOptimized(_) => &Neighborhood::moore(1),
+            _ => neighborhood,
+        };
+        neighborhood.offsets().iter().map(|offset| (offset.dr, offset.dc)).collect()
+    }
+
+    fn param_names(&self) -> &'static [&'static str] {
+        Rule::param_names(self)
+    }
+
+    fn get_param(&self, name: &str) -> Option<f32> {
+        Rule::get_param(self, name)
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        Rule::set_param(self, name, value)
+    }
+
+    fn rulestring(&self) -> Option<String> {
+        Rule::rulestring(self)
+    }
+
+    fn set_rulestring(&mut self, rulestring: &str) -> Result<(), String> {
+        Rule::set_rulestring(self, rulestring)
+    }
+
+    fn bind_traits(&mut self, trait_names: &[String]) -> Result<(), String> {
+        Rule::bind_traits(self, trait_names)
+    }
+
+    fn input_names(&self) -> &'static [&'static str] {
+        Rule::input_names(self)
+    }
+
+    fn get_input(&self, role: &str) -> Option<usize> {
+        Rule::get_input(self, role)
+    }
+
+    fn set_input(&mut self, role: &str, trait_name: &str) -> bool {
+        Rule::set_input(self, role, trait_name)
+    }
+}
+
+
+/// Larger than Life: live cells counted over the radius-r window of the params, a Moore or von Neumann
+/// neighborhood built with the rule (the Moore square being a hexagon on a hexagonal lattice),
+/// compiled for the grid by `prepare`, and read instead of the neighborhood of the trait
+#[derive(Clone, PartialEq, Debug)]
+pub struct LargerThanLife {
+    params: LargerThanLifeParams,
+    window: Neighborhood,
+    /// Lattice of the window, the one of the last grid prepared
+    lattice: Lattice,
+}
+
+impl Default for LargerThanLife {
+    fn default() -> Self {
+        Self::new(LargerThanLifeParams::DEFAULT)
+    }
+}
+
+impl LargerThanLife {
+    pub fn new(params: LargerThanLifeParams) -> Self {
+        Self { params, window: Self::window(&params, Lattice::Square), lattice: Lattice::Square }
+    }
+
+    #[inline]
+    pub fn params(&self) -> &LargerThanLifeParams {
+        &self.params
+    }
+
+    fn window(params: &LargerThanLifeParams, lattice: Lattice) -> Neighborhood {
+        let radius = params.radius as usize;
+        match lattice {
+            _ if params.von_neumann => Neighborhood::von_neumann(radius),
+            Lattice::Square => Neighborhood::moore(radius),
+            Lattice::Hexagonal => Neighborhood::hexagonal(radius),
+        }
+    }
+}
+
+impl CellRule for LargerThanLife {
+    fn name(&self) -> &'static str {
+        "larger than life"
+    }
+
+    fn apply(&self, trait_index: usize, cell_r: usize, cell_c: usize, _neighborhood: &Neighborhood, grid: &Grid, _rng: &mut RuleRng) -> f32 {
+        let states = self.params.states;
+        let values = grid.get_trait_slice(trait_index);
+        let offsets = if self.params.include_center { self.window.offsets() } else { self.window.neighbor_offsets() };
+        let mut alive_neighbors = 0;
+
+        self.window.for_each_cell(offsets, cell_r, cell_c, grid, |_, idx| {
+            if !grid.is_empty[idx] && states.state(values[idx]) == 1 {
+                alive_neighbors += 1;
+            }
+        });
+
+        let in_interval = |(min, max): (u32, u32)| (min..=max).contains(&alive_neighbors);
+        let state = states.state(values[cell_r * grid.width + cell_c]);
+        states.level(states.next(state, in_interval(self.params.birth), in_interval(self.params.survival)))
+    }
+
+    /// Window of the lattice of the grid, and its flat offsets read away from the borders without boundary lookups
+    fn prepare(&mut self, _trait_index: usize, grid: &Grid) {
+        if self.lattice != grid.lattice {
+            self.lattice = grid.lattice;
+            self.window = Self::window(&self.params, self.lattice);
+        }
+        self.window.compile(grid);
+    }
+
+    fn read_offsets(&self, _neighborhood: &Neighborhood) -> Vec<(isize, isize)> {
+        self.window.offsets().iter().map(|offset| (offset.dr, offset.dc)).collect()
+    }
+
+    fn rulestring(&self) -> Option<String> {
+        self.params.rulestring()
+    }
+
+    fn set_rulestring(&mut self, rulestring: &str) -> Result<(), String> {
+        *self = Self::new(LargerThanLifeParams::parse(rulestring)?);
+        Ok(())
+    }
+}
+
+
+/// Lenia on a continuous trait in [0, 1]: the potential U is the trait convolved with a smooth ring
+/// kernel of `radius` cells, read instead of the neighborhood, and the value grows by dt * G(U),
+/// G(u) = 2 exp(-(u - mu)² / 2 sigma²) - 1. The synchronous modes convolve the whole trait once
+/// per timestep in `prepare` (by FFT for large kernels), the others each cell as it is updated
+#[derive(Clone, Debug)]
+pub struct Lenia {
+    params: LeniaParams,
+    convolution: Convolution,
+    /// Lattice of the distances in the kernel, the one of the last grid prepared
+    lattice: Lattice,
+    /// Potential of every cell, computed by `prepare`
+    potential: Vec<f32>,
+}
+
+impl Default for Lenia {
+    fn default() -> Self {
+        Self::new(LeniaParams::DEFAULT)
+    }
+}
+
+impl Lenia {
+    pub fn new(params: LeniaParams) -> Self {
+        let convolution = Self::kernel(&params, Lattice::Square);
+        Self { params, convolution, lattice: Lattice::Square, potential: Vec::new() }
+    }
+
+    #[inline]
+    pub fn params(&self) -> &LeniaParams {
+        &self.params
+    }
+
+    /// Ring kernel exp(4 - 1 / (d (1 - d))) of the normalized distance d on the lattice, summing to 1
+    fn kernel(params: &LeniaParams, lattice: Lattice) -> Convolution {
+        let radius = params.radius.round().max(1.0) as usize;
+        let side = 2 * radius + 1;
+        let mut weights: Vec<f32> = (0..side * side)
+            .map(|i| {
+                let dr = (i / side) as isize - radius as isize;
+                let dc = (i % side) as isize - radius as isize;
+                let d = lattice.distance(dr, dc) / radius as f32;
+                if d > 0.0 && d < 1.0 { (4.0 - 1.0 / (d * (1.0 - d))).exp() } else { 0.0 }
+            })
+            .collect();
+        let sum: f32 = weights.iter().sum();
+        if sum > 0.0 {
+            weights.iter_mut().for_each(|weight| *weight /= sum);
+        }
+        Convolution::new(radius, weights)
+    }
+
+    /// Ring of the lattice of the grid
+    fn follow_lattice(&mut self, grid: &Grid) {
+        if self.lattice != grid.lattice {
+            self.lattice = grid.lattice;
+            self.convolution = Self::kernel(&self.params, self.lattice);
+        }
+    }
+
+    #[inline(always)]
+    fn grow(&self, value: f32, potential: f32) -> f32 {
+        let LeniaParams { mu, sigma, dt, .. } = self.params;
+        let growth = 2.0 * (-(potential - mu).powi(2) / (2.0 * sigma * sigma)).exp() - 1.0;
+        (value + dt * growth).clamp(0.0, 1.0)
+    }
+}
+
+impl CellRule for Lenia {
+    fn name(&self) -> &'static str {
+        "lenia"
+    }
+
+    fn apply(&self, trait_index: usize, cell_r: usize, cell_c: usize, _neighborhood: &Neighborhood, grid: &Grid, _rng: &mut RuleRng) -> f32 {
+        let potential = self.convolution.at(grid, trait_index, cell_r, cell_c);
+        self.grow(grid.get_cell_trait(cell_r, cell_c, trait_index), potential)
+    }
+
+    fn apply_row(&self, trait_index: usize, row: usize, neighborhood: &Neighborhood, grid: &Grid, random: StepRandom, next_row: &mut [f32]) {
+        if self.potential.len() != grid.num_cells {
+            apply_cells(trait_index, row, grid, random, next_row, |col, rng| self.apply(trait_index, row, col, neighborhood, grid, rng));
+            return;
+        }
+        let row_offset = row * grid.width;
+        let values = &grid.get_trait_slice(trait_index)[row_offset..row_offset + grid.width];
+        let potential = &self.potential[row_offset..row_offset + grid.width];
+        for (col, next_val) in next_row.iter_mut().enumerate() {
+            if !grid.is_empty[row_offset + col] {
+                *next_val = self.grow(values[col], potential[col]);
+            }
+        }
+    }
+
+    fn prepare(&mut self, trait_index: usize, grid: &Grid) {
+        self.follow_lattice(grid);
+        self.convolution.apply(grid, trait_index, &mut self.potential);
+    }
+
+    /// `apply` convolves around each cell, the potential of the whole grid would go unread
+    fn prepare_cells(&mut self, _trait_index: usize, grid: &Grid) {
+        self.follow_lattice(grid);
+        self.potential.clear();
+    }
+
+    fn read_offsets(&self, _neighborhood: &Neighborhood) -> Vec<(isize, isize)> {
+        self.convolution.offsets().collect()
+    }
+
+    fn param_names(&self) -> &'static [&'static str] {
+        LeniaParams::NAMES
+    }
+
+    fn get_param(&self, name: &str) -> Option<f32> {
+        self.params.get(name)
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        if !self.params.set(name, value) {
+            return false;
+        }
+        if name == "radius" {
+            self.convolution = Self::kernel(&self.params, self.lattice);
+            self.potential.clear();
+        }
+        true
+    }
+}
+
+
+/// Rules available by name with their default state, the built-in rules first
+static CATALOG: LazyLock<RwLock<Vec<Box<dyn CellRule>>>> =
+    LazyLock::new(|| {
+        let mut catalog: Vec<Box<dyn CellRule>> = Rule::ALL.iter().cloned().map(Box::from).collect();
+        catalog.push(Box::new(LargerThanLife::default()));
+        catalog.push(Box::new(Lenia::default()));
+        RwLock::new(catalog)
+    });
+
+/// Makes `rule` available under its name, its current state being the defaults of new instances.
+/// Checkpoints find their rules by name, so register before loading one that uses the rule.
+/// The catalog is global to the process: fails if the name is taken
+pub fn register_rule(rule: impl CellRule) -> Result<(), String> {
+    let mut catalog = CATALOG.write().unwrap();
+    if catalog.iter().any(|known| known.name() == rule.name()) {
+        return Err(format!("a rule named '{}' is already registered", rule.name()));
+    }
+    catalog.push(Box::new(rule));
+    Ok(())
+}
+
+/// New instance of the registered rule `name`, with its default state
+pub fn create_rule(name: &str) -> Option<Box<dyn CellRule>> {
+    CATALOG.read().unwrap().iter().find(|rule| rule.name() == name).cloned()
+}
+
+/// Names of the registered rules, in registration order
+pub fn rule_names() -> Vec<&'static str> {
+    CATALOG.read().unwrap().iter().map(|rule| rule.name()).collect()
+}
+
+
+/// Rule entry of the config files, the name of a registered rule or a table with the name and some parameters:
+/// `"diffusion"` or `{ name = "diffusion", decay = 0.99 }`.
+/// A bare rulestring stands for the rule it describes: `"B36/S23"` (life-like), `"B2/S/C3"` (generations)
+/// or `"R5,C0,M1,S34..58,B34..45,NM"` (larger than life).
+/// Expression rules give their source as `expression`: `{ name = "expression", expression = "mean(nbr)" }`,
+/// coupled rules name the traits of their inputs: `{ name = "charge", inputs = { energy = "Heat" } }`
+#[derive(Clone, Debug)]
+pub enum RuleSpec {
+    Name(String),
+    Table {
+        name: String,
+        rulestring: Option<String>,
+        inputs: BTreeMap<String, String>,
+        params: BTreeMap<String, f32>,
+    },
+}
+
+/// Hand-written so that a malformed entry names its rule and key
+/// instead of failing with the generic error of an untagged enum
+impl<'de> Deserialize<'de> for RuleSpec {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SpecVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for SpecVisitor {
+            type Value = RuleSpec;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a rule name, a rulestring or a table with the name of the rule and its parameters")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, name: &str) -> Result<RuleSpec, E> {
+                Ok(RuleSpec::Name(name.to_string()))
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<RuleSpec, A::Error> {
+                use serde::de::Error;
+                let mut name: Option<String> = None;
+                let mut rulestring = None;
+                let mut inputs = BTreeMap::new();
+                let mut params = BTreeMap::new();
+                let rule = |name: &Option<String>| {
+                    name.as_ref().map_or(String::new(), |name| format!(" of rule '{}'", name))
+                };
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "name" => {
+                            name = Some(map.next_value().map_err(|e| A::Error::custom(format!("'name' {}", e)))?);
+                        }
+                        "rulestring" | "expression" => {
+                            rulestring = Some(map.next_value::<String>().map_err(|e| {
+                                A::Error::custom(format!("'{}'{} must be a string: {}", key, rule(&name), e))
+                            })?);
+                        }
+                        "inputs" => {
+                            inputs = map.next_value().map_err(|e| {
+                                A::Error::custom(format!(
+                                    "'inputs'{} must be a table of trait names: {}", rule(&name), e
+                                ))
+                            })?;
+                        }
+                        _ => {
+                            let value = map.next_value::<f32>().map_err(|e| {
+                                A::Error::custom(format!("parameter '{}'{} must be a number: {}", key, rule(&name), e))
+                            })?;
+                            params.insert(key, value);
+                        }
                     }
                 }
+                let name = name.ok_or_else(|| A::Error::custom(format!(
+                    "rule table without a 'name', valid rules are: {:?}", rule_names()
+                )))?;
+                Ok(RuleSpec::Table { name, rulestring, inputs, params })
             }
         }
-        
-        // Natural advance
-        let mut new_phase = phase + 0.07;
-        
-        // Apply sync/anti-sync
-        if neighbor_count > 0 {
-            new_phase += (sync_pull / neighbor_count as f32) * 0.12;
-        }
-        
-        new_phase.rem_euclid(1.0)
+
+        deserializer.deserialize_any(SpecVisitor)
     }
 }
 
-
-
-
-macro_rules! define_rules {
-    ($(($variant:ident, $name:expr, $func:path)),* $(,)?) => {
-        #[derive(Clone, Copy, PartialEq, Eq, Debug)]
-        pub enum Rule {
-            $($variant),*
-        }
-        
-        impl Rule {
-            pub const ALL: &'static [Rule] = &[$(Rule::$variant),*];
-            pub const NAMES: &'static [&'static str] = &[$($name),*];
-            
-            #[inline]
-            pub fn name(&self) -> &'static str {
-                match self {
-                    $(Rule::$variant => $name),*
+impl RuleSpec {
+    pub fn to_rule(&self) -> Result<Box<dyn CellRule>, String> {
+        let (name, rulestring, params, inputs) = match self {
+            RuleSpec::Name(name) => {
+                if create_rule(name).is_none() {
+                    if let Ok(params) = LifeLikeParams::parse(name) {
+                        return Ok(Rule::LifeLike(params).into());
+                    }
+                    if let Ok(params) = GenerationsParams::parse(name) {
+                        return Ok(Rule::Generations(params).into());
+                    }
+                    if let Ok(params) = LargerThanLifeParams::parse(name) {
+                        return Ok(Box::new(LargerThanLife::new(params)));
+                    }
                 }
+                (name, None, None, None)
             }
-            
-            #[inline]
-            pub fn from_name(name: &str) -> Option<Rule> {
-                match name {
-                    $($name => Some(Rule::$variant)),*,
-                    _ => None,
-                }
+            RuleSpec::Table { name, rulestring, params, inputs } => (name, rulestring.as_ref(), Some(params), Some(inputs)),
+        };
+        let mut rule = create_rule(name)
+            .ok_or_else(|| format!("Unknown rule: '{}'. Valid rules are: {:?}", name, rule_names()))?;
+        if let Some(rulestring) = rulestring {
+            rule.set_rulestring(rulestring)?;
+        }
+        for (param, &value) in params.into_iter().flatten() {
+            if !rule.set_param(param, value) {
+                return Err(format!(
+                    "Unknown parameter '{}' for rule '{}'. Valid parameters are: {:?}",
+                    param, name, rule.param_names()
+                ));
             }
-            
-            #[inline]
-            pub fn get_fn(&self) -> RuleFnType {
-                match self {
-                    $(Rule::$variant => $func),*
-                }
+        }
+        for (role, trait_name) in inputs.into_iter().flatten() {
+            if !rule.set_input(role, trait_name) {
+                return Err(format!(
+                    "Unknown input '{}' for rule '{}'. Valid inputs are: {:?}",
+                    role, name, rule.input_names()
+                ));
             }
         }
-    };
+        Ok(rule)
+    }
 }
 
-// ============================================================
-// ADD NEW RULES HERE - Just add one line!
-// Format: (EnumVariant, "display name", RuleFunction::function_name)
-// ============================================================
-define_rules!(
-    (Static,          "static",           RuleFunction::static_rule),
-    (Average,         "average",          RuleFunction::average),
-    (Conway,          "conway",           RuleFunction::conway),
-    (ConwayOptimized, "conway optimized", RuleFunction::conway_optimized),
-    (Diffusion,       "diffusion",        RuleFunction::diffusion),
-    (Maximum,         "maximum",          RuleFunction::maximum),
-    (Minimum,         "minimum",          RuleFunction::minimum),
-    (WeightedAverage, "weighted_average", RuleFunction::weighted_average),
-    (Energy,          "energy",           RuleFunction::energy_update),
-    (Charge,          "charge",           RuleFunction::charge_update),
-    (Phase,           "phase",            RuleFunction::phase_update),
-    // Add new rules here:
-);
-
-pub type RuleFnType = fn(usize, usize, usize, &Neighborhood, &Grid) -> f32;
-
 
+/// Rules return raw values, the registry then maps them into the domain of their trait.
+/// Expression rules are compiled against the trait names of the registry
 #[derive(Clone)]
 pub struct RulesRegistry {
-    rules: Vec<RuleFnType>,
-    rule_types: Vec<Rule>,
+    rule_types: Vec<Box<dyn CellRule>>,
+    domains: Vec<Domain>,
+    /// Own neighborhood of each trait, the one passed to the rules is used when None
+    neighborhoods: Vec<Option<Neighborhood>>,
+    trait_names: Vec<String>,
 }
 
 impl RulesRegistry {
     pub fn default(num_traits: usize) -> Self {
         Self {
-            rules: vec![RuleFunction::average; num_traits],
-            rule_types: vec![Rule::Average; num_traits],
+            rule_types: vec![Rule::Average.into(); num_traits],
+            domains: vec![Domain::default(); num_traits],
+            neighborhoods: vec![None; num_traits],
+            trait_names: default_traits_names(num_traits),
         }
     }
     
-    pub fn custom(rule_types: Vec<Rule>) -> Self {
-        let rules = rule_types.iter().map(|rt| rt.get_fn()).collect();
-        Self { rules, rule_types }
+    /// Expression rules stay unbound until `set_trait_names`
+    pub fn custom<R: Into<Box<dyn CellRule>>>(rule_types: impl IntoIterator<Item = R>) -> Self {
+        let rule_types: Vec<Box<dyn CellRule>> = rule_types.into_iter().map(Into::into).collect();
+        let domains = vec![Domain::default(); rule_types.len()];
+        let neighborhoods = vec![None; rule_types.len()];
+        let trait_names = default_traits_names(rule_types.len());
+        Self { rule_types, domains, neighborhoods, trait_names }
     }
     
     #[inline(always)]
-    pub fn apply_rule(&self, trait_index: usize, cell_r: usize, cell_c: usize, neighborhood: &Neighborhood, grid: &Grid, ) -> f32 {
-        let rule = unsafe { *self.rules.get_unchecked(trait_index) };
-        rule(trait_index, cell_r, cell_c, neighborhood, grid)
+    pub fn apply_rule(&self, trait_index: usize, cell_r: usize, cell_c: usize, neighborhood: &Neighborhood, grid: &Grid, random: StepRandom) -> f32 {
+        let mut rng = RuleRng::new(random, cell_r * grid.width + cell_c, trait_index);
+        self.apply_rule_with(trait_index, cell_r, cell_c, neighborhood, grid, &mut rng)
+    }
+
+    /// Same as `apply_rule`, drawing from the given generator (e.g. a cell updated several times per timestep)
+    #[inline(always)]
+    pub fn apply_rule_with(&self, trait_index: usize, cell_r: usize, cell_c: usize, neighborhood: &Neighborhood, grid: &Grid, rng: &mut RuleRng) -> f32 {
+        let rule = unsafe { self.rule_types.get_unchecked(trait_index) };
+        let domain = unsafe { self.domains.get_unchecked(trait_index) };
+        let neighborhood = self.neighborhood(trait_index, neighborhood);
+        domain.apply(rule.apply(trait_index, cell_r, cell_c, neighborhood, grid, rng))
+    }
+
+    /// Neighborhood read by the rule of the trait, `global` unless the trait has its own
+    #[inline(always)]
+    pub fn neighborhood<'a>(&'a self, trait_index: usize, global: &'a Neighborhood) -> &'a Neighborhood {
+        match unsafe { self.neighborhoods.get_unchecked(trait_index) } {
+            Some(neighborhood) => neighborhood,
+            None => global,
+        }
+    }
+
+    /// New values of row `row` of the trait, empty cells keep their value
+    #[inline]
+    pub fn apply_rule_row(&self, trait_index: usize, row: usize, neighborhood: &Neighborhood, grid: &Grid, random: StepRandom, next_row: &mut [f32]) {
+        let row_offset = row * grid.width;
+        next_row.copy_from_slice(&grid.get_trait_slice(trait_index)[row_offset..row_offset + grid.width]);
+        let neighborhood = self.neighborhood(trait_index, neighborhood);
+        self.rule_types[trait_index].apply_row(trait_index, row, neighborhood, grid, random, next_row);
+        let domain = self.domains[trait_index];
+        for (col, next_val) in next_row.iter_mut().enumerate() {
+            if !grid.is_empty[row_offset + col] {
+                *next_val = domain.apply(*next_val);
+            }
+        }
+    }
+
+    /// Lets the rules of the active traits refresh their state for the update mode, before the cells
+    /// are updated, and compiles the own neighborhoods for the grid
+    pub fn prepare(&mut self, active_traits: &[usize], grid: &Grid, update_mode: UpdateMode) {
+        for &trait_index in active_traits {
+            let rule = &mut self.rule_types[trait_index];
+            if update_mode.updates_rows() {
+                rule.prepare(trait_index, grid);
+            } else {
+                rule.prepare_cells(trait_index, grid);
+            }
+        }
+        for neighborhood in self.neighborhoods.iter_mut().flatten() {
+            neighborhood.compile(grid);
+        }
+    }
+
+    /// (dr, dc) of the cells read by the rules of the active traits, sorted and without duplicates
+    pub fn read_offsets(&self, active_traits: &[usize], global: &Neighborhood) -> Vec<(isize, isize)> {
+        let mut offsets: Vec<(isize, isize)> = active_traits
+            .iter()
+            .flat_map(|&trait_index| self.rule_types[trait_index].read_offsets(self.neighborhood(trait_index, global)))
+            .collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+        offsets
+    }
+
+    /// Names the traits and compiles every rule against them, nothing changes on error
+    pub fn set_trait_names(&mut self, trait_names: &[String]) -> Result<(), String> {
+        let mut rule_types = self.rule_types.clone();
+        for rule in rule_types.iter_mut() {
+            rule.bind_traits(trait_names)?;
+        }
+        self.rule_types = rule_types;
+        self.trait_names = trait_names.to_vec();
+        Ok(())
+    }
+
+    #[inline]
+    pub fn get_trait_names(&self) -> &[String] {
+        &self.trait_names
+    }
+
+    /// Domains of the first traits (the others keep theirs)
+    pub fn set_domains(&mut self, domains: &[Domain]) {
+        for (current, domain) in self.domains.iter_mut().zip(domains) {
+            *current = *domain;
+        }
+    }
+
+    #[inline]
+    pub fn get_domain(&self, trait_index: usize) -> Domain {
+        self.domains[trait_index]
+    }
+
+    #[inline]
+    pub fn get_domains(&self) -> &[Domain] {
+        &self.domains
+    }
+
+    /// Own neighborhoods of the first traits (the others keep theirs)
+    pub fn set_neighborhoods(&mut self, neighborhoods: &[Option<Neighborhood>]) {
+        for (current, neighborhood) in self.neighborhoods.iter_mut().zip(neighborhoods) {
+            current.clone_from(neighborhood);
+        }
+    }
+
+    /// None makes the trait use the global neighborhood
+    pub fn set_neighborhood(&mut self, trait_index: usize, neighborhood: Option<Neighborhood>) {
+        self.neighborhoods[trait_index] = neighborhood;
+    }
+
+    /// Own neighborhood of the trait, None when it uses the global one
+    #[inline]
+    pub fn get_neighborhood(&self, trait_index: usize) -> Option<&Neighborhood> {
+        self.neighborhoods[trait_index].as_ref()
+    }
+
+    #[inline]
+    pub fn get_neighborhoods(&self) -> &[Option<Neighborhood>] {
+        &self.neighborhoods
     }
     
-    pub fn set_rule(&mut self, trait_idx: usize, rule_type: Rule) {
-        self.rules[trait_idx] = rule_type.get_fn();
+    /// Fails if the rule uses an unknown trait name, the trait then keeps its rule
+    pub fn set_rule(&mut self, trait_idx: usize, rule_type: impl Into<Box<dyn CellRule>>) -> Result<(), String> {
+        let mut rule_type = rule_type.into();
+        rule_type.bind_traits(&self.trait_names)?;
         self.rule_types[trait_idx] = rule_type;
+        Ok(())
+    }
+
+    /// False if the rule of the trait has no parameter with this name
+    pub fn set_rule_param(&mut self, trait_idx: usize, name: &str, value: f32) -> bool {
+        self.rule_types[trait_idx].set_param(name, value)
+    }
+
+    /// Reads `role` from the trait named `trait_name`, fails if the rule has no such input or trait
+    pub fn set_rule_input(&mut self, trait_idx: usize, role: &str, trait_name: &str) -> Result<(), String> {
+        let mut rule = self.rule_types[trait_idx].clone();
+        if !rule.set_input(role, trait_name) {
+            return Err(format!("rule '{}' has no input '{}'", rule.name(), role));
+        }
+        self.set_rule(trait_idx, rule)
+    }
+
+    pub fn set_rule_rulestring(&mut self, trait_idx: usize, rulestring: &str) -> Result<(), String> {
+        let mut rule = self.rule_types[trait_idx].clone();
+        rule.set_rulestring(rulestring)?;
+        self.set_rule(trait_idx, rule)
     }
     
     #[inline]
@@ -486,29 +2064,38 @@ impl RulesRegistry {
     }
 
     #[inline]
-    pub fn get_rule(&self, trait_index: usize) -> Rule {
-        self.rule_types[trait_index]
+    pub fn get_rule(&self, trait_index: usize) -> &dyn CellRule {
+        &*self.rule_types[trait_index]
     }
 
-    pub fn is_stored_function(&self, trait_index: usize, rule: Rule) -> bool {
-        self.rule_types[trait_index] == rule
+    #[inline]
+    pub fn get_rules(&self) -> &[Box<dyn CellRule>] {
+        &self.rule_types
+    }
+
+    /// Whether the trait uses the rule `rule_name`, whatever its parameters
+    pub fn is_stored_function(&self, trait_index: usize, rule_name: &str) -> bool {
+        self.rule_types[trait_index].name() == rule_name
     }
 
     #[inline]
-    pub fn get_name_for_rule(rule: Rule) -> &'static str {
+    pub fn get_name_for_rule(rule: &dyn CellRule) -> &'static str {
         rule.name()
     }
 
+    /// Registered rule, built-in or not
     #[inline]
-    pub fn get_rule_by_name(rule_name: &str) -> Option<Rule> {
-        Rule::from_name(rule_name)
+    pub fn get_rule_by_name(rule_name: &str) -> Option<Box<dyn CellRule>> {
+        create_rule(rule_name)
     }
 
-    #[inline(always)]
-    pub fn get_all_names() -> &'static [&'static str] {
-        Rule::NAMES
+    /// Names of the registered rules, built-in or not
+    #[inline]
+    pub fn get_all_names() -> Vec<&'static str> {
+        rule_names()
     }
 
+    /// The built-in rules
     #[inline]
     pub fn get_all_rules() -> &'static [Rule] {
         Rule::ALL
@@ -521,21 +2108,37 @@ impl RulesRegistry {
 
 #[cfg(test)]
 mod tests {
-    use crate::grid::Grid;
-    use crate::rules::{RuleFunction, RulesRegistry};
-    use crate::neighborhood::Neighborhood;
+    use crate::grid::{Boundary, Grid, Lattice};
+    use crate::rules::{
+        CellRule, DiffusionParams, ExpressionParams, GenerationsParams, LargerThanLifeParams, LifeLikeParams,
+        KernelDiffusionParams, LargerThanLife, Lenia, ProbabilisticLifeParams, RandomDecayParams, Rule, RuleFunction, RuleSpec, RulesRegistry, States, VoterParams,
+        create_rule, register_rule, rule_names,
+    };
+    use crate::neighborhood::{Kernel, Neighborhood};
+    use crate::random::{RuleRng, StepRandom};
+    use crate::traits::Domain;
+    use crate::update::UpdateMode;
+
+    fn rng() -> RuleRng {
+        RuleRng::new(StepRandom::new(0, 0), 0, 0)
+    }
+
+    /// Built-in rule out of a parsed one
+    fn builtin(rule: Result<Box<dyn CellRule>, String>) -> Rule {
+        rule.unwrap().downcast_ref::<Rule>().unwrap().clone()
+    }
 
     #[test]
     fn test_rule_average_direct() {
-        let grid = Grid::new(3, 3);
-        let mask = vec![vec![true; 3]; 3];
+        let grid = Grid::new(3, 3, 2);
+        let mask = vec![vec![1; 3]; 3];
         let neighborhood = Neighborhood::new(
             3, 3,        // width, height
             1, 1,        // center row, center col for traits
             mask,       // mask
         );
 
-        let result = RuleFunction::average(0, 1, 1, &neighborhood, &grid);
+        let result = RuleFunction::average(0, 1, 1, &neighborhood, &grid, &mut rng());
         assert!(
             (0.0..=1.0).contains(&result),
             "Average rule should produce value between 0.0 and 1.0"
@@ -544,15 +2147,15 @@ mod tests {
 
     #[test]
     fn test_rule_conway_direct() {
-        let grid = Grid::new(3, 3);
-        let mask = vec![vec![true; 3]; 3];
+        let grid = Grid::new(3, 3, 2);
+        let mask = vec![vec![1; 3]; 3];
         let neighborhood = Neighborhood::new(
             3, 3,        // width, height
             1, 1,        // center row, center col for traits
             mask,       // mask
         );
 
-        let result = RuleFunction::conway(0, 1, 1, &neighborhood, &grid);
+        let result = RuleFunction::conway(0, 1, 1, &neighborhood, &grid, &mut rng());
         assert!(
             result == 0.0 || result == 1.0,
             "Conway rule should produce 0.0 or 1.0"
@@ -561,16 +2164,16 @@ mod tests {
 
     #[test]
     fn test_rules_registry_apply_default() {
-        let grid = Grid::new(3, 3);
-        let mask = vec![vec![true; 3]; 3];
+        let grid = Grid::new(3, 3, 2);
+        let mask = vec![vec![1; 3]; 3];
         let neighborhood = Neighborhood::new(
             3, 3,        // width, height
             1, 1,        // center row, center col for traits
             mask,       // mask
         );
 
-        let rules_registry = RulesRegistry::default();
-        let result = rules_registry.apply_rule(0, 1, 1, &neighborhood, &grid);
+        let rules_registry = RulesRegistry::default(2);
+        let result = rules_registry.apply_rule(0, 1, 1, &neighborhood, &grid, StepRandom::new(0, 0));
 
         assert!(
             (0.0..=1.0).contains(&result),
@@ -580,15 +2183,15 @@ mod tests {
 
     #[test]
     fn test_rules_registry_apply_custom() {
-        let grid = Grid::new(3, 3);
-        let mask = vec![vec![true; 3]; 3];
+        let grid = Grid::new(3, 3, 2);
+        let mask = vec![vec![1; 3]; 3];
         let neighborhood = Neighborhood::new(
             3, 3,        // width, height
             1, 1,        // center row, center col for traits
             mask,       // mask
         );
 
-        let rules_registry = RulesRegistry::custom([
+        let rules_registry = RulesRegistry::custom(vec![
             Rule::Static,
             Rule::Conway,
             Rule::Average,
@@ -601,10 +2204,632 @@ mod tests {
             Rule::Average,
         ]);
 
-        let result = rules_registry.apply_rule(1, 1, 1, &neighborhood, &grid);
+        let result = rules_registry.apply_rule(1, 1, 1, &neighborhood, &grid, StepRandom::new(0, 0));
         assert!(
             result == 0.0 || result == 1.0,
             "Custom rules registry with Conway rule should produce 0.0 or 1.0"
         );
     }
+
+    #[test]
+    fn test_registry_applies_domains() {
+        let mut grid = Grid::new(3, 3, 1);
+        for r in 0..3 {
+            for c in 0..3 {
+                grid.set_cell_trait(r, c, 0, 0.8);
+            }
+        }
+        let mask = vec![vec![1; 3]; 3];
+        let neighborhood = Neighborhood::new(3, 3, 1, 1, mask);
+
+        let mut rules_registry = RulesRegistry::custom(vec![Rule::Average]);
+        assert!((rules_registry.apply_rule(0, 1, 1, &neighborhood, &grid, StepRandom::new(0, 0)) - 0.8).abs() < 1e-6);
+
+        rules_registry.set_domains(&[Domain::Bounded(-1.0, 0.5)]);
+        assert_eq!(rules_registry.apply_rule(0, 1, 1, &neighborhood, &grid, StepRandom::new(0, 0)), 0.5);
+
+        rules_registry.set_domains(&[Domain::Cyclic(0.0, 0.5)]);
+        assert!((rules_registry.apply_rule(0, 1, 1, &neighborhood, &grid, StepRandom::new(0, 0)) - 0.3).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_registry_neighborhoods() {
+        // Cell (2, 2) of a 5x5 grid has 4 live von Neumann neighbors and 8 live Moore neighbors
+        let mut grid = Grid::new(5, 5, 2);
+        for r in 1..4 {
+            for c in 1..4 {
+                grid.set_cell_trait(r, c, 0, 1.0);
+                grid.set_cell_trait(r, c, 1, 1.0);
+            }
+        }
+        let moore = Neighborhood::from_mask(vec![vec![1; 3]; 3]);
+        let von_neumann = Neighborhood::from_mask(vec![vec![0, 1, 0], vec![1, 1, 1], vec![0, 1, 0]]);
+        let count = Rule::Expression(ExpressionParams::parse("count(nbr.self > 0)").unwrap());
+        let mut rules_registry = RulesRegistry::custom(vec![count.clone(), count]);
+        rules_registry.set_trait_names(&["A".to_string(), "B".to_string()]).unwrap();
+        rules_registry.set_domains(&[Domain::Unbounded, Domain::Unbounded]);
+        rules_registry.set_neighborhoods(&[None, Some(von_neumann.clone())]);
+
+        assert_eq!(rules_registry.get_neighborhood(1), Some(&von_neumann));
+        assert_eq!(rules_registry.apply_rule(0, 2, 2, &moore, &grid, StepRandom::new(0, 0)), 8.0);
+        assert_eq!(rules_registry.apply_rule(1, 2, 2, &moore, &grid, StepRandom::new(0, 0)), 4.0);
+        let mut next_row = vec![0.0; 5];
+        rules_registry.apply_rule_row(1, 2, &moore, &grid, StepRandom::new(0, 0), &mut next_row);
+        assert_eq!(next_row[2], 4.0);
+
+        rules_registry.set_neighborhood(1, None);
+        assert_eq!(rules_registry.apply_rule(1, 2, 2, &moore, &grid, StepRandom::new(0, 0)), 8.0);
+    }
+
+    #[test]
+    fn test_conway_optimized_honours_boundaries() {
+        let mut grid = Grid::new(5, 5, 1);
+        for r in 0..5 {
+            for c in 0..5 {
+                grid.set_cell_trait(r, c, 0, 0.0);
+            }
+        }
+        // Vertical blinker across the top/bottom edge
+        grid.set_cell_trait(4, 2, 0, 1.0);
+        grid.set_cell_trait(0, 2, 0, 1.0);
+        grid.set_cell_trait(1, 2, 0, 1.0);
+        let mask = vec![vec![1; 3]; 3];
+        let neighborhood = Neighborhood::new(3, 3, 1, 1, mask);
+
+        for boundary in Boundary::ALL {
+            grid.set_boundaries(*boundary, Boundary::Periodic);
+            for r in 0..5 {
+                for c in 0..5 {
+                    assert_eq!(
+                        RuleFunction::conway_optimized(0, r, c, &neighborhood, &grid, &mut rng()),
+                        RuleFunction::conway(0, r, c, &neighborhood, &grid, &mut rng()),
+                        "{:?} at ({}, {})", boundary, r, c
+                    );
+                }
+            }
+        }
+
+        // On a hexagonal lattice the six adjacent cells are counted, whatever the neighborhood
+        let mut grid = Grid::new(7, 6, 1);
+        for (i, value) in grid.data.iter_mut().enumerate() {
+            *value = ((i * 5) % 3 != 0) as u8 as f32;
+        }
+        grid.lattice = Lattice::Hexagonal;
+        let hexagon = Neighborhood::hexagonal(1);
+        let highlife = LifeLikeParams::parse("B36/S23").unwrap();
+        let mut differs = false;
+        for boundary in [Boundary::Periodic, Boundary::Fixed] {
+            grid.set_boundaries(boundary, boundary);
+            for r in 0..6 {
+                for c in 0..7 {
+                    let hexagonal = RuleFunction::conway(0, r, c, &hexagon, &grid, &mut rng());
+                    assert_eq!(RuleFunction::conway_optimized(0, r, c, &neighborhood, &grid, &mut rng()), hexagonal);
+                    assert_eq!(
+                        RuleFunction::life_like_optimized(0, r, c, &neighborhood, &grid, &highlife, &mut rng()),
+                        RuleFunction::life_like(0, r, c, &hexagon, &grid, &highlife, &mut rng())
+                    );
+                    differs |= hexagonal != RuleFunction::conway(0, r, c, &neighborhood, &grid, &mut rng());
+                }
+            }
+        }
+        assert!(differs);
+    }
+
+    #[test]
+    fn test_rule_params() {
+        let mut grid = Grid::new(3, 3, 1);
+        for r in 0..3 {
+            for c in 0..3 {
+                grid.set_cell_trait(r, c, 0, 1.0);
+            }
+        }
+        let mask = vec![vec![1; 3]; 3];
+        let neighborhood = Neighborhood::new(3, 3, 1, 1, mask);
+
+        let mut rules_registry = RulesRegistry::custom(vec![Rule::from_name("diffusion").unwrap()]);
+        assert!((rules_registry.apply_rule(0, 1, 1, &neighborhood, &grid, StepRandom::new(0, 0)) - 0.98).abs() < 1e-6);
+        assert!(rules_registry.set_rule_param(0, "decay", 0.5));
+        assert!(!rules_registry.set_rule_param(0, "coupling", 0.5));
+        assert!((rules_registry.apply_rule(0, 1, 1, &neighborhood, &grid, StepRandom::new(0, 0)) - 0.5).abs() < 1e-6);
+        assert!(rules_registry.is_stored_function(0, "diffusion"));
+        assert_eq!(rules_registry.get_rule(0).get_param("decay"), Some(0.5));
+        assert!(Rule::Conway.params().is_empty());
+    }
+
+    #[test]
+    fn test_rule_spec() {
+        #[derive(serde::Deserialize)]
+        struct Config {
+            rules: Vec<RuleSpec>,
+        }
+        let config: Config = toml::from_str(r#"
+            rules = ["conway", { name = "diffusion", decay = 0.9, isolated_decay = 1 }]
+        "#).unwrap();
+        let rules: Vec<Rule> = config.rules.iter().map(|spec| builtin(spec.to_rule())).collect();
+        assert_eq!(rules[0], Rule::Conway);
+        assert_eq!(
+            rules[1],
+            Rule::Diffusion(DiffusionParams { decay: 0.9, isolated_decay: 1.0, ..DiffusionParams::DEFAULT })
+        );
+
+        let config: Config = toml::from_str(r#"rules = [{ name = "conway", decay = 0.9 }]"#).unwrap();
+        assert!(config.rules[0].to_rule().is_err());
+
+        for (source, expected) in [
+            (r#"rules = [{ name = "diffusion", decay = "high" }]"#, "parameter 'decay' of rule 'diffusion'"),
+            (r#"rules = [{ name = "lenia", rulestring = 3 }]"#, "'rulestring' of rule 'lenia'"),
+            (r#"rules = [{ name = "energy", inputs = "Heat" }]"#, "'inputs' of rule 'energy'"),
+            (r#"rules = [{ nme = "diffusion" }]"#, "parameter 'nme' must be a number"),
+            (r#"rules = [{ decay = 0.5 }]"#, "without a 'name'"),
+        ] {
+            let error = toml::from_str::<Config>(source).err().unwrap().to_string();
+            assert!(error.contains(expected), "{}: {}", source, error);
+        }
+    }
+
+    #[test]
+    fn test_life_like_rulestrings() {
+        let highlife = LifeLikeParams::parse("b36/s23").unwrap();
+        assert!(highlife.births(3) && highlife.births(6) && !highlife.births(2));
+        assert!(highlife.survives(2) && highlife.survives(3) && !highlife.survives(6));
+        assert_eq!(Rule::LifeLike(highlife).rulestring().as_deref(), Some("B36/S23"));
+        assert_eq!(LifeLikeParams::parse("S23/B3"), Ok(LifeLikeParams::DEFAULT));
+        assert_eq!(Rule::LifeLike(LifeLikeParams::parse("B2/S").unwrap()).rulestring().as_deref(), Some("B2/S"));
+        let large = LifeLikeParams::parse("B3,10/S2,3").unwrap();
+        assert!(large.births(10));
+        assert_eq!(Rule::LifeLike(large).rulestring().as_deref(), Some("B3,10/S23"));
+        for invalid in ["", "B3", "B3/S2x", "B3/B4", "X3/S23", "B3,64/S2"] {
+            assert!(LifeLikeParams::parse(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(builtin(RuleSpec::Name("B36/S23".to_string()).to_rule()), Rule::LifeLike(highlife));
+    }
+
+    #[test]
+    fn test_life_like_matches_conway() {
+        let mut grid = Grid::new(6, 5, 1);
+        for (i, value) in grid.data.iter_mut().enumerate() {
+            *value = ((i * 7) % 3 == 0) as u8 as f32;
+        }
+        let mask = vec![vec![1; 3]; 3];
+        let neighborhood = Neighborhood::new(3, 3, 1, 1, mask);
+        let conway = LifeLikeParams::DEFAULT;
+
+        for boundary in [Boundary::Periodic, Boundary::Fixed, Boundary::Reflective] {
+            grid.set_boundaries(boundary, boundary);
+            for r in 0..5 {
+                for c in 0..6 {
+                    let expected = RuleFunction::conway(0, r, c, &neighborhood, &grid, &mut rng());
+                    assert_eq!(RuleFunction::life_like(0, r, c, &neighborhood, &grid, &conway, &mut rng()), expected);
+                    assert_eq!(RuleFunction::life_like_optimized(0, r, c, &neighborhood, &grid, &conway, &mut rng()), expected);
+                }
+            }
+        }
+
+        // Seeds (B2/S) on the von Neumann neighborhood: every live cell dies
+        let von_neumann = Neighborhood::new(3, 3, 1, 1, vec![vec![0, 1, 0], vec![1, 1, 1], vec![0, 1, 0]]);
+        let seeds = LifeLikeParams::parse("B2/S").unwrap();
+        grid.set_boundaries(Boundary::Periodic, Boundary::Periodic);
+        for r in 0..5 {
+            for c in 0..6 {
+                let result = RuleFunction::life_like(0, r, c, &von_neumann, &grid, &seeds, &mut rng());
+                if grid.get_cell_trait(r, c, 0) > 0.5 {
+                    assert_eq!(result, 0.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_generations_states() {
+        let states = States::new(4).unwrap();
+        for state in 0..4 {
+            assert_eq!(states.state(states.level(state)), state);
+        }
+        // Alive at the top, dying states step down toward dead
+        assert_eq!(states.level(1), 1.0);
+        assert!(states.level(2) > states.level(3) && states.level(3) > states.level(0));
+        assert_eq!(states.next(1, false, false), 2);
+        assert_eq!(states.next(3, true, true), 0);
+        assert!(States::new(1).is_none());
+
+        let brain = GenerationsParams::parse("B2/S/C3").unwrap();
+        assert_eq!(brain, GenerationsParams::DEFAULT);
+        assert_eq!(Rule::Generations(brain).rulestring().as_deref(), Some("B2/S/C3"));
+        assert!(GenerationsParams::parse("B2/S").is_err());
+        assert!(GenerationsParams::parse("B2/S/C1").is_err());
+        assert_eq!(builtin(RuleSpec::Name("B2/S/C3".to_string()).to_rule()), Rule::Generations(brain));
+
+        // Brian's Brain: a live cell always starts dying, a dying cell dies, a dead cell with 2 live neighbors is born
+        let mut grid = Grid::new(3, 3, 1);
+        grid.data.fill(0.0);
+        grid.set_cell_trait(0, 0, 0, 1.0);
+        grid.set_cell_trait(0, 1, 0, 1.0);
+        grid.set_cell_trait(2, 2, 0, 0.5);
+        let mask = vec![vec![1; 3]; 3];
+        let neighborhood = Neighborhood::new(3, 3, 1, 1, mask);
+        assert_eq!(RuleFunction::generations(0, 0, 0, &neighborhood, &grid, &brain, &mut rng()), 0.5);
+        assert_eq!(RuleFunction::generations(0, 2, 2, &neighborhood, &grid, &brain, &mut rng()), 0.0);
+        assert_eq!(RuleFunction::generations(0, 1, 1, &neighborhood, &grid, &brain, &mut rng()), 1.0);
+    }
+
+    #[test]
+    fn test_larger_than_life() {
+        let bosco = "R5,C0,M1,S34..58,B34..45,NM";
+        assert_eq!(LargerThanLifeParams::parse(bosco), Ok(LargerThanLifeParams::DEFAULT));
+        assert_eq!(LargerThanLife::default().rulestring().as_deref(), Some(bosco));
+        let rule = RuleSpec::Name(bosco.to_string()).to_rule().unwrap();
+        assert_eq!(rule.downcast_ref::<LargerThanLife>(), Some(&LargerThanLife::default()));
+        for invalid in ["R0,S1..2,B1..2", "R5,S3..1,B1..2", "R5,B1..2", "R5,S1..2,B1..2,NX"] {
+            assert!(LargerThanLifeParams::parse(invalid).is_err(), "{}", invalid);
+        }
+
+        // Radius 1 without the middle cell is Conway
+        let mut conway = create_rule("larger than life").unwrap();
+        conway.set_rulestring("R1,C0,M0,S2..3,B3..3,NM").unwrap();
+        let mut grid = Grid::new(6, 5, 1);
+        for (i, value) in grid.data.iter_mut().enumerate() {
+            *value = ((i * 7) % 3 == 0) as u8 as f32;
+        }
+        let mask = vec![vec![1; 3]; 3];
+        let neighborhood = Neighborhood::new(3, 3, 1, 1, mask);
+        // Through boundary lookups, then through the flat offsets compiled by prepare
+        for boundary in [Boundary::Periodic, Boundary::Fixed] {
+            grid.set_boundaries(boundary, Boundary::Periodic);
+            for prepared in [false, true] {
+                if prepared {
+                    conway.prepare(0, &grid);
+                }
+                for r in 0..5 {
+                    for c in 0..6 {
+                        assert_eq!(
+                            conway.apply(0, r, c, &neighborhood, &grid, &mut rng()),
+                            RuleFunction::conway(0, r, c, &neighborhood, &grid, &mut rng())
+                        );
+                    }
+                }
+            }
+        }
+
+        // The square window is a hexagon on a hexagonal lattice
+        grid.lattice = Lattice::Hexagonal;
+        conway.prepare(0, &grid);
+        let hexagon = Neighborhood::hexagonal(1);
+        assert_eq!(conway.read_offsets(&neighborhood).len(), 7);
+        for r in 0..5 {
+            for c in 0..6 {
+                assert_eq!(
+                    conway.apply(0, r, c, &neighborhood, &grid, &mut rng()),
+                    RuleFunction::conway(0, r, c, &hexagon, &grid, &mut rng())
+                );
+            }
+        }
+        conway.set_rulestring("R2,C0,M0,S2..3,B3..3,NN").unwrap();
+        conway.prepare(0, &grid);
+        assert_eq!(conway.read_offsets(&neighborhood), Neighborhood::von_neumann(2).offsets().iter().map(|o| (o.dr, o.dc)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_expression_rule() {
+        #[derive(serde::Deserialize)]
+        struct Config {
+            rules: Vec<RuleSpec>,
+        }
+        let config: Config = toml::from_str(r#"
+            rules = [{ name = "expression", expression = "next = clamp(0.7*self + 0.3*mean(nbr.Heat) - 0.02, 0, 1)" }, "average"]
+        "#).unwrap();
+        let rule = config.rules[0].to_rule().unwrap();
+        assert_eq!(rule.rulestring().as_deref(), Some("next = clamp(0.7*self + 0.3*mean(nbr.Heat) - 0.02, 0, 1)"));
+
+        let mut grid = Grid::new(3, 3, 2);
+        for r in 0..3 {
+            for c in 0..3 {
+                grid.set_cell_trait(r, c, 0, 0.5);
+                grid.set_cell_trait(r, c, 1, 1.0);
+            }
+        }
+        let mask = vec![vec![1; 3]; 3];
+        let neighborhood = Neighborhood::new(3, 3, 1, 1, mask);
+
+        // Unbound, the rule keeps the value
+        let mut rules_registry = RulesRegistry::custom(vec![rule, Rule::Average.into()]);
+        assert_eq!(rules_registry.apply_rule(0, 1, 1, &neighborhood, &grid, StepRandom::new(0, 0)), 0.5);
+
+        let names = vec!["Alive".to_string(), "Heat".to_string()];
+        rules_registry.set_trait_names(&names).unwrap();
+        assert!((rules_registry.apply_rule(0, 1, 1, &neighborhood, &grid, StepRandom::new(0, 0)) - 0.63).abs() < 1e-6);
+
+        // Unknown trait names are rejected and leave the rule unchanged
+        assert!(rules_registry.set_rule_rulestring(0, "mean(nbr.Cold)").is_err());
+        assert!(rules_registry.set_rule(1, Rule::Expression(ExpressionParams::parse("Cold").unwrap())).is_err());
+        assert_eq!(rules_registry.get_rule(1).downcast_ref(), Some(&Rule::Average));
+        assert!(rules_registry.set_trait_names(&["Alive".to_string()]).is_err());
+        assert_eq!(rules_registry.get_trait_names(), &names[..]);
+        rules_registry.set_rule_rulestring(0, "Heat - self").unwrap();
+        assert_eq!(rules_registry.apply_rule(0, 1, 1, &neighborhood, &grid, StepRandom::new(0, 0)), 0.5);
+        assert!(Rule::from_name("expression").unwrap().set_rulestring("1 +").is_err());
+    }
+
+    /// Rule defined outside the built-in enum, with a parameter and some state.
+    /// The catalog is shared by the tests of the process, so no other test registers this name
+    #[derive(Clone, Debug)]
+    struct Threshold {
+        level: f32,
+        prepared: u32,
+    }
+
+    impl CellRule for Threshold {
+        fn name(&self) -> &'static str {
+            "test threshold"
+        }
+
+        fn apply(&self, trait_index: usize, cell_r: usize, cell_c: usize, _neighborhood: &Neighborhood, grid: &Grid, _rng: &mut RuleRng) -> f32 {
+            (grid.get_cell_trait(cell_r, cell_c, trait_index) > self.level) as u8 as f32
+        }
+
+        fn prepare(&mut self, _trait_index: usize, _grid: &Grid) {
+            self.prepared += 1;
+        }
+
+        fn param_names(&self) -> &'static [&'static str] {
+            &["level"]
+        }
+
+        fn get_param(&self, name: &str) -> Option<f32> {
+            (name == "level").then_some(self.level)
+        }
+
+        fn set_param(&mut self, name: &str, value: f32) -> bool {
+            if name == "level" {
+                self.level = value;
+            }
+            name == "level"
+        }
+
+        fn save_state(&self) -> Vec<u8> {
+            self.prepared.to_le_bytes().to_vec()
+        }
+
+        fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+            self.prepared = u32::from_le_bytes(state.try_into().map_err(|_| "bad threshold state")?);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_registered_rule() {
+        register_rule(Threshold { level: 0.5, prepared: 0 }).unwrap();
+        assert!(register_rule(Threshold { level: 0.1, prepared: 0 }).is_err());
+        assert!(rule_names().contains(&"test threshold"));
+        assert!(rule_names().starts_with(Rule::NAMES));
+
+        #[derive(serde::Deserialize)]
+        struct Config {
+            rules: Vec<RuleSpec>,
+        }
+        let config: Config = toml::from_str(r#"
+            rules = ["test threshold", { name = "test threshold", level = 0.25 }]
+        "#).unwrap();
+        let rules: Vec<Box<dyn CellRule>> = config.rules.iter().map(|spec| spec.to_rule().unwrap()).collect();
+        assert_eq!(rules[0].params(), vec![("level", 0.5)]);
+
+        let mut grid = Grid::new(3, 3, 2);
+        for r in 0..3 {
+            for c in 0..3 {
+                grid.set_cell_trait(r, c, 0, 0.3);
+                grid.set_cell_trait(r, c, 1, 0.3);
+            }
+        }
+        let neighborhood = Neighborhood::new(3, 3, 1, 1, vec![vec![1; 3]; 3]);
+        let mut rules_registry = RulesRegistry::custom(rules);
+        rules_registry.prepare(&[1], &grid, UpdateMode::Synchronous);
+        assert_eq!(rules_registry.apply_rule(0, 1, 1, &neighborhood, &grid, StepRandom::new(0, 0)), 0.0);
+        assert_eq!(rules_registry.apply_rule(1, 1, 1, &neighborhood, &grid, StepRandom::new(0, 0)), 1.0);
+        assert!(rules_registry.is_stored_function(1, "test threshold"));
+        assert_eq!(rules_registry.get_rule(0).downcast_ref::<Threshold>().unwrap().prepared, 0);
+        assert_eq!(rules_registry.get_rule(1).downcast_ref::<Threshold>().unwrap().prepared, 1);
+        assert!(rules_registry.get_rule(1).downcast_ref::<Rule>().is_none());
+
+        let mut restored = create_rule("test threshold").unwrap();
+        restored.load_state(&rules_registry.get_rule(1).save_state()).unwrap();
+        assert_eq!(restored.downcast_ref::<Threshold>().unwrap().prepared, 1);
+        assert!(restored.load_state(&[1]).is_err());
+    }
+
+    #[test]
+    fn test_coupled_rule_inputs() {
+        // Energy-charge-phase model in its default slots and in reverse order
+        let mut grid = Grid::new(4, 4, 3);
+        let mut reversed = Grid::new(4, 4, 3);
+        for r in 0..4 {
+            for c in 0..4 {
+                for t in 0..3 {
+                    let value = ((r * 7 + c * 3 + t * 5) % 11) as f32 / 11.0;
+                    grid.set_cell_trait(r, c, t, value);
+                    reversed.set_cell_trait(r, c, 2 - t, value);
+                }
+            }
+        }
+        let neighborhood = Neighborhood::new(3, 3, 1, 1, vec![vec![1; 3]; 3]);
+
+        #[derive(serde::Deserialize)]
+        struct Config {
+            rules: Vec<RuleSpec>,
+        }
+        let config: Config = toml::from_str(r#"
+            rules = [
+                { name = "phase", inputs = { charge = "Charge" } },
+                { name = "charge", inputs = { energy = "Energy" } },
+                { name = "energy", inputs = { phase = "Phase" } },
+            ]
+        "#).unwrap();
+        let names: Vec<String> = ["Phase", "Charge", "Energy"].iter().map(|name| name.to_string()).collect();
+        let mut rules_reversed = RulesRegistry::custom(config.rules.iter().map(|spec| spec.to_rule().unwrap()));
+        rules_reversed.set_trait_names(&names).unwrap();
+        let rules = RulesRegistry::custom(vec![Rule::from_name("energy").unwrap(), Rule::from_name("charge").unwrap(), Rule::from_name("phase").unwrap()]);
+
+        assert_eq!(rules_reversed.get_rule(2).get_input("phase"), Some(0));
+        for r in 0..4 {
+            for c in 0..4 {
+                for t in 0..3 {
+                    assert_eq!(
+                        rules.apply_rule(t, r, c, &neighborhood, &grid, StepRandom::new(0, 0)),
+                        rules_reversed.apply_rule(2 - t, r, c, &neighborhood, &reversed, StepRandom::new(0, 0))
+                    );
+                }
+            }
+        }
+
+        let config: Config = toml::from_str(r#"rules = [{ name = "energy", inputs = { heat = "Phase" } }]"#).unwrap();
+        assert!(config.rules[0].to_rule().is_err());
+        assert!(rules_reversed.set_rule_input(2, "phase", "Spin").is_err());
+        assert!(rules_reversed.set_rule_input(0, "phase", "Energy").is_err());
+        rules_reversed.set_rule_input(2, "phase", "Charge").unwrap();
+        assert_eq!(rules_reversed.get_rule(2).get_input("phase"), Some(1));
+        // The default slots must exist
+        let mut alone = RulesRegistry::custom(vec![Rule::from_name("energy").unwrap()]);
+        assert!(alone.set_trait_names(&["Energy".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_stochastic_rules() {
+        let mut grid = Grid::new(6, 5, 1);
+        for (i, value) in grid.data.iter_mut().enumerate() {
+            *value = ((i * 7) % 3 == 0) as u8 as f32;
+        }
+        let neighborhood = Neighborhood::new(3, 3, 1, 1, vec![vec![1; 3]; 3]);
+        let random = StepRandom::new(7, 3);
+
+        // Certain births and survivals fall back to the deterministic rule, impossible ones kill every cell
+        let mut certain = ProbabilisticLifeParams::DEFAULT;
+        certain.birth_probability = 1.0;
+        certain.survival_probability = 1.0;
+        let mut impossible = certain;
+        impossible.birth_probability = 0.0;
+        impossible.survival_probability = 0.0;
+        for r in 0..5 {
+            for c in 0..6 {
+                let idx = r * 6 + c;
+                let expected = RuleFunction::conway(0, r, c, &neighborhood, &grid, &mut rng());
+                assert_eq!(RuleFunction::probabilistic_life(0, r, c, &neighborhood, &grid, &certain, &mut RuleRng::new(random, idx, 0)), expected);
+                assert_eq!(RuleFunction::probabilistic_life(0, r, c, &neighborhood, &grid, &impossible, &mut RuleRng::new(random, idx, 0)), 0.0);
+            }
+        }
+
+        // The draws only depend on (seed, timestep, cell, trait)
+        let rules = RulesRegistry::custom(vec![Rule::ProbabilisticLife(ProbabilisticLifeParams::DEFAULT)]);
+        let run = |random: StepRandom| -> Vec<f32> {
+            let mut next = vec![0.0; 6];
+            let mut values = Vec::new();
+            for r in 0..5 {
+                rules.apply_rule_row(0, r, &neighborhood, &grid, random, &mut next);
+                values.extend_from_slice(&next);
+            }
+            values
+        };
+        assert_eq!(run(random), run(StepRandom::new(7, 3)));
+        assert_ne!(run(random), run(StepRandom::new(7, 4)));
+
+        // Without noise the voter copies one of its neighbors
+        let voter = VoterParams { noise: 0.0 };
+        let noisy = VoterParams { noise: 1.0 };
+        let mut votes = Vec::new();
+        for idx in 0..30 {
+            let (r, c) = (idx / 6, idx % 6);
+            let vote = RuleFunction::voter(0, 2, 2, &neighborhood, &grid, &voter, &mut RuleRng::new(random, idx, 0));
+            assert!([1, 2, 3].iter().any(|&dr| [1, 2, 3].iter().any(|&dc| (dr, dc) != (2, 2) && grid.get_cell_trait(dr, dc, 0) == vote)));
+            votes.push(vote);
+            let value = RuleFunction::voter(0, r, c, &neighborhood, &grid, &noisy, &mut RuleRng::new(random, idx, 0));
+            assert!((0.0..1.0).contains(&value));
+        }
+        assert!(votes.contains(&0.0) && votes.contains(&1.0));
+
+        grid.set_cell_trait(0, 0, 0, 0.8);
+        let always = RandomDecayParams { probability: 1.0, factor: 0.5 };
+        let never = RandomDecayParams { probability: 0.0, factor: 0.5 };
+        assert_eq!(RuleFunction::random_decay(0, 0, 0, &neighborhood, &grid, &always, &mut rng()), 0.4);
+        assert_eq!(RuleFunction::random_decay(0, 0, 0, &neighborhood, &grid, &never, &mut rng()), 0.8);
+    }
+
+    #[test]
+    fn test_kernel_rules() {
+        let mut grid = Grid::new(5, 5, 1);
+        grid.data.fill(0.0);
+        grid.set_cell_trait(2, 2, 0, 1.0);
+        let gaussian = Neighborhood::from_mask(vec![vec![1; 3]; 3]).with_kernel(Kernel::Gaussian { sigma: 1.0 }).unwrap();
+        let total: f32 = (0..3).flat_map(|r| (0..3).map(move |c| (r, c))).map(|(r, c)| gaussian.weight(r, c)).sum();
+
+        // The peak spreads as a Gaussian blur, which keeps the total
+        let mut blurred = 0.0;
+        for r in 0..5 {
+            for c in 0..5 {
+                blurred += RuleFunction::kernel_average(0, r, c, &gaussian, &grid, &mut rng());
+            }
+        }
+        assert!((blurred - 1.0).abs() < 1e-5);
+        assert!((RuleFunction::kernel_average(0, 2, 2, &gaussian, &grid, &mut rng()) - 1.0 / total).abs() < 1e-6);
+        assert_eq!(RuleFunction::kernel_average(0, 0, 2, &gaussian, &grid, &mut rng()), 0.0);
+
+        let half = KernelDiffusionParams { rate: 0.5, decay: 1.0 };
+        let expected = 0.5 + 0.5 / total;
+        assert!((RuleFunction::kernel_diffusion(0, 2, 2, &gaussian, &grid, &half, &mut rng()) - expected).abs() < 1e-6);
+
+        // The weighted average rule reads the precomputed distance weights
+        let uniform = Neighborhood::from_mask(vec![vec![1; 3]; 3]);
+        grid.set_cell_trait(1, 2, 0, 1.0);
+        let expected = 0.5 / (4.0 * 0.5 + 4.0 / (1.0 + 2f32.sqrt()));
+        assert!((RuleFunction::weighted_average(0, 2, 2, &uniform, &grid, &mut rng()) - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_lenia() {
+        let mut lenia = create_rule("lenia").unwrap();
+        assert_eq!(lenia.get_param("radius"), Some(13.0));
+        assert!(lenia.set_param("radius", 4.0));
+        let kernel = &lenia.downcast_ref::<Lenia>().unwrap().convolution;
+        assert_eq!(kernel.radius(), 4);
+        assert!(kernel.weights()[4 * 9 + 4] == 0.0 && (kernel.weights().iter().sum::<f32>() - 1.0).abs() < 1e-5);
+
+        // A uniform field at the potential of maximal growth grows by dt everywhere
+        let mut grid = Grid::new(12, 10, 1);
+        grid.data.fill(0.15);
+        let neighborhood = Neighborhood::from_mask(vec![vec![1; 3]; 3]);
+        let mut next_row = vec![0.0f32; 12];
+        lenia.prepare(0, &grid);
+        lenia.apply_row(0, 5, &neighborhood, &grid, StepRandom::new(0, 0), &mut next_row);
+        assert!(next_row.iter().all(|&value| (value - 0.25).abs() < 1e-5));
+        assert!((lenia.apply(0, 0, 0, &neighborhood, &grid, &mut rng()) - 0.25).abs() < 1e-5);
+
+        // The potential of prepare, by FFT, is the per-cell stencil of the sequential modes
+        assert!(lenia.set_param("radius", 6.0));
+        assert!(lenia.downcast_ref::<Lenia>().unwrap().convolution.uses_fft());
+        for (i, value) in grid.data.iter_mut().enumerate() {
+            *value = ((i * 7) % 13) as f32 / 12.0;
+        }
+        grid.is_empty[17] = true;
+        lenia.prepare(0, &grid);
+        for row in [0, 4, 9] {
+            lenia.apply_row(0, row, &neighborhood, &grid, StepRandom::new(0, 0), &mut next_row);
+            for (col, &value) in next_row.iter().enumerate() {
+                assert!((value - lenia.apply(0, row, col, &neighborhood, &grid, &mut rng())).abs() < 1e-5);
+            }
+        }
+
+        // On a hexagonal lattice the ring follows the distances between the cell centers:
+        // (3, -3) and (3, 0) are both 3 cells away
+        let weights = |lenia: &dyn CellRule| lenia.downcast_ref::<Lenia>().unwrap().convolution.weights().to_vec();
+        assert_ne!(weights(&*lenia)[9 * 13 + 3], weights(&*lenia)[9 * 13 + 6]);
+        grid.lattice = Lattice::Hexagonal;
+        lenia.prepare(0, &grid);
+        assert_eq!(weights(&*lenia)[9 * 13 + 3], weights(&*lenia)[9 * 13 + 6]);
+
+        // The cell by cell modes skip the potential of the whole grid, rows then fall back to apply
+        grid.lattice = Lattice::Square;
+        lenia.prepare_cells(0, &grid);
+        assert!(lenia.downcast_ref::<Lenia>().unwrap().potential.is_empty());
+        assert_ne!(weights(&*lenia)[9 * 13 + 3], weights(&*lenia)[9 * 13 + 6]);
+        lenia.apply_row(0, 4, &neighborhood, &grid, StepRandom::new(0, 0), &mut next_row);
+        for (col, &value) in next_row.iter().enumerate() {
+            assert_eq!(value, lenia.apply(0, 4, col, &neighborhood, &grid, &mut rng()));
+        }
+    }
 }
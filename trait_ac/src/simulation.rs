@@ -0,0 +1,802 @@
+use crate::checkpoint::Checkpoint;
+use crate::config::EngineConfig;
+use crate::grid::{Boundary, Grid, Lattice};
+use crate::movement::MovementRegistry;
+use crate::neighborhood::Neighborhood;
+use crate::random::{random_seed, RuleRng, StepRandom, STREAM_RANDOMIZE, STREAM_UPDATE};
+use crate::rules::RulesRegistry;
+use crate::traits;
+use crate::update::{Colouring, UpdateMode};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::io;
+use std::path::Path;
+
+
+/// Owns the whole simulation state and runs the rule-then-movement step loop.
+/// Every frontend drives the engine through this type so they all share the same code path.
+pub struct Simulation {
+    grid: Grid,
+    /// Scratch buffer receiving the rule outputs before movement
+    next_grid: Grid,
+    grid_density: f32,
+    initialisation_ranges: Vec<(f32, f32)>,
+    rules_registry: RulesRegistry,
+    movement_registry: MovementRegistry,
+    neighborhood_traits: Neighborhood,
+    neighborhood_mvt: Neighborhood,
+    active_traits: Vec<usize>,
+    update_mode: UpdateMode,
+    timestep: usize,
+    /// Master seed: the same seed and configuration give bit-identical runs
+    seed: u64,
+    /// Number of `randomize` calls since the grid was initialised, which keys their draws
+    /// so that a resumed run randomizes like the uninterrupted one
+    randomizations: u64,
+    /// Classes of the checkerboard mode, rebuilt when the grid or the cells read change
+    colouring: Option<Colouring>,
+}
+
+impl Simulation {
+    /// Create a simulation on a freshly initialised grid, with every trait active
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        width: usize,
+        height: usize,
+        grid_density: f32,
+        num_traits: usize,
+        initialisation_ranges: Vec<(f32, f32)>,
+        rules_registry: RulesRegistry,
+        movement_registry: MovementRegistry,
+        neighborhood_traits: Neighborhood,
+        neighborhood_mvt: Neighborhood,
+        seed: u64,
+    ) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let grid = Grid::new_with_density(width, height, grid_density, num_traits, &initialisation_ranges, &mut rng);
+        let next_grid = grid.clone();
+
+        Self {
+            grid,
+            next_grid,
+            grid_density,
+            initialisation_ranges,
+            rules_registry,
+            movement_registry,
+            neighborhood_traits,
+            neighborhood_mvt,
+            active_traits: (0..num_traits).collect(),
+            update_mode: UpdateMode::default(),
+            timestep: 0,
+            seed,
+            randomizations: 0,
+            colouring: None,
+        }
+    }
+
+    /// Build the simulation described by a config (resolved with `EngineConfig::resolve`),
+    /// a config without seed draws one from entropy
+    pub fn from_config(config: &EngineConfig) -> Result<Self, String> {
+        let mut rules_registry = RulesRegistry::custom(config.rules.clone());
+        rules_registry.set_domains(&traits::domains(&config.traits));
+        rules_registry.set_neighborhoods(&traits::neighborhoods(&config.traits)?);
+        rules_registry.set_trait_names(&traits::names(&config.traits))?;
+        let mut movement_registry = MovementRegistry::custom(
+            config.grid_width,
+            config.grid_height,
+            config.movement,
+        );
+        movement_registry.set_params(config.movement_params.resolve(config.movement, &config.traits)?)?;
+
+        let mut simulation = Self::new(
+            config.grid_width,
+            config.grid_height,
+            config.grid_density,
+            config.traits.len(),
+            traits::initialisation_ranges(&config.traits),
+            rules_registry,
+            movement_registry,
+            config.neighborhood_traits()?,
+            config.neighborhood_mvt()?,
+            config.seed.unwrap_or_else(random_seed),
+        );
+        simulation.set_active_mask(&traits::active_mask(&config.traits));
+        simulation.set_boundaries(config.row_boundary, config.col_boundary);
+        simulation.set_lattice(config.lattice);
+        simulation.set_update_mode(config.update_mode);
+        Ok(simulation)
+    }
+
+    /// Rebuild a simulation from a checkpoint, stepping it continues the saved run exactly
+    pub fn from_checkpoint(checkpoint: Checkpoint) -> Self {
+        let grid = checkpoint.grid.into_owned();
+        let next_grid = grid.clone();
+        let mut movement_registry = MovementRegistry::custom(grid.width, grid.height, checkpoint.movement);
+        movement_registry.set_params(checkpoint.movement_params).expect("checkpoint movement params are checked when read");
+        let mut rules_registry = RulesRegistry::custom(checkpoint.rules);
+        rules_registry.set_domains(&checkpoint.domains);
+        rules_registry.set_neighborhoods(&checkpoint.neighborhoods);
+        rules_registry.set_trait_names(&checkpoint.trait_names).expect("checkpoint rules are bound when read");
+
+        Self {
+            grid,
+            next_grid,
+            grid_density: checkpoint.grid_density,
+            initialisation_ranges: checkpoint.initialisation_ranges,
+            rules_registry,
+            movement_registry,
+            neighborhood_traits: checkpoint.neighborhood_traits,
+            neighborhood_mvt: checkpoint.neighborhood_mvt,
+            active_traits: checkpoint.active_traits,
+            update_mode: checkpoint.update_mode,
+            timestep: checkpoint.timestep as usize,
+            seed: checkpoint.seed,
+            randomizations: checkpoint.randomizations,
+            colouring: None,
+        }
+    }
+
+    /// Snapshot of the full state (the grid is borrowed, not copied)
+    pub fn checkpoint(&self) -> Checkpoint<'_> {
+        Checkpoint {
+            timestep: self.timestep as u64,
+            seed: self.seed,
+            randomizations: self.randomizations,
+            grid_density: self.grid_density,
+            initialisation_ranges: self.initialisation_ranges.clone(),
+            active_traits: self.active_traits.clone(),
+            update_mode: self.update_mode,
+            trait_names: self.rules_registry.get_trait_names().to_vec(),
+            rules: self.rules_registry.get_rules().to_vec(),
+            domains: self.rules_registry.get_domains().to_vec(),
+            neighborhoods: self.rules_registry.get_neighborhoods().to_vec(),
+            movement: self.movement_registry.get_movement(),
+            movement_params: self.movement_registry.get_params().clone(),
+            neighborhood_traits: self.neighborhood_traits.clone(),
+            neighborhood_mvt: self.neighborhood_mvt.clone(),
+            grid: Cow::Borrowed(&self.grid),
+        }
+    }
+
+    /// Write the full state to a binary checkpoint file
+    pub fn save_checkpoint(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.checkpoint().save(path)
+    }
+
+    /// Resume a run from a checkpoint file written by `save_checkpoint`
+    pub fn load_checkpoint(path: impl AsRef<Path>) -> io::Result<Self> {
+        Checkpoint::load(path).map(Self::from_checkpoint)
+    }
+
+    /// Advance the simulation by one timestep (rules, then movement)
+    pub fn step(&mut self) {
+        // Flat neighbor offsets for the current grid width and lattice (after a resize or a new neighborhood)
+        self.neighborhood_traits.compile(&self.grid);
+        self.neighborhood_mvt.compile(&self.grid);
+        self.rules_registry.prepare(&self.active_traits, &self.grid, self.update_mode);
+
+        // Shared by the rules and the movement, which draw from different streams
+        let random = StepRandom::new(self.seed, self.timestep as u64);
+
+        // --- STEP 1: Rules ---
+        // The rule outputs end up in next_grid whatever the update mode
+        match self.update_mode {
+            UpdateMode::Synchronous => self.update_synchronous(random),
+            UpdateMode::RandomIndependent(probability) => {
+                self.update_synchronous(random);
+                self.keep_unselected_cells(probability, random);
+            }
+            UpdateMode::Checkerboard => self.update_checkerboard(random),
+            UpdateMode::RandomSequential | UpdateMode::FixedSweep => self.update_sequential(random),
+        }
+
+        // --- STEP 2: Movement ---
+        self.movement_registry.apply_movement(
+            &self.neighborhood_mvt,
+            &mut self.grid,
+            &mut self.next_grid,
+            random,
+        );
+        // no need to swap the grids as the updates naturally comes to "grid" after the 2 steps
+
+        self.timestep += 1;
+    }
+
+    /// Every active trait of every cell computed from `grid` into `next_grid`
+    fn update_synchronous(&mut self, random: StepRandom) {
+        let width = self.grid.width;
+        let grid = &self.grid;
+        let rules_registry = &self.rules_registry;
+        let neighborhood_traits = &self.neighborhood_traits;
+
+        // Sequential over active traits (small number), parallel over rows
+        for &trait_idx in &self.active_traits {
+            let next_trait = self.next_grid.get_trait_slice_mut(trait_idx);
+
+            // Process rows in parallel
+            next_trait
+                .par_chunks_mut(width)
+                .enumerate()
+                .for_each(|(row, next_row)| {
+                    rules_registry.apply_rule_row(trait_idx, row, neighborhood_traits, grid, random, next_row);
+                });
+        }
+    }
+
+    /// Cells not drawn with `probability` keep their current values
+    fn keep_unselected_cells(&mut self, probability: f32, random: StepRandom) {
+        let grid = &self.grid;
+        for &trait_idx in &self.active_traits {
+            let current = grid.get_trait_slice(trait_idx);
+            self.next_grid
+                .get_trait_slice_mut(trait_idx)
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(idx, next_val)| {
+                    // The same draw for every trait, the cell updates as a whole
+                    if random.cell_rng(idx, STREAM_UPDATE).r#gen::<f32>() >= probability {
+                        *next_val = current[idx];
+                    }
+                });
+        }
+    }
+
+    /// The classes of the colouring one after the other, each updated synchronously in `grid`
+    /// from the values of the classes before it
+    fn update_checkerboard(&mut self, random: StepRandom) {
+        let offsets = self.rules_registry.read_offsets(&self.active_traits, &self.neighborhood_traits);
+        let colouring = match self.colouring.take() {
+            Some(colouring) if colouring.fits(&self.grid, &offsets) => colouring,
+            _ => Colouring::new(&self.grid, &offsets),
+        };
+        let width = self.grid.width;
+        for class in colouring.classes() {
+            let grid = &self.grid;
+            let rules_registry = &self.rules_registry;
+            let neighborhood_traits = &self.neighborhood_traits;
+            // Every trait of the class from the same values, then written
+            let values: Vec<Vec<f32>> = self.active_traits
+                .iter()
+                .map(|&trait_idx| {
+                    class
+                        .par_iter()
+                        .map(|&idx| {
+                            if grid.is_empty[idx] {
+                                grid.get_trait_slice(trait_idx)[idx]
+                            } else {
+                                rules_registry.apply_rule(trait_idx, idx / width, idx % width, neighborhood_traits, grid, random)
+                            }
+                        })
+                        .collect()
+                })
+                .collect();
+            for (&trait_idx, values) in self.active_traits.iter().zip(values) {
+                let current = self.grid.get_trait_slice_mut(trait_idx);
+                for (&idx, value) in class.iter().zip(values) {
+                    current[idx] = value;
+                }
+            }
+        }
+        self.colouring = Some(colouring);
+        for &trait_idx in &self.active_traits {
+            self.next_grid.get_trait_slice_mut(trait_idx).copy_from_slice(self.grid.get_trait_slice(trait_idx));
+        }
+    }
+
+    /// Cells updated one at a time in `grid`, each reading the values already updated,
+    /// in row-major order (fixed sweep) or drawn uniformly (random sequential)
+    fn update_sequential(&mut self, random: StepRandom) {
+        let num_cells = self.grid.num_cells;
+        let mut picks = random.cell_rng(0, STREAM_UPDATE);
+        for update in 0..num_cells {
+            let idx = match self.update_mode {
+                UpdateMode::RandomSequential => picks.gen_range(0..num_cells),
+                _ => update,
+            };
+            if self.grid.is_empty[idx] {
+                continue;
+            }
+            let (row, col) = (idx / self.grid.width, idx % self.grid.width);
+            for &trait_idx in &self.active_traits {
+                // A cell drawn twice draws different values
+                let mut rng = RuleRng::new(random, update, trait_idx);
+                let value = self.rules_registry.apply_rule_with(trait_idx, row, col, &self.neighborhood_traits, &self.grid, &mut rng);
+                self.grid.set_cell_trait(row, col, trait_idx, value);
+            }
+        }
+        for &trait_idx in &self.active_traits {
+            self.next_grid.get_trait_slice_mut(trait_idx).copy_from_slice(self.grid.get_trait_slice(trait_idx));
+        }
+    }
+
+    /// Advance the simulation by `steps` timesteps
+    pub fn run(&mut self, steps: usize) {
+        for _ in 0..steps {
+            self.step();
+        }
+    }
+
+    /// Re-initialise the grid with the stored density and initialisation ranges
+    pub fn reset(&mut self) {
+        let width = self.grid.width;
+        let height = self.grid.height;
+        self.resize(width, height);
+    }
+
+    /// Re-initialise the grid with new dimensions
+    pub fn resize(&mut self, width: usize, height: usize) {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let (row_boundary, col_boundary) = self.boundaries();
+        let lattice = self.lattice();
+        self.grid = Grid::new_with_density(
+            width,
+            height,
+            self.grid_density,
+            self.grid.num_traits,
+            &self.initialisation_ranges,
+            &mut rng,
+        );
+        self.grid.set_boundaries(row_boundary, col_boundary);
+        self.grid.lattice = lattice;
+        self.next_grid = self.grid.clone();
+        self.movement_registry.prepare(width, height);
+        self.timestep = 0;
+        self.randomizations = 0;
+    }
+
+    /// Draw new trait values for the filled cells, keeping the occupancy
+    pub fn randomize(&mut self) {
+        self.grid.randomize(&self.initialisation_ranges, &mut StepRandom::new(self.seed, self.randomizations).cell_rng(0, STREAM_RANDOMIZE));
+        self.randomizations += 1;
+        self.timestep = 0;
+    }
+
+    #[inline]
+    pub fn grid(&self) -> &Grid {
+        &self.grid
+    }
+
+    #[inline]
+    pub fn grid_mut(&mut self) -> &mut Grid {
+        &mut self.grid
+    }
+
+    #[inline]
+    pub fn timestep(&self) -> usize {
+        self.timestep
+    }
+
+    #[inline]
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Seed used from the next `reset`/`resize` on
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    #[inline]
+    pub fn num_traits(&self) -> usize {
+        self.grid.num_traits
+    }
+
+    /// (top/bottom, left/right) boundaries, shared by the rules and the movement
+    #[inline]
+    pub fn boundaries(&self) -> (Boundary, Boundary) {
+        (self.grid.row_boundary, self.grid.col_boundary)
+    }
+
+    pub fn set_boundaries(&mut self, row_boundary: Boundary, col_boundary: Boundary) {
+        self.grid.set_boundaries(row_boundary, col_boundary);
+        self.next_grid.set_boundaries(row_boundary, col_boundary);
+    }
+
+    /// Square or hexagonal cells, shared by the rules and the movement
+    #[inline]
+    pub fn lattice(&self) -> Lattice {
+        self.grid.lattice
+    }
+
+    /// Moore neighborhoods become hexagonal ones on a hexagonal lattice and back (see
+    /// `Neighborhood::for_lattice`), the other masks are read in the coordinates of the new lattice
+    pub fn set_lattice(&mut self, lattice: Lattice) {
+        self.grid.lattice = lattice;
+        self.next_grid.lattice = lattice;
+        for neighborhood in [&mut self.neighborhood_traits, &mut self.neighborhood_mvt] {
+            if let Some(adapted) = neighborhood.for_lattice(lattice) {
+                *neighborhood = adapted;
+            }
+        }
+        for trait_index in 0..self.rules_registry.get_neighborhoods().len() {
+            if let Some(adapted) = self.rules_registry.get_neighborhood(trait_index).and_then(|own| own.for_lattice(lattice)) {
+                self.rules_registry.set_neighborhood(trait_index, Some(adapted));
+            }
+        }
+    }
+
+    /// Fails if a neighborhood reaches farther than its radius on the lattice, see `Neighborhood::check_lattice`
+    pub fn check_lattice(&self) -> Result<(), &'static str> {
+        let lattice = self.lattice();
+        self.neighborhood_traits.check_lattice(lattice)?;
+        self.neighborhood_mvt.check_lattice(lattice)?;
+        for own in self.rules_registry.get_neighborhoods().iter().flatten() {
+            own.check_lattice(lattice)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn grid_density(&self) -> f32 {
+        self.grid_density
+    }
+
+    /// Density used by the next `reset`/`resize`
+    pub fn set_grid_density(&mut self, grid_density: f32) {
+        self.grid_density = grid_density;
+    }
+
+    #[inline]
+    pub fn rules_registry(&self) -> &RulesRegistry {
+        &self.rules_registry
+    }
+
+    #[inline]
+    pub fn rules_registry_mut(&mut self) -> &mut RulesRegistry {
+        &mut self.rules_registry
+    }
+
+    #[inline]
+    pub fn movement_registry(&self) -> &MovementRegistry {
+        &self.movement_registry
+    }
+
+    #[inline]
+    pub fn movement_registry_mut(&mut self) -> &mut MovementRegistry {
+        &mut self.movement_registry
+    }
+
+    #[inline]
+    pub fn neighborhood_traits(&self) -> &Neighborhood {
+        &self.neighborhood_traits
+    }
+
+    #[inline]
+    pub fn neighborhood_mvt(&self) -> &Neighborhood {
+        &self.neighborhood_mvt
+    }
+
+    #[inline]
+    pub fn active_traits(&self) -> &[usize] {
+        &self.active_traits
+    }
+
+    #[inline]
+    pub fn update_mode(&self) -> UpdateMode {
+        self.update_mode
+    }
+
+    pub fn set_update_mode(&mut self, update_mode: UpdateMode) {
+        self.update_mode = update_mode;
+    }
+
+    /// Only the traits with a non-zero entry in the mask are updated by the rules
+    pub fn set_active_mask(&mut self, active_mask: &[u8]) {
+        self.active_traits = active_mask
+            .iter()
+            .take(self.grid.num_traits)
+            .enumerate()
+            .filter_map(|(i, &m)| if m != 0 { Some(i) } else { None })
+            .collect();
+    }
+}
+
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movement::{Movement, MovementFnType, MovementFunction, MovementParams};
+    use crate::random::STREAM_MOVEMENT;
+    use crate::rules::{ExpressionParams, Rule};
+    use crate::traits::Domain;
+
+    fn build_simulation(movement: Movement) -> Simulation {
+        build_seeded_simulation(movement, 0)
+    }
+
+    fn build_seeded_simulation(movement: Movement, seed: u64) -> Simulation {
+        let mask = vec![vec![1; 3]; 3];
+        Simulation::new(
+            8, 8,
+            1.0,
+            1,
+            vec![(0.0, 1.0)],
+            RulesRegistry::custom(vec![Rule::ConwayOptimized]),
+            MovementRegistry::custom(8, 8, movement),
+            Neighborhood::new(3, 3, 1, 1, mask.clone()),
+            Neighborhood::new(3, 3, 1, 1, mask),
+            seed,
+        )
+    }
+
+    #[test]
+    fn test_step_advances_timestep() {
+        let mut simulation = build_simulation(Movement::Static);
+        simulation.run(3);
+        assert_eq!(simulation.timestep(), 3);
+        simulation.reset();
+        assert_eq!(simulation.timestep(), 0);
+    }
+
+    #[test]
+    fn test_movement_preserves_population() {
+        let mut simulation = build_simulation(Movement::Random);
+        let filled = simulation.grid().count_filled_cells();
+        simulation.run(5);
+        assert_eq!(simulation.grid().count_filled_cells(), filled);
+    }
+
+    #[test]
+    fn test_resize() {
+        let mut simulation = build_simulation(Movement::Random);
+        simulation.resize(12, 5);
+        simulation.step();
+        assert_eq!(simulation.grid().width, 12);
+        assert_eq!(simulation.grid().height, 5);
+    }
+
+    #[test]
+    fn test_same_seed_gives_identical_runs() {
+        let mut a = build_seeded_simulation(Movement::Random, 1234);
+        let mut b = build_seeded_simulation(Movement::Random, 1234);
+        a.run(10);
+        b.run(10);
+        assert_eq!(a.grid().data, b.grid().data);
+        assert_eq!(a.grid().is_empty, b.grid().is_empty);
+
+        a.reset();
+        let mut c = build_seeded_simulation(Movement::Random, 1234);
+        assert_eq!(a.grid().data, c.grid().data);
+        c.step();
+        assert_ne!(a.grid().data, c.grid().data);
+    }
+
+    #[test]
+    fn test_runs_do_not_depend_on_thread_count() {
+        let run_with_threads = |num_threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(num_threads).build().unwrap();
+            pool.install(|| {
+                let mut simulation = build_seeded_simulation(Movement::Random, 99);
+                // Large enough to be split into several parallel chunks
+                simulation.resize(300, 300);
+                simulation.run(5);
+                (simulation.grid().data.clone(), simulation.grid().is_empty.clone())
+            })
+        };
+        assert_eq!(run_with_threads(1), run_with_threads(4));
+    }
+
+    #[test]
+    fn test_boundaries_survive_resize() {
+        let mut simulation = build_simulation(Movement::Random);
+        simulation.set_boundaries(Boundary::Reflective, Boundary::Absorbing);
+        simulation.resize(10, 6);
+        simulation.step();
+        assert_eq!(simulation.boundaries(), (Boundary::Reflective, Boundary::Absorbing));
+    }
+
+    #[test]
+    fn test_checkpoint_resumes_exactly() {
+        let mut reference = build_seeded_simulation(Movement::Random, 5);
+        reference.run(4);
+
+        let mut bytes = Vec::new();
+        reference.checkpoint().write_to(&mut bytes).unwrap();
+        let mut resumed = Simulation::from_checkpoint(Checkpoint::read_from(&mut bytes.as_slice()).unwrap());
+        assert_eq!(resumed.timestep(), 4);
+
+        reference.run(6);
+        resumed.run(6);
+        assert_eq!(reference.grid().data, resumed.grid().data);
+        assert_eq!(reference.grid().is_empty, resumed.grid().is_empty);
+
+        // The draws of randomize continue too, and differ from one call to the next
+        reference.randomize();
+        let mut bytes = Vec::new();
+        reference.checkpoint().write_to(&mut bytes).unwrap();
+        let mut resumed = Simulation::from_checkpoint(Checkpoint::read_from(&mut bytes.as_slice()).unwrap());
+        let first = reference.grid().data.clone();
+        reference.randomize();
+        resumed.randomize();
+        assert_eq!(reference.grid().data, resumed.grid().data);
+        assert_ne!(reference.grid().data, first);
+    }
+
+    #[test]
+    fn test_randomize_draws_from_the_initialisation_ranges() {
+        let ranges = vec![(2.0, 3.0), (-1.0, -0.5)];
+        let mut simulation = Simulation::new(
+            8, 8,
+            0.5,
+            2,
+            ranges.clone(),
+            RulesRegistry::custom(vec![Rule::ConwayOptimized, Rule::ConwayOptimized]),
+            MovementRegistry::custom(8, 8, Movement::Static),
+            Neighborhood::moore(1),
+            Neighborhood::moore(1),
+            4,
+        );
+        let before = simulation.grid().clone();
+        simulation.randomize();
+        let grid = simulation.grid();
+        assert_ne!(grid.data, before.data);
+        assert_eq!(grid.is_empty, before.is_empty);
+        for (t, &(min, max)) in ranges.iter().enumerate() {
+            for r in 0..grid.height {
+                for c in 0..grid.width {
+                    if !grid.is_cell_empty(r, c) {
+                        assert!((min..=max).contains(&grid.get_cell_trait(r, c, t)));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_update_modes() {
+        let run = |update_mode: UpdateMode, movement: Movement, num_threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(num_threads).build().unwrap();
+            pool.install(|| {
+                let mut simulation = build_seeded_simulation(movement, 21);
+                simulation.resize(40, 30);
+                simulation.set_update_mode(update_mode);
+                simulation.run(3);
+                (simulation.grid().data.clone(), simulation.grid().is_empty.clone())
+            })
+        };
+        let synchronous = run(UpdateMode::Synchronous, Movement::Static, 1);
+        assert_eq!(run(UpdateMode::RandomIndependent(1.0), Movement::Static, 1), synchronous);
+        let mut frozen = build_seeded_simulation(Movement::Static, 21);
+        frozen.resize(40, 30);
+        let initial_data = frozen.grid().data.clone();
+        frozen.set_update_mode(UpdateMode::RandomIndependent(0.0));
+        frozen.run(3);
+        assert_eq!(frozen.grid().data, initial_data);
+
+        for update_mode in [
+            UpdateMode::RandomSequential,
+            UpdateMode::RandomIndependent(0.5),
+            UpdateMode::Checkerboard,
+            UpdateMode::FixedSweep,
+        ] {
+            let result = run(update_mode, Movement::Random, 1);
+            assert_eq!(run(update_mode, Movement::Random, 4), result, "{}", update_mode.name());
+            assert_ne!(run(update_mode, Movement::Static, 1), synchronous, "{}", update_mode.name());
+        }
+    }
+
+    #[test]
+    fn test_fixed_sweep_reads_updated_cells() {
+        let spread = |update_mode: UpdateMode| {
+            let mask = vec![vec![1; 3]; 3];
+            let mut rules_registry = RulesRegistry::custom(vec![
+                Rule::Expression(ExpressionParams::parse("max(self, max(nbr.self))").unwrap()),
+            ]);
+            rules_registry.set_trait_names(&["Signal".to_string()]).unwrap();
+            let mut simulation = Simulation::new(
+                6, 4,
+                1.0,
+                1,
+                vec![(0.0, 0.0)],
+                rules_registry,
+                MovementRegistry::custom(6, 4, Movement::Static),
+                Neighborhood::new(3, 3, 1, 1, mask.clone()),
+                Neighborhood::new(3, 3, 1, 1, mask),
+                0,
+            );
+            simulation.set_boundaries(Boundary::Fixed, Boundary::Fixed);
+            simulation.set_update_mode(update_mode);
+            simulation.grid_mut().set_cell_trait(0, 0, 0, 1.0);
+            simulation.step();
+            simulation.grid().data.iter().filter(|&&value| value == 1.0).count()
+        };
+        assert_eq!(spread(UpdateMode::Synchronous), 4);
+        // Every cell sees its already updated upper-left neighbors
+        assert_eq!(spread(UpdateMode::FixedSweep), 24);
+        // Each class reads the classes updated before it
+        assert_eq!(spread(UpdateMode::Checkerboard), 6);
+    }
+
+    #[test]
+    fn test_hexagonal_lattice_moves_to_adjacent_cells() {
+        let mut simulation = build_seeded_simulation(Movement::Random, 5);
+        simulation.set_lattice(Lattice::Hexagonal);
+        assert_eq!(*simulation.neighborhood_traits(), Neighborhood::hexagonal(1));
+        assert_eq!(*simulation.neighborhood_mvt(), Neighborhood::hexagonal(1));
+        let hexagonal = |mv: &(isize, isize)| *mv == (0, 0) || Lattice::Hexagonal.directions().contains(mv);
+
+        // Every neighbor above the cell, so that each movement draws among all of them
+        let mut grid = simulation.grid().clone();
+        grid.data.fill(1.0);
+        grid.set_cell_trait(4, 4, 0, 0.0);
+        let movements: [(MovementFnType, MovementParams); 3] = [
+            (MovementFunction::random_movement, MovementParams::defaults(Movement::Random)),
+            (MovementFunction::gradient, MovementParams { threshold: 0.0, ..MovementParams::defaults(Movement::Gradient) }),
+            (MovementFunction::avoid_crowding, MovementParams { threshold: 0.0, ..MovementParams::defaults(Movement::AvoidCrowding) }),
+        ];
+        for (movement, params) in movements {
+            let moves: std::collections::HashSet<_> = (0..200)
+                .map(|seed| {
+                    let mut rng = StepRandom::new(seed, 0).cell_rng(36, STREAM_MOVEMENT);
+                    movement(4, 4, simulation.neighborhood_mvt(), &grid, &params, &mut rng)
+                })
+                .collect();
+            assert!(moves.len() >= 6 && moves.iter().all(hexagonal), "{:?}", moves);
+        }
+
+        // A lone individual walking on the grid
+        simulation.grid_mut().is_empty.fill(true);
+        simulation.grid_mut().is_empty[4 * 8 + 4] = false;
+        let mut position = (4, 4);
+        for _ in 0..50 {
+            simulation.step();
+            let idx = simulation.grid().is_empty.iter().position(|&empty| !empty).unwrap();
+            let next = ((idx / 8) as isize, (idx % 8) as isize);
+            let mv = ((next.0 - position.0 + 12) % 8 - 4, (next.1 - position.1 + 12) % 8 - 4);
+            assert!(hexagonal(&mv), "{:?}", mv);
+            position = next;
+        }
+
+        simulation.set_lattice(Lattice::Square);
+        assert_eq!(*simulation.neighborhood_mvt(), Neighborhood::moore(1));
+    }
+
+    #[test]
+    fn test_checkerboard_classes_do_not_read_each_other() {
+        // Each cell one more than its largest neighbor: two neighbors updated in the same class
+        // would both read the other's old value and end up equal
+        let run = |mask: Vec<Vec<u8>>, width: usize, height: usize, boundary: Boundary| {
+            let mut rules_registry = RulesRegistry::custom(vec![
+                Rule::Expression(ExpressionParams::parse("max(nbr.self) + 1").unwrap()),
+            ]);
+            rules_registry.set_trait_names(&["Depth".to_string()]).unwrap();
+            rules_registry.set_domains(&[Domain::Unbounded]);
+            let neighborhood = Neighborhood::from_mask(mask);
+            let mut simulation = Simulation::new(
+                width, height,
+                1.0,
+                1,
+                vec![(0.0, 0.0)],
+                rules_registry,
+                MovementRegistry::custom(width, height, Movement::Static),
+                neighborhood.clone(),
+                neighborhood.clone(),
+                0,
+            );
+            simulation.set_boundaries(boundary, boundary);
+            simulation.set_update_mode(UpdateMode::Checkerboard);
+            simulation.step();
+            let grid = simulation.grid();
+            for idx in 0..grid.num_cells {
+                let (row, col) = (idx / width, idx % width);
+                for offset in neighborhood.neighbor_offsets() {
+                    if let Some((r, c)) = grid.get_position(row as isize + offset.dr, col as isize + offset.dc)
+                        && (r, c) != (row, col) {
+                        assert_ne!(grid.get_cell_trait(r, c, 0), grid.data[idx], "({}, {}) and ({}, {})", row, col, r, c);
+                    }
+                }
+            }
+        };
+        // Diagonal neighbors have the same (row + col) parity
+        run(vec![vec![1; 3]; 3], 8, 6, Boundary::Fixed);
+        // The parity does not wrap around odd periodic sides
+        run(vec![vec![0, 1, 0], vec![1, 1, 1], vec![0, 1, 0]], 7, 5, Boundary::Periodic);
+        run(vec![vec![1; 3]; 3], 7, 5, Boundary::Periodic);
+        run(vec![vec![1; 5]; 5], 11, 9, Boundary::Reflective);
+    }
+}
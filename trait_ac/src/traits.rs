@@ -0,0 +1,339 @@
+use crate::neighborhood::{Kernel, Neighborhood, Shape};
+use crate::utils::default_traits_names;
+use serde::Deserialize;
+
+
+/// Values a trait can take, the rules registry maps every rule output into it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Domain {
+    /// Clamped to [min, max]
+    Bounded(f32, f32),
+    /// Left as computed by the rule
+    Unbounded,
+    /// Wrapped into [min, max), like an angle
+    Cyclic(f32, f32),
+}
+
+impl Default for Domain {
+    /// The historical behaviour of the rules
+    fn default() -> Self {
+        Domain::Bounded(0.0, 1.0)
+    }
+}
+
+impl Domain {
+    pub const NAMES: &'static [&'static str] = &["bounded", "unbounded", "cyclic"];
+
+    #[inline]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Domain::Bounded(..) => "bounded",
+            Domain::Unbounded => "unbounded",
+            Domain::Cyclic(..) => "cyclic",
+        }
+    }
+
+    /// Domain of the given kind over [min, max] (ignored when unbounded)
+    pub fn from_name(name: &str, min: f32, max: f32) -> Option<Domain> {
+        match name {
+            "bounded" => Some(Domain::Bounded(min, max)),
+            "unbounded" => Some(Domain::Unbounded),
+            "cyclic" => Some(Domain::Cyclic(min, max)),
+            _ => None,
+        }
+    }
+
+    /// Map a rule output into the domain
+    #[inline(always)]
+    pub fn apply(&self, value: f32) -> f32 {
+        match *self {
+            Domain::Bounded(min, max) => value.clamp(min, max),
+            Domain::Unbounded => value,
+            Domain::Cyclic(min, max) => min + (value - min).rem_euclid(max - min),
+        }
+    }
+
+    /// (min, max) of a bounded or cyclic domain
+    #[inline]
+    pub fn bounds(&self) -> Option<(f32, f32)> {
+        match *self {
+            Domain::Bounded(min, max) | Domain::Cyclic(min, max) => Some((min, max)),
+            Domain::Unbounded => None,
+        }
+    }
+}
+
+
+/// Metadata of one trait, declared by a `[[traits]]` table in the config files
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct TraitInfo {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub unit: String,
+    /// Range the initial values are drawn from
+    #[serde(default = "unit_range")]
+    pub initial_range: (f32, f32),
+    /// "bounded" (clamped), "unbounded" or "cyclic" (wrapped)
+    #[serde(default = "default_domain", deserialize_with = "deserialize_domain")]
+    pub domain: String,
+    /// Bounds of a bounded or cyclic domain
+    #[serde(default = "unit_range")]
+    pub domain_range: (f32, f32),
+    /// Values mapped to the two ends of the color scale, the domain bounds when absent
+    /// (and the current extent of the values for unbounded traits)
+    #[serde(default)]
+    pub display_range: Option<(f32, f32)>,
+    /// Inactive traits are not updated by the rules
+    #[serde(default = "default_active")]
+    pub active: bool,
+    /// Preset or custom neighborhood read by the rule, e.g. { kind = "circular", radius = 5 }
+    #[serde(default)]
+    pub neighborhood: Option<Shape>,
+    /// Mask of the neighborhood read by the rule, the global one when absent (and without `neighborhood`)
+    #[serde(default)]
+    pub neighborhood_mask: Option<Vec<Vec<u8>>>,
+    /// Weights of that neighborhood (uniform when absent)
+    #[serde(default)]
+    pub neighborhood_kernel: Option<Kernel>,
+}
+
+fn unit_range() -> (f32, f32) {
+    (0.0, 1.0)
+}
+
+fn default_domain() -> String {
+    Domain::default().name().to_string()
+}
+
+fn deserialize_domain<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    if Domain::NAMES.contains(&name.as_str()) {
+        Ok(name)
+    } else {
+        Err(serde::de::Error::custom(format!(
+            "Unknown domain: '{}'. Valid domains are: {:?}",
+            name,
+            Domain::NAMES
+        )))
+    }
+}
+
+fn default_active() -> bool {
+    true
+}
+
+impl TraitInfo {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            description: String::new(),
+            unit: String::new(),
+            initial_range: unit_range(),
+            domain: default_domain(),
+            domain_range: unit_range(),
+            display_range: None,
+            active: default_active(),
+            neighborhood: None,
+            neighborhood_mask: None,
+            neighborhood_kernel: None,
+        }
+    }
+
+    /// Active traits named `Trait_<index>`, for runs without trait metadata (e.g. checkpoints)
+    pub fn defaults(num_traits: usize) -> Vec<Self> {
+        default_traits_names(num_traits).into_iter().map(Self::new).collect()
+    }
+
+    /// Name followed by the unit, if any
+    pub fn label(&self) -> String {
+        if self.unit.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{} [{}]", self.name, self.unit)
+        }
+    }
+
+    /// Domain declared by `domain` and `domain_range`
+    pub fn domain(&self) -> Domain {
+        let (min, max) = self.domain_range;
+        Domain::from_name(&self.domain, min, max).unwrap_or_default()
+    }
+
+    /// Own neighborhood declared by `neighborhood` or `neighborhood_mask`, weighted by `neighborhood_kernel`, if any
+    pub fn neighborhood(&self) -> Result<Option<Neighborhood>, &'static str> {
+        let neighborhood = match (&self.neighborhood, &self.neighborhood_mask) {
+            (Some(_), Some(_)) => return Err("trait neighborhood and neighborhood_mask are exclusive"),
+            (Some(shape), None) => Neighborhood::from_shape(shape)?,
+            (None, Some(mask)) => {
+                Neighborhood::check_mask(mask)?;
+                Neighborhood::from_mask(mask.clone())
+            }
+            (None, None) => {
+                return match self.neighborhood_kernel {
+                    Some(_) => Err("trait neighborhood_kernel requires neighborhood or neighborhood_mask"),
+                    None => Ok(None),
+                };
+            }
+        };
+        match &self.neighborhood_kernel {
+            Some(kernel) => neighborhood.with_kernel(kernel.clone()).map(Some),
+            None => Ok(Some(neighborhood)),
+        }
+    }
+
+    /// Fixed display range, if any (None for an unbounded trait without `display_range`)
+    pub fn display_bounds(&self) -> Option<(f32, f32)> {
+        self.display_range.or_else(|| self.domain().bounds())
+    }
+
+    pub fn validate(traits: &[TraitInfo]) -> Result<(), &'static str> {
+        if traits.is_empty() {
+            return Err("at least one [[traits]] entry is required");
+        }
+        for info in traits {
+            if info.name.is_empty() {
+                return Err("trait names must not be empty");
+            }
+            if info.initial_range.0 > info.initial_range.1 {
+                return Err("trait initial_range must be [min, max] with min <= max");
+            }
+            if info.domain_range.0 >= info.domain_range.1 {
+                return Err("trait domain_range must be [min, max] with min < max");
+            }
+            if let Some((min, max)) = info.display_range
+                && min >= max {
+                return Err("trait display_range must be [min, max] with min < max");
+            }
+            info.neighborhood()?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Initial range of every trait, in trait order
+pub fn initialisation_ranges(traits: &[TraitInfo]) -> Vec<(f32, f32)> {
+    traits.iter().map(|info| info.initial_range).collect()
+}
+
+/// Name of every trait, in trait order
+pub fn names(traits: &[TraitInfo]) -> Vec<String> {
+    traits.iter().map(|info| info.name.clone()).collect()
+}
+
+/// Domain of every trait, in trait order
+pub fn domains(traits: &[TraitInfo]) -> Vec<Domain> {
+    traits.iter().map(TraitInfo::domain).collect()
+}
+
+/// Position of `value` in [min, max], clamped to [0, 1] (for the color scales)
+#[inline(always)]
+pub fn normalize(value: f32, (min, max): (f32, f32)) -> f32 {
+    if max > min {
+        ((value - min) / (max - min)).clamp(0.0, 1.0)
+    } else {
+        0.5
+    }
+}
+
+/// Own neighborhood of every trait, in trait order (None for the global one)
+pub fn neighborhoods(traits: &[TraitInfo]) -> Result<Vec<Option<Neighborhood>>, &'static str> {
+    traits.iter().map(TraitInfo::neighborhood).collect()
+}
+
+/// 1 for active traits, 0 otherwise, in trait order
+pub fn active_mask(traits: &[TraitInfo]) -> Vec<u8> {
+    traits.iter().map(|info| info.active as u8).collect()
+}
+
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Config {
+        traits: Vec<TraitInfo>,
+    }
+
+    #[test]
+    fn test_traits_table() {
+        let config: Config = toml::from_str(r#"
+            [[traits]]
+            name = "Temperature"
+            unit = "K"
+            initial_range = [250.0, 300.0]
+            domain = "unbounded"
+            display_range = [200.0, 400.0]
+
+            [[traits]]
+            name = "Alive"
+            active = false
+            neighborhood_mask = [[0, 1, 0], [1, 0, 1], [0, 1, 0]]
+            neighborhood_kernel = { kind = "gaussian", sigma = 1.0 }
+
+            [[traits]]
+            name = "Angle"
+            domain = "cyclic"
+            domain_range = [-180.0, 180.0]
+            neighborhood = { kind = "circular", radius = 3 }
+        "#).unwrap();
+
+        assert!(TraitInfo::validate(&config.traits).is_ok());
+        assert_eq!(config.traits[0].label(), "Temperature [K]");
+        assert_eq!(normalize(300.0, config.traits[0].display_bounds().unwrap()), 0.5);
+        assert_eq!(config.traits[1].label(), "Alive");
+        assert_eq!(config.traits[1].display_bounds(), Some((0.0, 1.0)));
+        assert_eq!(
+            domains(&config.traits),
+            vec![Domain::Unbounded, Domain::Bounded(0.0, 1.0), Domain::Cyclic(-180.0, 180.0)]
+        );
+        assert_eq!(initialisation_ranges(&config.traits), vec![(250.0, 300.0), (0.0, 1.0), (0.0, 1.0)]);
+        assert_eq!(active_mask(&config.traits), vec![1, 0, 1]);
+        let neighborhoods = neighborhoods(&config.traits).unwrap();
+        assert!(neighborhoods[0].is_none());
+        assert_eq!(neighborhoods[2], Some(Neighborhood::circular(3)));
+        let von_neumann = neighborhoods[1].as_ref().unwrap();
+        assert_eq!((von_neumann.width, von_neumann.height, von_neumann.center_row, von_neumann.center_col), (3, 3, 1, 1));
+        assert_eq!(von_neumann.kernel(), &Kernel::Gaussian { sigma: 1.0 });
+    }
+
+    #[test]
+    fn test_domain_apply() {
+        assert_eq!(Domain::Bounded(-1.0, 1.0).apply(-3.0), -1.0);
+        assert_eq!(Domain::Unbounded.apply(-3.0), -3.0);
+        assert_eq!(Domain::Cyclic(0.0, 1.0).apply(1.25), 0.25);
+        assert_eq!(Domain::Cyclic(0.0, 1.0).apply(-0.25), 0.75);
+        assert_eq!(Domain::Cyclic(-180.0, 180.0).apply(190.0), -170.0);
+    }
+
+    #[test]
+    fn test_invalid_traits() {
+        assert!(TraitInfo::validate(&[]).is_err());
+        let mut info = TraitInfo::new("Charge");
+        info.display_range = Some((1.0, -1.0));
+        assert!(TraitInfo::validate(&[info]).is_err());
+        let mut info = TraitInfo::new("Charge");
+        info.neighborhood_mask = Some(vec![vec![1, 1], vec![1]]);
+        assert!(TraitInfo::validate(&[info]).is_err());
+        let mut info = TraitInfo::new("Charge");
+        info.neighborhood_kernel = Some(Kernel::InverseDistance);
+        assert!(TraitInfo::validate(&[info.clone()]).is_err());
+        info.neighborhood_mask = Some(vec![vec![1; 3]; 3]);
+        assert!(TraitInfo::validate(&[info.clone()]).is_ok());
+        info.neighborhood = Some(Shape::Cross { radius: 1 });
+        assert!(TraitInfo::validate(&[info.clone()]).is_err());
+        info.neighborhood = None;
+        info.neighborhood_kernel = Some(Kernel::Custom { weights: vec![vec![1.0; 2]; 3] });
+        assert!(TraitInfo::validate(&[info]).is_err());
+        assert!(toml::from_str::<Config>("[[traits]]\nname = \"Charge\"\ndomain = \"signed\"").is_err());
+    }
+}
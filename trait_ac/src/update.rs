@@ -0,0 +1,236 @@
+use crate::grid::{Boundary, Grid};
+
+
+/// Order in which the rules update the cells within a timestep
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum UpdateMode {
+    /// Every cell computed from the previous state (double buffered)
+    #[default]
+    Synchronous,
+    /// As many updates as cells, each of a uniformly drawn cell (with replacement) in place
+    RandomSequential,
+    /// Synchronous, but each cell only takes its new value with the given probability
+    RandomIndependent(f32),
+    /// Classes of cells that read none of each other (see `Colouring`), one after the other, each synchronous and in parallel
+    Checkerboard,
+    /// Every cell in place, row by row
+    FixedSweep,
+}
+
+impl UpdateMode {
+    pub const NAMES: &'static [&'static str] = &["synchronous", "random sequential", "random independent", "checkerboard", "fixed sweep"];
+    /// Update probability of a random independent mode created without one
+    pub const DEFAULT_PROBABILITY: f32 = 0.5;
+
+    #[inline]
+    pub fn name(&self) -> &'static str {
+        match self {
+            UpdateMode::Synchronous => "synchronous",
+            UpdateMode::RandomSequential => "random sequential",
+            UpdateMode::RandomIndependent(_) => "random independent",
+            UpdateMode::Checkerboard => "checkerboard",
+            UpdateMode::FixedSweep => "fixed sweep",
+        }
+    }
+
+    /// Mode of the given name, `probability` is only used by random independent
+    pub fn from_name(name: &str, probability: f32) -> Option<UpdateMode> {
+        match name {
+            "synchronous" => Some(UpdateMode::Synchronous),
+            "random sequential" => Some(UpdateMode::RandomSequential),
+            "random independent" => Some(UpdateMode::RandomIndependent(probability)),
+            "checkerboard" => Some(UpdateMode::Checkerboard),
+            "fixed sweep" => Some(UpdateMode::FixedSweep),
+            _ => None,
+        }
+    }
+
+    /// Update probability of a random independent mode
+    #[inline]
+    pub fn probability(&self) -> Option<f32> {
+        match *self {
+            UpdateMode::RandomIndependent(probability) => Some(probability),
+            _ => None,
+        }
+    }
+
+    /// Cells read the values already updated during the timestep, one at a time
+    #[inline]
+    pub fn is_sequential(&self) -> bool {
+        matches!(self, UpdateMode::RandomSequential | UpdateMode::FixedSweep)
+    }
+
+    /// The rules update whole rows with `apply_row`, the other modes call `apply` cell by cell
+    #[inline]
+    pub fn updates_rows(&self) -> bool {
+        matches!(self, UpdateMode::Synchronous | UpdateMode::RandomIndependent(_))
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if let Some(probability) = self.probability()
+            && !(0.0..=1.0).contains(&probability) {
+            return Err("update_probability must be between 0.0 and 1.0");
+        }
+        Ok(())
+    }
+}
+
+
+/// Classes of the checkerboard mode: no cell reads a cell of its own class, so a class updates in
+/// parallel and its cells read the classes before it already updated. Cells are coloured
+/// (a · row + col) mod m with the smallest m for which no read offset keeps the colour, which is
+/// the red/black parity for von Neumann neighborhoods and four colours for the Moore one.
+/// Where the boundaries map reads across an edge onto a cell of the same colour (periodic sides
+/// the colours do not divide, reflective edges), the cell takes the first colour none of its
+/// neighbors has
+#[derive(Clone, Debug)]
+pub struct Colouring {
+    /// Grid dimensions and boundaries the classes were built for
+    dimensions: (usize, usize, Boundary, Boundary),
+    /// Read offsets the classes were built for
+    offsets: Vec<(isize, isize)>,
+    /// Row-major indices of the cells of each class, in update order
+    classes: Vec<Vec<usize>>,
+}
+
+impl Colouring {
+    /// Classes of the cells of `grid` for rules reading the cells at `offsets` around the updated one
+    pub fn new(grid: &Grid, offsets: &[(isize, isize)]) -> Self {
+        // A cell must not read nor be read by a cell of its colour
+        let mut related: Vec<(isize, isize)> = offsets
+            .iter()
+            .flat_map(|&(dr, dc)| [(dr, dc), (-dr, -dc)])
+            .filter(|&offset| offset != (0, 0))
+            .collect();
+        related.sort_unstable();
+        related.dedup();
+
+        let (a, m) = Self::linear(grid, &related);
+        let mut colours: Vec<usize> = (0..grid.num_cells).map(|idx| (a * (idx / grid.width) + idx % grid.width) % m).collect();
+
+        // Within this band of the edges the boundaries decide which cell is read
+        let reach_r = related.iter().map(|&(dr, _)| dr.unsigned_abs()).max().unwrap_or(0);
+        let reach_c = related.iter().map(|&(_, dc)| dc.unsigned_abs()).max().unwrap_or(0);
+        let mut neighbor_colours = Vec::with_capacity(related.len());
+        for idx in 0..grid.num_cells {
+            let (row, col) = (idx / grid.width, idx % grid.width);
+            if row >= reach_r && row + reach_r < grid.height && col >= reach_c && col + reach_c < grid.width {
+                continue;
+            }
+            neighbor_colours.clear();
+            for &(dr, dc) in &related {
+                if let Some((r, c)) = grid.get_position(row as isize + dr, col as isize + dc) {
+                    let neighbor = r * grid.width + c;
+                    if neighbor != idx {
+                        neighbor_colours.push(colours[neighbor]);
+                    }
+                }
+            }
+            if neighbor_colours.contains(&colours[idx]) {
+                colours[idx] = (0..).find(|colour| !neighbor_colours.contains(colour)).unwrap();
+            }
+        }
+
+        let mut classes = vec![Vec::new(); colours.iter().max().map_or(0, |&colour| colour + 1)];
+        for (idx, &colour) in colours.iter().enumerate() {
+            classes[colour].push(idx);
+        }
+        classes.retain(|class| !class.is_empty());
+        Self {
+            dimensions: (grid.width, grid.height, grid.row_boundary, grid.col_boundary),
+            offsets: offsets.to_vec(),
+            classes,
+        }
+    }
+
+    /// (a, m) of the smallest linear colouring separating the related offsets, one that also
+    /// wraps around the periodic sides if there is one of no more colours than the fallback
+    fn linear(grid: &Grid, related: &[(isize, isize)]) -> (usize, usize) {
+        let reach_r = related.iter().map(|&(dr, _)| dr.unsigned_abs()).max().unwrap_or(0);
+        let reach_c = related.iter().map(|&(_, dc)| dc.unsigned_abs()).max().unwrap_or(0);
+        // (2 reach_c + 1) · row + col separates every offset of the bounding box
+        let fallback = (2 * reach_c + 1, (2 * reach_r + 1) * (2 * reach_c + 1));
+        let separates = |a: usize, m: usize| {
+            related.iter().all(|&(dr, dc)| (a as isize * dr + dc).rem_euclid(m as isize) != 0)
+        };
+        let wraps = |a: usize, m: usize| {
+            (grid.col_boundary != Boundary::Periodic || grid.width.is_multiple_of(m))
+                && (grid.row_boundary != Boundary::Periodic || (a * grid.height).is_multiple_of(m))
+        };
+        for wrapping in [true, false] {
+            for m in 1..=fallback.1 {
+                if let Some(a) = (0..m).find(|&a| separates(a, m) && (!wrapping || wraps(a, m))) {
+                    return (a, m);
+                }
+            }
+        }
+        fallback
+    }
+
+    /// The classes were built for the dimensions and boundaries of `grid` and these offsets
+    pub fn fits(&self, grid: &Grid, offsets: &[(isize, isize)]) -> bool {
+        self.dimensions == (grid.width, grid.height, grid.row_boundary, grid.col_boundary) && self.offsets == offsets
+    }
+
+    /// Row-major indices of the cells of each class, in update order
+    #[inline]
+    pub fn classes(&self) -> &[Vec<usize>] {
+        &self.classes
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neighborhood::Neighborhood;
+
+    #[test]
+    fn test_colouring() {
+        let offsets = |neighborhood: Neighborhood| -> Vec<(isize, isize)> {
+            neighborhood.offsets().iter().map(|offset| (offset.dr, offset.dc)).collect()
+        };
+        let cases = [
+            (offsets(Neighborhood::von_neumann(1)), 2),
+            (offsets(Neighborhood::moore(1)), 4),
+            (offsets(Neighborhood::moore(2)), 9),
+            (offsets(Neighborhood::hexagonal(1)), 3),
+            (offsets(Neighborhood::cross(2)), 3),
+            (vec![(0, 0)], 1),
+        ];
+        for (offsets, colours) in &cases {
+            for (width, height) in [(12, 12), (9, 7), (5, 3)] {
+                for boundary in Boundary::ALL {
+                    let mut grid = Grid::new(width, height, 1);
+                    grid.set_boundaries(*boundary, *boundary);
+                    let colouring = Colouring::new(&grid, offsets);
+                    assert!(colouring.fits(&grid, offsets));
+                    // The linear colouring wraps around periodic sides its colours divide
+                    if *boundary != Boundary::Periodic || ((width, height) == (12, 12) && 12 % colours == 0) {
+                        assert_eq!(colouring.classes().len(), *colours, "{:?} {:?}", offsets, boundary);
+                    }
+
+                    let mut class_of = vec![usize::MAX; grid.num_cells];
+                    for (class, cells) in colouring.classes().iter().enumerate() {
+                        for &idx in cells {
+                            assert_eq!(class_of[idx], usize::MAX);
+                            class_of[idx] = class;
+                        }
+                    }
+                    for idx in 0..grid.num_cells {
+                        let (row, col) = (idx / width, idx % width);
+                        for &(dr, dc) in offsets {
+                            if let Some((r, c)) = grid.get_position(row as isize + dr, col as isize + dc) {
+                                let read = r * width + c;
+                                assert!(read == idx || class_of[read] != class_of[idx], "{:?} {:?} {}x{}", offsets, boundary, width, height);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        let grid = Grid::new(12, 12, 1);
+        assert!(!Colouring::new(&grid, &cases[0].0).fits(&Grid::new(12, 10, 1), &cases[0].0));
+        assert!(!Colouring::new(&grid, &cases[0].0).fits(&grid, &cases[1].0));
+    }
+}
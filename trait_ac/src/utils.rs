@@ -1,26 +1,45 @@
 use crate::grid::Grid;
+use crate::neighborhood::Shape;
 use crate::rules::RulesRegistry;
+use crate::traits::TraitInfo;
 
 
 
-/// Print active trait indices with their names
-pub fn print_active_traits(num_traits: usize, active_mask: &[u8], trait_names: &[String], rules_registry: &RulesRegistry) {
+/// Print active trait indices with their names, units and descriptions
+pub fn print_active_traits(traits: &[TraitInfo], active_mask: &[u8], rules_registry: &RulesRegistry) {
     println!("  Active traits:");
-    for trait_index in 0..num_traits {
-        if active_mask[trait_index] == 0 {
+    for (trait_index, info) in traits.iter().enumerate() {
+        if active_mask.get(trait_index).copied().unwrap_or(0) == 0 {
             continue;
         }
-        let trait_name = &trait_names[trait_index];
-        let rule_name = rules_registry.get_rule_name(trait_index);
-        println!("    {}: {} (rule: {})", trait_index, trait_name, rule_name);
+        let rule = rules_registry.get_rule(trait_index);
+        let params: Vec<String> = rule.rulestring()
+            .into_iter()
+            .chain(rule.params().iter().map(|(name, value)| format!("{} = {}", name, value)))
+            .chain(rule.input_names().iter().filter_map(|&role| {
+                let input = rule.get_input(role)?;
+                Some(format!("{} = {}", role, traits.get(input).map_or(input.to_string(), |info| info.name.clone())))
+            }))
+            .collect();
+        if params.is_empty() {
+            println!("    {}: {} (rule: {})", trait_index, info.label(), rule.name());
+        } else {
+            println!("    {}: {} (rule: {} [{}])", trait_index, info.label(), rule.name(), params.join(", "));
+        }
+        if !info.description.is_empty() {
+            println!("       {}", info.description);
+        }
+        if let Some(neighborhood) = rules_registry.get_neighborhood(trait_index) {
+            println!("       own neighborhood: {} {}x{}", Shape::of(neighborhood).name(), neighborhood.width, neighborhood.height);
+        }
     }
 }
 
 /// Print a single trait array in row-major order
-pub fn print_trait_array(grid: &Grid, trait_index: usize, trait_names: &[String]) {
+pub fn print_trait_array(grid: &Grid, trait_index: usize, traits: &[TraitInfo]) {
     let values = grid.get_trait_slice(trait_index);
     
-    println!("\n=== Trait {} ({}) ===", trait_index, trait_names[trait_index]);
+    println!("\n=== Trait {} ({}) ===", trait_index, traits[trait_index].label());
     println!("[");
     
     for row in 0..grid.height {
@@ -43,24 +62,25 @@ pub fn print_trait_array(grid: &Grid, trait_index: usize, trait_names: &[String]
 }
 
 /// Print all active trait arrays
-pub fn print_active_traits_array(grid: &Grid, active_mask: &[u8], trait_names: &[String]) {
-    for trait_index in 0..grid.num_traits {
-        if active_mask[trait_index] == 0 {
+pub fn print_active_traits_array(grid: &Grid, active_mask: &[u8], traits: &[TraitInfo]) {
+    for (trait_index, &active) in active_mask.iter().enumerate().take(grid.num_traits) {
+        if active == 0 {
             continue;
         }
-        print_trait_array(grid, trait_index, trait_names);
+        print_trait_array(grid, trait_index, traits);
     }
 }
 
 /// Print grid statistics
-pub fn print_statistics(grid: &Grid, active_mask: &[u8; 9]) {
+pub fn print_statistics(grid: &Grid, active_mask: &[u8]) {
     println!("\n=== Grid Statistics ===");
     println!("Grid size: {}x{}", grid.width, grid.height);
     println!("Total cells: {}", grid.width * grid.height);
-    println!("Active traits: {}/9", active_mask.iter().sum::<u8>());
+    let num_active = active_mask.iter().take(grid.num_traits).filter(|&&m| m != 0).count();
+    println!("Active traits: {}/{}", num_active, grid.num_traits);
 
-    for trait_index in 0..grid.num_traits {
-        if active_mask[trait_index] == 0 {
+    for (trait_index, &active) in active_mask.iter().enumerate().take(grid.num_traits) {
+        if active == 0 {
             continue;
         }
         let values = grid.get_trait_slice(trait_index);
@@ -76,35 +96,11 @@ pub fn print_statistics(grid: &Grid, active_mask: &[u8; 9]) {
     }
 }
 
-/// Create default trait names
-pub fn default_traits_names() -> Vec<String> {
-    vec![
-        "Trait_0_0".to_string(),
-        "Trait_0_1".to_string(),
-        "Trait_0_2".to_string(),
-        "Trait_1_0".to_string(),
-        "Trait_1_1".to_string(),
-        "Trait_1_2".to_string(),
-        "Trait_2_0".to_string(),
-        "Trait_2_1".to_string(),
-        "Trait_2_2".to_string(),
-    ]
+/// Create default trait names, one per trait
+pub fn default_traits_names(num_traits: usize) -> Vec<String> {
+    (0..num_traits).map(|i| format!("Trait_{}", i)).collect()
 }
 
-/// Create semantic trait names for examples
-pub fn semantic_traits_names() -> Vec<String> {
-    vec![
-        "Energy".to_string(),
-        "Charge".to_string(),
-        "Phase".to_string(),
-        "Aggression".to_string(),
-        "Stability".to_string(),
-        "Mobility".to_string(),
-        "Resource".to_string(),
-        "Age".to_string(),
-        "Adaptability".to_string(),
-    ]
-}
 
 /// Print a horizontal separator
 pub fn print_separator() {
@@ -121,9 +117,9 @@ mod tests {
 
     #[test]
     fn test_traits_names() {
-        let names = default_traits_names();
-        assert_eq!(names.len(), 9);
-        assert_eq!(names[0], "Trait_0_0");
-        assert_eq!(names[8], "Trait_2_2");
+        let names = default_traits_names(24);
+        assert_eq!(names.len(), 24);
+        assert_eq!(names[0], "Trait_0");
+        assert_eq!(names[23], "Trait_23");
     }
 }
@@ -1,46 +1,10 @@
-use trait_ac::rules::Rule;
-use trait_ac::movement::Movement;
+use trait_ac::config::EngineConfig;
 
 use crate::color_scheme::ColorScheme;
 
 use serde::Deserialize;
 use std::fs;
 
-// Custom deserializer for Rule
-fn deserialize_rules<'de, D>(deserializer: D) -> Result<Vec<Rule>, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    let names: Vec<String> = Vec::deserialize(deserializer)?;
-    names
-        .into_iter()
-        .map(|name| {
-            Rule::from_name(&name).ok_or_else(|| {
-                serde::de::Error::custom(format!(
-                    "Unknown rule: '{}'. Valid rules are: {:?}",
-                    name,
-                    Rule::NAMES
-                ))
-            })
-        })
-        .collect()
-}
-
-// Custom deserializer for Movement
-fn deserialize_movement<'de, D>(deserializer: D) -> Result<Movement, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    let name = String::deserialize(deserializer)?;
-    Movement::from_name(&name).ok_or_else(|| {
-        serde::de::Error::custom(format!(
-            "Unknown movement: '{}'. Valid movements are: {:?}",
-            name,
-            Movement::NAMES
-        ))
-    })
-}
-
 // Custom deserializer for ColorScheme
 fn deserialize_color_scheme<'de, D>(deserializer: D) -> Result<ColorScheme, D::Error>
 where
@@ -60,17 +24,18 @@ where
 #[derive(Deserialize)]
 #[serde(default)]
 pub struct Config {
-    // Grid settings
-    pub grid_width: usize,
-    pub grid_height: usize,
-    pub grid_density: f32,
-    pub num_traits: usize,
+    // Grid, traits, rules, movement and neighborhoods
+    #[serde(flatten)]
+    pub engine: EngineConfig,
 
     // Simulation timing
     pub steps_per_second: f32,
     pub timed_simulation: bool,
     pub timestep_max: usize,
 
+    // File used by the Save/Load checkpoint buttons
+    pub checkpoint_path: String,
+
     // Grid bounds
     pub grid_width_min: usize,
     pub grid_width_max: usize,
@@ -100,33 +65,19 @@ pub struct Config {
     pub base_color_not_empty_min: f32,
     pub base_color_not_empty_max: f32,
 
-    // Trait settings
-    pub active_mask: Vec<u8>,
+    // Trait shown at startup
     pub initial_selected_trait: usize,
-    pub initialisation_ranges: Vec<(f32, f32)>,
-
-    // Rules & movement
-    #[serde(deserialize_with = "deserialize_rules")]
-    pub rules: Vec<Rule>,
-    #[serde(deserialize_with = "deserialize_movement")]
-    pub movement: Movement,
-
-    // Neighborhood masks
-    pub neighborhood_traits_mask: Vec<Vec<u8>>,
-    pub neighborhood_mvt_mask: Vec<Vec<u8>>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            grid_width: 500,
-            grid_height: 500,
-            grid_density: 1.0,
-            num_traits: 3,
+            engine: EngineConfig::default(),
 
             steps_per_second: 25.0,
             timed_simulation: false,
             timestep_max: 100,
+            checkpoint_path: "checkpoint.bin".to_string(),
 
             grid_width_min: 3,
             grid_width_max: 5000,
@@ -149,36 +100,7 @@ impl Default for Config {
             base_color_not_empty_min: 0.0,
             base_color_not_empty_max: 1.0,
 
-            active_mask: vec![
-                1, 1, 1,
-                0, 0, 0,
-                0, 0, 0,
-            ],
             initial_selected_trait: 0,
-
-            initialisation_ranges: vec![
-                (0.0, 1.0), (0.0, 1.0), (0.0, 1.0),
-                (0.0, 1.0), (0.0, 1.0), (0.0, 1.0),
-                (0.0, 1.0), (0.0, 1.0), (0.0, 1.0),
-            ],
-
-            rules: vec![
-                Rule::Energy,          Rule::Charge,          Rule::Phase,
-                Rule::ConwayOptimized, Rule::ConwayOptimized, Rule::ConwayOptimized,
-                Rule::ConwayOptimized, Rule::ConwayOptimized, Rule::ConwayOptimized,
-            ],
-            movement: Movement::EnergyChargePhase,
-
-            neighborhood_traits_mask: vec![
-                vec![1, 1, 1],
-                vec![1, 1, 1],
-                vec![1, 1, 1],
-            ],
-            neighborhood_mvt_mask: vec![
-                vec![1, 1, 1],
-                vec![1, 1, 1],
-                vec![1, 1, 1],
-            ],
         }
     }
 }
@@ -186,24 +108,19 @@ impl Default for Config {
 impl Config {
     pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
         let content = fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&content)?;
+        let mut config: Config = toml::from_str(&content)?;
+        config.engine.resolve()?;
         config.validate()?;
         Ok(config)
     }
 
     fn validate(&self) -> Result<(), &'static str> {
-        if self.grid_width < self.grid_width_min || self.grid_width > self.grid_width_max {
+        if self.engine.grid_width < self.grid_width_min || self.engine.grid_width > self.grid_width_max {
             return Err("grid_width is out of bounds");
         }
-        if self.grid_height < self.grid_height_min || self.grid_height > self.grid_height_max {
+        if self.engine.grid_height < self.grid_height_min || self.engine.grid_height > self.grid_height_max {
             return Err("grid_height is out of bounds");
         }
-        if !(0.0..=1.0).contains(&self.grid_density) {
-            return Err("grid_density must be between 0.0 and 1.0");
-        }
-        if self.num_traits <= 0 {
-            return Err("num_traits must be > 0");
-        }
         if self.steps_per_second < self.steps_per_second_min
             || self.steps_per_second > self.steps_per_second_max
         {
@@ -227,28 +144,7 @@ impl Config {
         if self.base_color_not_empty_max < 0.0 || self.base_color_not_empty_max > 1.0 {
             return Err("base_color_not_empty_max should be between 0.0 and 1.0");
         }
-        if self.active_mask.is_empty() {
-            return Err("active_mask must not be empty");
-        }
-        // Check that no active bit in active_mask is at an index >= num_traits
-        let max_active_index = self.active_mask
-            .iter()
-            .enumerate()
-            .filter(|&(_, &m)| m != 0)
-            .map(|(i, _)| i)
-            .max();
-        if let Some(max_idx) = max_active_index {
-            if max_idx >= self.num_traits as usize {
-                return Err("active_mask has active traits indexes beyond 'num_traits' (only the indexes of active_mask from 0 to 'num_traits' can be used)");
-            }
-        }
-        if self.initialisation_ranges.is_empty() {
-            return Err("initialisation_ranges must not be empty");
-        }
-        if self.rules.is_empty() {
-            return Err("rules must not be empty");
-        }
-        if self.initial_selected_trait >= self.num_traits {
+        if self.initial_selected_trait >= self.engine.traits.len() {
             return Err("initial_selected_trait is out of bounds");
         }
         Ok(())
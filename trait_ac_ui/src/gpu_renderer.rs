@@ -3,6 +3,58 @@ use crate::color_scheme::ColorScheme;
 use eframe::{egui, glow};
 use std::sync::Arc;
 use eframe::glow::HasContext;
+use trait_ac::grid::{Boundary, Grid, Lattice};
+
+
+/// Placement of the cells in the texture. Square cells are a texel each; hexagonal cells are
+/// staggered bricks of two texels, each row shifted one texel (half a cell) right of the row above,
+/// wrapping around when the columns are periodic so that the texture stays a rectangle
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TextureLayout {
+    /// Texels per cell along a row
+    pub texels_per_cell: usize,
+    pub width: usize,
+    pub height: usize,
+    /// Texels a row is shifted right of the row above
+    stagger: usize,
+    /// The rows wrap around at the texture width
+    wrap: bool,
+}
+
+impl TextureLayout {
+    pub fn of(grid: &Grid) -> Self {
+        match grid.lattice {
+            Lattice::Square => Self { texels_per_cell: 1, width: grid.width, height: grid.height, stagger: 0, wrap: false },
+            Lattice::Hexagonal => {
+                // Without wrapping the grid is drawn as a parallelogram
+                let wrap = grid.col_boundary == Boundary::Periodic;
+                let width = if wrap { 2 * grid.width } else { 2 * grid.width + grid.height - 1 };
+                Self { texels_per_cell: 2, width, height: grid.height, stagger: 1, wrap }
+            }
+        }
+    }
+
+    /// Texel column of the left half of a cell, the right half being the next one (modulo the width)
+    #[inline(always)]
+    pub fn texel(&self, row: usize, col: usize) -> usize {
+        let texel = col * self.texels_per_cell + row * self.stagger;
+        if self.wrap { texel % self.width } else { texel }
+    }
+
+    /// Horizontal position of the center of a cell, in cells from the left of the texture
+    #[inline]
+    pub fn cell_x(&self, row: usize, col: usize) -> f32 {
+        (self.texel(row, col) as f32 + self.texels_per_cell as f32 / 2.0) / self.texels_per_cell as f32
+    }
+
+    /// Size of the whole texture drawn with cells of `cell_size` points
+    pub fn content_size(&self, cell_size: f32) -> egui::Vec2 {
+        egui::vec2(
+            self.width as f32 * cell_size / self.texels_per_cell as f32,
+            self.height as f32 * cell_size,
+        )
+    }
+}
 
 
 pub struct GPURenderer {
@@ -42,6 +94,8 @@ impl GPURenderer {
         }
     }
     
+    /// # Safety
+    /// `gl` must be the current OpenGL context of the calling thread.
     pub unsafe fn create_program(gl: &glow::Context, vs: &str, fs: &str) -> Result<glow::Program, String> {
         let program = unsafe { gl.create_program()? };
         let v = unsafe { gl.create_shader(glow::VERTEX_SHADER)? };
@@ -121,10 +175,10 @@ impl GPURenderer {
             
             // Calculate what portion of the texture is visible
             // Clamp to [0, 1] range to avoid repeating
-            let tex_min_x = (scroll_offset.x / content_size.x).max(0.0).min(1.0);
-            let tex_min_y = (scroll_offset.y / content_size.y).max(0.0).min(1.0);
-            let tex_max_x = ((scroll_offset.x + rect_size.x) / content_size.x).max(0.0).min(1.0);
-            let tex_max_y = ((scroll_offset.y + rect_size.y) / content_size.y).max(0.0).min(1.0);
+            let tex_min_x = (scroll_offset.x / content_size.x).clamp(0.0, 1.0);
+            let tex_min_y = (scroll_offset.y / content_size.y).clamp(0.0, 1.0);
+            let tex_max_x = ((scroll_offset.x + rect_size.x) / content_size.x).clamp(0.0, 1.0);
+            let tex_max_y = ((scroll_offset.y + rect_size.y) / content_size.y).clamp(0.0, 1.0);
             
             // Calculate how much of the viewport actually contains texture
             let visible_content_width = (content_size.x - scroll_offset.x).max(0.0).min(rect_size.x);
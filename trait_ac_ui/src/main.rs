@@ -1,18 +1,25 @@
-use trait_ac::grid::Grid;
-use trait_ac::neighborhood::Neighborhood;
-use trait_ac::rules::{RulesRegistry, Rule};
-use trait_ac::movement::{MovementRegistry, Movement};
-use trait_ac::utils::{semantic_traits_names, print_separator, print_active_traits};
+use trait_ac::grid::{Boundary, Lattice};
+use trait_ac::neighborhood::{Kernel, Neighborhood, Shape};
+use trait_ac::rules;
+use trait_ac::movement::{MovementConfig, Movement};
+use trait_ac::random::random_seed;
+use trait_ac::simulation::Simulation;
+use trait_ac::traits::{self, TraitInfo};
+use trait_ac::update::UpdateMode;
+use trait_ac::utils::{print_separator, print_active_traits};
 
 use trait_ac_ui::color_scheme::ColorScheme;
 use trait_ac_ui::config::Config;
-use trait_ac_ui::gpu_renderer::GPURenderer;
+use trait_ac_ui::gpu_renderer::{GPURenderer, TextureLayout};
 
 use eframe::{egui, glow};
 use rayon::prelude::*;
 use std::time::Instant;
 use std::sync::{Arc, Mutex};
 
+/// Largest radius of the own neighborhoods set from the rule panel
+const MAX_NEIGHBORHOOD_RADIUS: usize = 7;
+
 fn main() -> eframe::Result<()> {
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
@@ -34,17 +41,18 @@ fn main() -> eframe::Result<()> {
 
 struct CAApp {
     // Grid state
-    grid: Grid,
+    simulation: Simulation,
+    grid_width: usize,
+    grid_height: usize,
     grid_density: f32,
     num_traits: usize,
-    initialisation_ranges: Vec<(f32, f32)>,
-    next_grid: Grid,
     
     // Simulation state
     initialized: bool,
-    timestep: usize,
     timed_simulation: bool,
     timestep_max: usize,
+    fixed_seed: bool,
+    checkpoint_path: String,
     start: Instant,
     is_playing: bool,
     steps_per_second: f32,
@@ -58,18 +66,12 @@ struct CAApp {
     
     // Configuration
     active_mask: Vec<u8>,
-    active_traits: Vec<usize>,
-    neighborhood_traits: Neighborhood,
-    neighborhood_mvt: Neighborhood,
-    rules_registry: RulesRegistry,
-    movement_registry: MovementRegistry,
     grid_width_min: usize,
     grid_width_max: usize,
     grid_height_min: usize,
     grid_height_max: usize,
     steps_per_second_min: f32,
     steps_per_second_max: f32,
-    rows_per_batch: usize,
     
     // Visualization
     selected_trait: usize,
@@ -84,8 +86,12 @@ struct CAApp {
     cell_size_min: f32,
     cell_size_max: f32,
     
-    // Trait names
-    trait_names: Vec<String>,
+    // Trait metadata (names, units, display ranges)
+    traits: Vec<TraitInfo>,
+    // Trait mapping of the movement inputs, reapplied when the movement changes
+    movement_params: MovementConfig,
+    // Rulestrings being edited, one per trait
+    rulestring_edits: Vec<String>,
 }
 
 impl CAApp {
@@ -106,78 +112,24 @@ impl CAApp {
             std::process::exit(1);
         });
 
-        let trait_names = semantic_traits_names();
-
-        let rules_registry = RulesRegistry::custom(config.rules);
-        let movement_registry = MovementRegistry::custom(
-            config.grid_width,
-            config.grid_height,
-            config.movement,
-        );
-
-        let grid = Grid::new_with_density(
-            config.grid_width,
-            config.grid_height,
-            config.grid_density,
-            config.num_traits,
-            &config.initialisation_ranges,
-        );
-
-        let neighborhood_traits_height = config.neighborhood_traits_mask.len();
-        let neighborhood_traits_width = config.neighborhood_traits_mask[0].len();
-        let neighborhood_traits_center_row = (neighborhood_traits_height - 1) / 2;
-        let neighborhood_traits_center_col = (neighborhood_traits_width - 1) / 2;
-
-        let neighborhood_mvt_height = config.neighborhood_mvt_mask.len();
-        let neighborhood_mvt_width = config.neighborhood_mvt_mask[0].len();
-        let neighborhood_mvt_center_row = (neighborhood_mvt_height - 1) / 2;
-        let neighborhood_mvt_center_col = (neighborhood_mvt_width - 1) / 2;
-
-        let neighborhood_traits = Neighborhood::new(
-            neighborhood_traits_width,
-            neighborhood_traits_height,
-            neighborhood_traits_center_row,
-            neighborhood_traits_center_col,
-            config.neighborhood_traits_mask,
-        );
-
-        let neighborhood_mvt = Neighborhood::new(
-            neighborhood_mvt_width,
-            neighborhood_mvt_height,
-            neighborhood_mvt_center_row,
-            neighborhood_mvt_center_col,
-            config.neighborhood_mvt_mask,
-        );
-
-        // Pre-allocate next grid
-        let next_grid = Grid {
-            width: grid.width,
-            height: grid.height,
-            num_cells: grid.num_cells,
-            num_traits: grid.num_traits,
-            data: grid.data.clone(),
-            is_empty: grid.is_empty.clone(),
-        };
-
-        let rows_per_batch = std::cmp::max(1, 4000 / grid.width);
 
-        let active_traits: Vec<usize> = config.active_mask
-            .iter()
-            .enumerate()
-            .filter_map(|(i, &m)| if m != 0 { Some(i) } else { None })
-            .collect();
+        let simulation = Simulation::from_config(&config.engine).unwrap_or_else(|e| {
+            eprintln!("Config error: {}", e);
+            std::process::exit(1);
+        });
         
         Self {
-            grid,
-            grid_density:config.grid_density,
-            num_traits: config.num_traits,
-            initialisation_ranges: config.initialisation_ranges,
-            next_grid,
+            simulation,
+            grid_width: config.engine.grid_width,
+            grid_height: config.engine.grid_height,
+            grid_density: config.engine.grid_density,
+            num_traits: config.engine.traits.len(),
 
             initialized: false,
-            timestep: 0,
             timed_simulation: config.timed_simulation,
             timestep_max: config.timestep_max,
+            fixed_seed: config.engine.seed.is_some(),
+            checkpoint_path: config.checkpoint_path,
             start: Instant::now(), // placeholder, it will be initialized in step_simulation at timestep 0
             is_playing: config.timed_simulation,
             steps_per_second: config.steps_per_second,
@@ -189,19 +141,13 @@ impl CAApp {
             avg_step_time: None,
             simulation_time: 0.0,
 
-            active_mask: config.active_mask,
-            active_traits,
-            neighborhood_traits,
-            neighborhood_mvt,
-            rules_registry,
-            movement_registry,
+            active_mask: traits::active_mask(&config.engine.traits),
             grid_width_min: config.grid_width_min,
             grid_width_max: config.grid_width_max,
             grid_height_min: config.grid_height_min,
             grid_height_max: config.grid_height_max,
             steps_per_second_min: config.steps_per_second_min,
             steps_per_second_max: config.steps_per_second_max,
-            rows_per_batch,
             
             selected_trait: config.initial_selected_trait,
             cell_size: config.cell_size,
@@ -215,70 +161,83 @@ impl CAApp {
             cell_size_min: config.cell_size_min,
             cell_size_max: config.cell_size_max,
 
-            trait_names,
+            traits: config.engine.traits,
+            movement_params: config.engine.movement_params,
+            rulestring_edits: Vec::new(),
         }
     }
 
     fn step_simulation(&mut self) {
-        if self.timestep == 0 {
+        if self.simulation.timestep() == 0 {
             self.start = Instant::now();
         }
-        let width = self.grid.width;
- 
-        // Sequential over active traits (small number), parallel over rows
-        for &trait_idx in &self.active_traits {
-            let current = self.grid.get_trait_slice(trait_idx);
-            let next_trait = self.next_grid.get_trait_slice_mut(trait_idx);
-            
-            // Process rows in parallel
-            next_trait
-                .par_chunks_mut(width)
-                .enumerate()
-                .for_each(|(row, next_row)| {
-                    let row_offset = row * width;
-                    
-                    for col in 0..width {
-                        let idx = row_offset + col;
-                        
-                        next_row[col] = if self.grid.is_empty[idx] {
-                            current[idx]
-                        } else {
-                            self.rules_registry.apply_rule(trait_idx, row, col, &self.neighborhood_traits, &self.grid)
-                        };
-                    }
-                });
-        }
-
-        // --- STEP 2: Movement ---
-        self.movement_registry.apply_movement(
-            &self.neighborhood_mvt,
-            &mut self.grid,
-            &mut self.next_grid,
-        );
-        
-        self.timestep += 1;
+        self.simulation.step();
     }
     
     fn reset_grid(&mut self) {
-        self.grid = Grid::new_with_density(self.grid.width, self.grid.height, self.grid_density, self.num_traits, &self.initialisation_ranges);
-        self.movement_registry.prepare(self.grid.width, self.grid.height);
-        // Pre-allocate next grid
-        self.next_grid = Grid {
-            width: self.grid.width,
-            height: self.grid.height,
-            num_cells: self.grid.num_cells,
-            num_traits: self.grid.num_traits,
-            data: self.grid.data.clone(),
-            is_empty: self.grid.is_empty.clone(),
-        };
-        self.rows_per_batch = std::cmp::max(1, 4000 / self.grid.width);
-        self.timestep = 0;
+        // Without a configured seed every reset gives a new grid
+        if !self.fixed_seed {
+            self.simulation.set_seed(random_seed());
+        }
+        self.simulation.set_grid_density(self.grid_density);
+        self.simulation.resize(self.grid_width, self.grid_height);
         self.time_accumulator = 0.0;
     }
     
     fn randomize_grid(&mut self) {
-        self.grid.randomize();
-        self.timestep = 0;
+        self.simulation.randomize();
+    }
+
+    fn save_checkpoint(&self) {
+        match self.simulation.save_checkpoint(&self.checkpoint_path) {
+            Ok(()) => println!("Checkpoint saved at timestep {}: {}", self.simulation.timestep(), self.checkpoint_path),
+            Err(e) => eprintln!("Checkpoint error: {}", e),
+        }
+    }
+
+    fn load_checkpoint(&mut self) {
+        let simulation = match Simulation::load_checkpoint(&self.checkpoint_path) {
+            Ok(simulation) => simulation,
+            Err(e) => {
+                eprintln!("Checkpoint error: {}", e);
+                return;
+            }
+        };
+        self.grid_width = simulation.grid().width;
+        self.grid_height = simulation.grid().height;
+        self.grid_density = simulation.grid_density();
+        self.num_traits = simulation.num_traits();
+        // the configured metadata only describes checkpoints with the same number of traits,
+        // the others get the names saved with their rules
+        if self.traits.len() != self.num_traits {
+            self.traits = simulation.rules_registry().get_trait_names()
+                .iter()
+                .take(self.num_traits)
+                .map(TraitInfo::new)
+                .collect();
+        }
+        self.active_mask = (0..self.num_traits)
+            .map(|i| simulation.active_traits().contains(&i) as u8)
+            .collect();
+        self.selected_trait = self.selected_trait.min(self.num_traits - 1);
+        self.fixed_seed = true;
+        self.simulation = simulation;
+        self.time_accumulator = 0.0;
+        println!("Checkpoint loaded at timestep {}: {}", self.simulation.timestep(), self.checkpoint_path);
+    }
+
+    /// Range of the trait mapped onto the color scheme,
+    /// unbounded traits without a display range are scaled to their current extent
+    fn display_bounds(&self, trait_idx: usize) -> (f32, f32) {
+        let grid = self.simulation.grid();
+        self.traits[trait_idx].display_bounds().unwrap_or_else(|| {
+            grid.get_trait_slice(trait_idx)
+                .par_iter()
+                .zip(grid.is_empty.par_iter())
+                .filter(|&(_, &empty)| !empty)
+                .map(|(&v, _)| (v, v))
+                .reduce(|| (f32::INFINITY, f32::NEG_INFINITY), |a, b| (a.0.min(b.0), a.1.max(b.1)))
+        })
     }
 
     fn update_grayscale_buffer(&mut self) {
@@ -290,40 +249,60 @@ impl CAApp {
             }
         }
         
-        let len = self.grid.width * self.grid.height;
+        let grid = self.simulation.grid();
+        let layout = TextureLayout::of(grid);
+        let len = layout.width * layout.height;
         let resize_flag = self.grayscale_buffer.len() != len;
         if  resize_flag {
             self.grayscale_buffer.resize(len, 0);
         }
         
+        let selected_trait = self.selected_trait;
+        let display_bounds = self.display_bounds(selected_trait);
+        let base_color_not_empty = self.base_color_not_empty;
         self.grayscale_buffer
-            .par_chunks_mut(self.grid.width)
+            .par_chunks_mut(layout.width)
             .enumerate()
             .for_each(|(row, pixels)| {
-                let start = row * self.grid.width;
-                for (col, pixel) in pixels.iter_mut().enumerate() {
+                let start = row * grid.width;
+                let shade = |col: usize| {
                     let idx = start + col;
-                    let is_not_empty = (!self.grid.is_empty[idx]) as u8;
-                    let trait_val = self.grid.get_cell_trait(row, col, self.selected_trait);
-                    let offset_val = ((self.base_color_not_empty + trait_val*(1.0-self.base_color_not_empty)) * 255.0) as u8;
-
-                    *pixel = offset_val * is_not_empty;
+                    let is_not_empty = (!grid.is_empty[idx]) as u8;
+                    // position in the trait display range
+                    let trait_val = traits::normalize(grid.get_cell_trait(row, col, selected_trait), display_bounds);
+                    let offset_val = ((base_color_not_empty + trait_val*(1.0-base_color_not_empty)) * 255.0) as u8;
+
+                    offset_val * is_not_empty
+                };
+                if layout.texels_per_cell == 1 {
+                    for (col, pixel) in pixels.iter_mut().enumerate() {
+                        *pixel = shade(col);
+                    }
+                } else {
+                    // Staggered hexagonal rows, the texels outside of the parallelogram stay blank
+                    pixels.fill(0);
+                    for col in 0..grid.width {
+                        let value = shade(col);
+                        let texel = layout.texel(row, col);
+                        for i in texel..texel + layout.texels_per_cell {
+                            pixels[i % layout.width] = value;
+                        }
+                    }
                 }
             });
         
         // Upload to GPU
-        if let Ok(mut guard) = self.gpu_renderer.lock() {
-            if let Some(ref mut r) = *guard {
-                r.update_texture(
-                    self.grid.width,
-                    self.grid.height,
-                    &self.grayscale_buffer,
-                    resize_flag,
-                );
-            }
+        if let Ok(mut guard) = self.gpu_renderer.lock()
+            && let Some(ref mut r) = *guard {
+            r.update_texture(
+                layout.width,
+                layout.height,
+                &self.grayscale_buffer,
+                resize_flag,
+            );
         }
 
-        self.last_rendered_timestep = self.timestep;
+        self.last_rendered_timestep = self.simulation.timestep();
     }
 }
 
@@ -334,27 +313,28 @@ impl CAApp {
 impl eframe::App for CAApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         // Check if we've reached the maximum timestep
-        if self.timed_simulation && self.timestep >= self.timestep_max {
-            println!("Simulation completed at timestep {}. Closing app.", self.timestep);
+        if self.timed_simulation && self.simulation.timestep() >= self.timestep_max {
+            println!("Simulation completed at timestep {}. Closing app.", self.simulation.timestep());
             print_separator();
             self.timed_simulation = false; // to avoid printing multiple times
 
             println!("Configuration:");
-            println!("  Grid: {}x{}", self.grid.width, self.grid.height);
-            println!("  Timesteps: {}", self.timestep);
+            println!("  Grid: {}x{}", self.simulation.grid().width, self.simulation.grid().height);
+            println!("  Timesteps: {}", self.simulation.timestep());
+            println!("  Seed: {}", self.simulation.seed());
             
             // Print active traits for info
-            print_active_traits(self.num_traits, &self.active_mask, &self.trait_names, &self.rules_registry);
+            print_active_traits(&self.traits, &self.active_mask, self.simulation.rules_registry());
             
             let elapsed = self.start.elapsed();
             println!("\nExecution time: {:?}", elapsed);
             println!(
                 "Performance: {:.2} timesteps/sec",
-                self.timestep as f64 / elapsed.as_secs_f64()
+                self.simulation.timestep() as f64 / elapsed.as_secs_f64()
             );
             println!(
                 "Cells/sec: {:.2}M",
-                (self.grid.width * self.grid.height * self.timestep) as f64 / elapsed.as_secs_f64() / 1_000_000.0
+                (self.simulation.grid().width * self.simulation.grid().height * self.simulation.timestep()) as f64 / elapsed.as_secs_f64() / 1_000_000.0
             );
             ctx.send_viewport_cmd(egui::ViewportCommand::Close);
             return;
@@ -390,7 +370,7 @@ impl eframe::App for CAApp {
             let simulation_budget = (target_frame_time - render_time_estimate).max(0.001);
             
             let estimated_step_time = self.avg_step_time.unwrap_or(0.0001);
-            let max_steps = if self.timestep == 0 {
+            let max_steps = if self.simulation.timestep() == 0 {
                 1
             } else {
                 ((simulation_budget / estimated_step_time) as usize).max(1)
@@ -441,24 +421,33 @@ impl eframe::App for CAApp {
                 }
             });
             
-            if ui.button("🎲 Randomize").clicked() {
-                self.randomize_grid();
-                flag_update_texture=true;
-            }
+            ui.horizontal(|ui| {
+                if ui.button("🎲 Randomize").clicked() {
+                    self.randomize_grid();
+                    flag_update_texture=true;
+                }
+                if ui.button("💾 Save").clicked() {
+                    self.save_checkpoint();
+                }
+                if ui.button("📂 Load").clicked() {
+                    self.load_checkpoint();
+                    flag_update_texture=true;
+                }
+            });
             
             ui.add(egui::Slider::new(&mut self.steps_per_second, self.steps_per_second_min..=self.steps_per_second_max)
                 .text("Steps/sec"));
             
-            ui.label(format!("Timestep: {}", self.timestep));
+            ui.label(format!("Timestep: {}", self.simulation.timestep()));
             
             ui.separator();
             
             // Grid size
             ui.label("Grid Configuration");
             let mut changed = false;
-            changed |= ui.add(egui::Slider::new(&mut self.grid.width, self.grid_width_min..=self.grid_width_max)
+            changed |= ui.add(egui::Slider::new(&mut self.grid_width, self.grid_width_min..=self.grid_width_max)
                 .text("Width")).changed();
-            changed |= ui.add(egui::Slider::new(&mut self.grid.height, self.grid_height_min..=self.grid_height_max)
+            changed |= ui.add(egui::Slider::new(&mut self.grid_height, self.grid_height_min..=self.grid_height_max)
                 .text("Height")).changed();
             changed |= ui.add(egui::Slider::new(&mut self.grid_density, 0.01..=1.0)
                 .text("Density")).changed();
@@ -467,18 +456,79 @@ impl eframe::App for CAApp {
                 self.reset_grid();
             }
             
+            // Boundaries
+            let (mut row_boundary, mut col_boundary) = self.simulation.boundaries();
+            let mut boundary_changed = false;
+            for (label, boundary) in [("Rows boundary", &mut row_boundary), ("Cols boundary", &mut col_boundary)] {
+                egui::ComboBox::from_label(label)
+                    .selected_text(boundary.name())
+                    .show_ui(ui, |ui| {
+                        for &candidate in Boundary::ALL {
+                            boundary_changed |= ui.selectable_value(boundary, candidate, candidate.name()).changed();
+                        }
+                    });
+            }
+            if boundary_changed {
+                self.simulation.set_boundaries(row_boundary, col_boundary);
+                // A hexagonal lattice wraps its staggered rows only when the columns are periodic
+                flag_update_texture = true;
+            }
+
+            // Lattice, the Moore and hexagonal neighborhoods following it, the other masks being read in its coordinates
+            let mut lattice = self.simulation.lattice();
+            egui::ComboBox::from_label("Lattice")
+                .selected_text(lattice.name())
+                .show_ui(ui, |ui| {
+                    for &candidate in Lattice::ALL {
+                        if ui.selectable_value(&mut lattice, candidate, candidate.name()).changed() {
+                            self.simulation.set_lattice(lattice);
+                            if let Err(e) = self.simulation.check_lattice() {
+                                eprintln!("Warning: {}", e);
+                            }
+                            flag_update_texture = true;
+                        }
+                    }
+                });
+
+            // Update order
+            let mut update_mode = self.simulation.update_mode();
+            egui::ComboBox::from_label("Update mode")
+                .selected_text(update_mode.name())
+                .show_ui(ui, |ui| {
+                    let probability = update_mode.probability().unwrap_or(UpdateMode::DEFAULT_PROBABILITY);
+                    for &name in UpdateMode::NAMES {
+                        if ui.selectable_label(update_mode.name() == name, name).clicked() {
+                            update_mode = UpdateMode::from_name(name, probability).expect("listed update mode");
+                        }
+                    }
+                });
+            if let UpdateMode::RandomIndependent(probability) = &mut update_mode {
+                ui.add(egui::Slider::new(probability, 0.0..=1.0).text("Update probability"));
+            }
+            if update_mode != self.simulation.update_mode() {
+                self.simulation.set_update_mode(update_mode);
+            }
+            
             ui.separator();
             
             // Movement type
             ui.label("Movement Type");
             egui::ComboBox::from_id_salt("movement")
-                .selected_text(self.movement_registry.get_movement_name())
+                .selected_text(self.simulation.movement_registry().get_movement_name())
                 .show_ui(ui, |ui| {
-                    for &name in Movement::NAMES {
-                        if let Some(movement) = Movement::from_name(name) {
-                            let is_selected = self.movement_registry.is_stored_movement(movement);
-                            if ui.selectable_label(is_selected, name).clicked() {
-                                self.movement_registry.set_movement(movement);
+                    for &movement in Movement::ALL {
+                        // Configured inputs when they fit this movement, else input i reads trait i
+                        let params = self.movement_params
+                            .resolve(movement, &self.traits)
+                            .or_else(|_| MovementConfig::default().resolve(movement, &self.traits));
+                        let is_selected = self.simulation.movement_registry().is_stored_movement(movement);
+                        let response = ui.add_enabled(params.is_ok(), egui::SelectableLabel::new(is_selected, movement.name()));
+                        if let Ok(params) = params
+                            && response.clicked() {
+                            let registry = self.simulation.movement_registry_mut();
+                            registry.set_movement(movement);
+                            if let Err(e) = registry.set_params(params) {
+                                eprintln!("Movement error: {}", e);
                             }
                         }
                     }
@@ -488,26 +538,21 @@ impl eframe::App for CAApp {
 
             // Active Traits configuration
             ui.label("Active Traits");
-            egui::Grid::new("trait_grid").show(ui, |ui| {
-                for trait_idx in 0..self.num_traits as usize {
-                    let mut active = self.active_mask[trait_idx] == 1;
-                    if ui
-                        .checkbox(&mut active, &self.trait_names[trait_idx])
-                        .changed()
-                    {
-                        self.active_mask[trait_idx] = if active { 1 } else { 0 };
-                        self.active_traits = self.active_mask
-                            .iter()
-                            .take(self.num_traits as usize)
-                            .enumerate()
-                            .filter_map(|(i, &m)| if m != 0 { Some(i) } else { None })
-                            .collect();
-                    }
-                    
-                    if (trait_idx + 1) % 3 == 0 {
-                        ui.end_row();
+            egui::ScrollArea::vertical().id_salt("trait_grid").max_height(150.0).show(ui, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    for trait_idx in 0..self.num_traits {
+                        let mut active = self.active_mask[trait_idx] == 1;
+                        let info = &self.traits[trait_idx];
+                        if ui
+                            .checkbox(&mut active, &info.name)
+                            .on_hover_text(&info.description)
+                            .changed()
+                        {
+                            self.active_mask[trait_idx] = if active { 1 } else { 0 };
+                            self.simulation.set_active_mask(&self.active_mask);
+                        }
                     }
-                }
+                });
             });
 
             ui.separator();
@@ -522,27 +567,179 @@ impl eframe::App for CAApp {
                     }
 
                     ui.horizontal(|ui| {
-                        ui.label(&self.trait_names[trait_idx]);
+                        ui.label(&self.traits[trait_idx].name);
 
                         egui::ComboBox::from_id_salt(format!("rule_{}", trait_idx))
-                            .selected_text(self.rules_registry.get_rule_name(trait_idx))
+                            .selected_text(self.simulation.rules_registry().get_rule_name(trait_idx))
                             .show_ui(ui, |ui| {
-                                for &name in Rule::NAMES {
-                                    if let Some(rule) = Rule::from_name(name) {
+                                for name in rules::rule_names() {
+                                    if let Some(rule) = rules::create_rule(name) {
                                         let is_selected = self
-                                            .rules_registry
-                                            .is_stored_function(trait_idx, rule);
+                                            .simulation
+                                            .rules_registry()
+                                            .is_stored_function(trait_idx, name);
 
                                         if ui
                                             .selectable_label(is_selected, name)
                                             .clicked()
+                                            && let Err(e) = self.simulation.rules_registry_mut().set_rule(trait_idx, rule)
                                         {
-                                            self.rules_registry.set_rule(trait_idx, rule);
+                                            eprintln!("{}", e);
                                         }
                                     }
                                 }
                             });
                     });
+
+                    // Rulestring (or expression), applied when the field loses focus
+                    let rule = self.simulation.rules_registry().get_rule(trait_idx);
+                    let label = if rule.name() == "expression" { "    expression" } else { "    rulestring" };
+                    if let Some(rulestring) = rule.rulestring() {
+                        self.rulestring_edits.resize(self.num_traits, String::new());
+                        ui.horizontal(|ui| {
+                            ui.label(label);
+                            let response = ui.text_edit_singleline(&mut self.rulestring_edits[trait_idx]);
+                            if response.lost_focus() {
+                                if let Err(e) = self.simulation
+                                    .rules_registry_mut()
+                                    .set_rule_rulestring(trait_idx, &self.rulestring_edits[trait_idx])
+                                {
+                                    eprintln!("{}", e);
+                                }
+                            } else if !response.has_focus() {
+                                self.rulestring_edits[trait_idx] = rulestring;
+                            }
+                        });
+                    }
+
+                    // Parameters of the rule
+                    for (name, mut value) in self.simulation.rules_registry().get_rule(trait_idx).params() {
+                        ui.horizontal(|ui| {
+                            ui.label(format!("    {}", name));
+                            if ui.add(egui::DragValue::new(&mut value).speed(0.001)).changed() {
+                                self.simulation.rules_registry_mut().set_rule_param(trait_idx, name, value);
+                            }
+                        });
+                    }
+
+                    // Traits read by the inputs of coupled rules
+                    let rule = self.simulation.rules_registry().get_rule(trait_idx);
+                    let inputs: Vec<(&str, usize)> = rule.input_names()
+                        .iter()
+                        .filter_map(|&role| rule.get_input(role).map(|input| (role, input)))
+                        .collect();
+                    for (role, input) in inputs {
+                        ui.horizontal(|ui| {
+                            ui.label(format!("    {}", role));
+                            egui::ComboBox::from_id_salt(format!("rule_{}_input_{}", trait_idx, role))
+                                .selected_text(self.traits.get(input).map_or("", |info| info.name.as_str()))
+                                .show_ui(ui, |ui| {
+                                    for (other_idx, info) in self.traits.iter().enumerate() {
+                                        if ui.selectable_label(other_idx == input, &info.name).clicked()
+                                            && let Err(e) = self.simulation
+                                                .rules_registry_mut()
+                                                .set_rule_input(trait_idx, role, &info.name)
+                                        {
+                                            eprintln!("{}", e);
+                                        }
+                                    }
+                                });
+                        });
+                    }
+
+                    // Neighborhood read by the rule, the global one unless the trait has its own
+                    let own = self.simulation.rules_registry().get_neighborhood(trait_idx).cloned();
+                    let mut neighborhood_edit = None;
+                    ui.horizontal(|ui| {
+                        ui.label("    neighborhood");
+                        let mut has_own = own.is_some();
+                        if ui.checkbox(&mut has_own, "own").changed() {
+                            neighborhood_edit = Some(has_own.then(|| self.simulation.neighborhood_traits().clone()));
+                        }
+                        match &own {
+                            Some(neighborhood) => {
+                                // Preset of the mask, kept when the radius changes (a custom mask becomes a square)
+                                let shape = Shape::of(neighborhood);
+                                let mut shape_name = shape.name();
+                                let mut radius = neighborhood.width.max(neighborhood.height) / 2;
+                                egui::ComboBox::from_id_salt(format!("rule_{}_shape", trait_idx))
+                                    .selected_text(shape_name)
+                                    .show_ui(ui, |ui| {
+                                        for &name in Shape::NAMES.iter().filter(|&&name| Shape::preset(name, 1).is_some()) {
+                                            ui.selectable_value(&mut shape_name, name, name);
+                                        }
+                                    });
+                                let radius_changed = ui.add(egui::DragValue::new(&mut radius).range(1..=MAX_NEIGHBORHOOD_RADIUS).prefix("radius ")).changed();
+                                if radius_changed || shape_name != shape.name() {
+                                    let preset = Shape::preset(shape_name, radius).unwrap_or(Shape::Moore { radius });
+                                    if let Ok(reshaped) = Neighborhood::from_shape(&preset) {
+                                        // Custom weights only fit the previous mask
+                                        neighborhood_edit = Some(Some(reshaped.clone().with_kernel(neighborhood.kernel().clone()).unwrap_or(reshaped)));
+                                    }
+                                }
+                                // Weights of the neighborhood, with the parameters of the kernel
+                                let mut kernel = neighborhood.kernel().clone();
+                                egui::ComboBox::from_id_salt(format!("rule_{}_kernel", trait_idx))
+                                    .selected_text(kernel.name())
+                                    .show_ui(ui, |ui| {
+                                        for candidate in [
+                                            Kernel::Uniform,
+                                            Kernel::Gaussian { sigma: 1.0 },
+                                            Kernel::Ring { radius: radius as f32 / 2.0, width: 1.0 },
+                                            Kernel::InverseDistance,
+                                        ] {
+                                            if ui.selectable_label(kernel.name() == candidate.name(), candidate.name()).clicked() {
+                                                kernel = candidate;
+                                            }
+                                        }
+                                    });
+                                match &mut kernel {
+                                    Kernel::Gaussian { sigma } => {
+                                        ui.add(egui::DragValue::new(sigma).speed(0.05).range(0.05..=50.0).prefix("sigma "));
+                                    }
+                                    Kernel::Ring { radius, width } => {
+                                        ui.add(egui::DragValue::new(radius).speed(0.05).range(0.0..=50.0).prefix("ring radius "));
+                                        ui.add(egui::DragValue::new(width).speed(0.05).range(0.05..=50.0).prefix("width "));
+                                    }
+                                    _ => {}
+                                }
+                                if &kernel != neighborhood.kernel()
+                                    && let Ok(weighted) = neighborhood.clone().with_kernel(kernel)
+                                {
+                                    neighborhood_edit = Some(Some(weighted));
+                                }
+                            }
+                            None => {
+                                let global = self.simulation.neighborhood_traits();
+                                ui.label(format!("global {}x{}", global.width, global.height));
+                            }
+                        }
+                    });
+                    // Cells of the mask, toggled by a click (the center is marked)
+                    if let Some(mut neighborhood) = own {
+                        let mut toggled = false;
+                        egui::Grid::new(format!("rule_{}_neighborhood", trait_idx))
+                            .spacing([1.0, 1.0])
+                            .show(ui, |ui| {
+                                for mask_r in 0..neighborhood.height {
+                                    for mask_c in 0..neighborhood.width {
+                                        let is_center = (mask_r, mask_c) == (neighborhood.center_row, neighborhood.center_col);
+                                        let is_set = neighborhood.mask[mask_r][mask_c] != 0;
+                                        if ui.selectable_label(is_set, if is_center { "o" } else { "  " }).clicked() {
+                                            neighborhood.set_cell(mask_r, mask_c, (!is_set) as u8);
+                                            toggled = true;
+                                        }
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                        if toggled {
+                            neighborhood_edit = Some(Some(neighborhood));
+                        }
+                    }
+                    if let Some(neighborhood) = neighborhood_edit {
+                        self.simulation.rules_registry_mut().set_neighborhood(trait_idx, neighborhood);
+                    }
                 }
             });
 
@@ -568,7 +765,7 @@ impl eframe::App for CAApp {
                     ui.horizontal(|ui| {
                         ui.label("Trait:");
                         egui::ComboBox::from_id_salt("trait_select")
-                            .selected_text(&self.trait_names[self.selected_trait])
+                            .selected_text(&self.traits[self.selected_trait].name)
                             .show_ui(ui, |ui| {
                                 for trait_idx in 0..self.num_traits {
                                     if self.active_mask[trait_idx] == 0 {
@@ -577,7 +774,7 @@ impl eframe::App for CAApp {
                                     if ui.selectable_value(
                                         &mut self.selected_trait,
                                         trait_idx,
-                                        &self.trait_names[trait_idx],
+                                        &self.traits[trait_idx].name,
                                     ).changed() {
                                         flag_update_texture = true;
                                     }
@@ -618,7 +815,7 @@ impl eframe::App for CAApp {
                     // --- Trait Statistics ---
                     if self.show_stats {
                         ui.label("Statistics");
-                        let fill_percentage = self.grid.get_fill_percentage();
+                        let fill_percentage = self.simulation.grid().get_fill_percentage();
                         ui.label(format!("  density: {:.3}", fill_percentage));
                         ui.separator();
                         egui::ScrollArea::vertical()
@@ -629,14 +826,14 @@ impl eframe::App for CAApp {
                                     if self.active_mask[trait_idx] == 0 {
                                         continue;
                                     }
-                                    let values = self.grid.get_trait_slice(trait_idx);
+                                    let values = self.simulation.grid().get_trait_slice(trait_idx);
                                     let total_count = values.len();
                                     
                                     // Normal average (all values)
                                     let avg = values.iter().sum::<f32>() / total_count as f32;
                                     
                                     // Filter non-zero values
-                                    let non_zero_values: Vec<f32> = values.iter().cloned().filter(|&v| v > 0.001).collect();
+                                    let non_zero_values: Vec<f32> = values.iter().cloned().filter(|&v| v.abs() > 0.001).collect();
                                     let non_zero_count = non_zero_values.len();
                                     
                                     // Density: proportion of non-zero cells
@@ -653,12 +850,13 @@ impl eframe::App for CAApp {
                                     let min = non_zero_values.iter().cloned().fold(f32::INFINITY, f32::min);
                                     let max = non_zero_values.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
                                     
-                                    ui.label(format!("{}:", self.trait_names[trait_idx]));
+                                    ui.label(format!("{}:", self.traits[trait_idx].label()))
+                                        .on_hover_text(&self.traits[trait_idx].description);
                                     ui.label(format!("  density: {:.3} ({}/{})", trait_density, non_zero_count, total_count));
                                     if non_zero_count > 0 {
                                         ui.label(format!("  (non-zero) min: {:.3}, max: {:.3}", min, max));
                                     } else {
-                                        ui.label(format!("  (non-zero) min: , max: "));
+                                        ui.label("  (non-zero) min: , max: ");
                                     }
                                     ui.label(format!("  avg: {:.3}, avg (non-zero): {:.3}", avg, avg_non_zero));
                                     ui.separator();
@@ -671,11 +869,11 @@ impl eframe::App for CAApp {
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading(format!(
                 "Grid - {}",
-                self.trait_names[self.selected_trait]
+                self.traits[self.selected_trait].label()
             ));
 
             // Update texture only when needed
-            if self.last_rendered_timestep != self.timestep || flag_update_texture {
+            if self.last_rendered_timestep != self.simulation.timestep() || flag_update_texture {
                 self.update_grayscale_buffer();
             }
 
@@ -683,10 +881,7 @@ impl eframe::App for CAApp {
             let scroll_id = ui.make_persistent_id("grid_scroll");
 
             // Calculate content size (logical size of the entire grid)
-            let content_size = egui::vec2(
-                self.grid.width as f32 * self.cell_size,
-                self.grid.height as f32 * self.cell_size,
-            );
+            let content_size = TextureLayout::of(self.simulation.grid()).content_size(self.cell_size);
 
             // ScrollArea configuration
             let mut scroll_area = egui::ScrollArea::both()
@@ -720,11 +915,10 @@ impl eframe::App for CAApp {
                 rect: viewport_rect,
                 callback: Arc::new(egui_glow::CallbackFn::new(
                     move |_info, _painter| {
-                        if let Ok(guard) = renderer.lock() {
-                            if let Some(ref r) = *guard {
-                                r.paint(scheme, viewport_rect, screen_arr, 
-                                    current_scroll, content_size);
-                            }
+                        if let Ok(guard) = renderer.lock()
+                            && let Some(ref r) = *guard {
+                            r.paint(scheme, viewport_rect, screen_arr, 
+                                current_scroll, content_size);
                         }
                     }
                 )),
@@ -739,7 +933,8 @@ impl eframe::App for CAApp {
             // Draw values on top if zoomed in enough
             if self.show_values && self.cell_size >= self.show_values_minimum_cell_size {
                 let painter = ui.painter();
-                let values = self.grid.get_trait_slice(self.selected_trait);
+                let grid = self.simulation.grid();
+                let values = grid.get_trait_slice(self.selected_trait);
 
                 // Calculate visible cell range based on scroll offset
                 let scroll_offset = scroll_output.state.offset;
@@ -748,18 +943,25 @@ impl eframe::App for CAApp {
                 let start_row = (scroll_offset.y / self.cell_size).floor() as usize;
                 let visible_cols = (viewport_rect.width() / self.cell_size).ceil() as usize + 1;
                 let visible_rows = (viewport_rect.height() / self.cell_size).ceil() as usize + 1;
-                let end_col = (start_col + visible_cols).min(self.grid.width);
-                let end_row = (start_row + visible_rows).min(self.grid.height);
+                let end_col = (start_col + visible_cols).min(grid.width);
+                let end_row = (start_row + visible_rows).min(grid.height);
 
                 // Capture needed values to avoid borrowing issues
-                let grid_width = self.grid.width;
+                let display_bounds = self.display_bounds(self.selected_trait);
+                let grid_width = grid.width;
                 let cell_size = self.cell_size;
+                let layout = TextureLayout::of(grid);
+                // The staggered hexagonal rows show other columns from one row to the next
+                let (start_col, end_col) = if layout.texels_per_cell == 1 { (start_col, end_col) } else { (0, grid.width) };
+                let min_x = scroll_offset.x / cell_size - 1.0;
+                let max_x = (scroll_offset.x + viewport_rect.width()) / cell_size + 1.0;
 
                 // Create index pairs for only visible cells
                 let visible_cells: Vec<_> = (start_row..end_row)
                     .flat_map(|row| {
                         (start_col..end_col).map(move |col| (row, col))
                     })
+                    .filter(|&(row, col)| (min_x..=max_x).contains(&layout.cell_x(row, col)))
                     .collect();
 
                 // Process visible cells in parallel
@@ -770,18 +972,18 @@ impl eframe::App for CAApp {
                         let value = values[idx];
 
                         // Skip empty cells
-                        if self.grid.is_cell_empty(row, col) {
+                        if grid.is_cell_empty(row, col) {
                             return None;
                         }
 
                         // Calculate cell center - using image_response.rect instead of viewport_rect
                         // because that's where the actual grid is drawn
-                        let cell_x = image_response.rect.min.x + (col as f32 + 0.5) * cell_size;
+                        let cell_x = image_response.rect.min.x + layout.cell_x(row, col) * cell_size;
                         let cell_y = image_response.rect.min.y + (row as f32 + 0.5) * cell_size;
                         let pos = egui::pos2(cell_x, cell_y);
 
                         let cell_color = self.color_scheme.map_value(
-                            value,
+                            traits::normalize(value, display_bounds),
                             false,
                             self.base_color_not_empty,
                         );
@@ -865,7 +1067,7 @@ impl eframe::App for CAApp {
                     if let Some(pos) = ctx.input(|i| i.pointer.hover_pos()) {
                         let rel = pos - viewport_rect.min;
 
-                        let state = scroll_output.state.clone();
+                        let state = scroll_output.state;
 
                         let old_world_pos = state.offset + rel;
                         let zoom_ratio = self.cell_size / old_cell;
@@ -873,10 +1075,7 @@ impl eframe::App for CAApp {
 
                         let mut new_offset = new_world_pos - rel;
 
-                        let content_size = egui::vec2(
-                            self.grid.width as f32 * self.cell_size,
-                            self.grid.height as f32 * self.cell_size,
-                        );
+                        let content_size = TextureLayout::of(self.simulation.grid()).content_size(self.cell_size);
                         let visible_size = viewport_rect.size();
 
                         new_offset.x = new_offset.x.clamp(